@@ -2,32 +2,241 @@ extern crate proc_macro;
 #[macro_use]
 extern crate syn;
 
-use proc_macro2::{Span, TokenStream};
+use proc_macro2::{Ident, Span, TokenStream, TokenTree};
 use quote::{quote, quote_spanned, ToTokens};
+use std::{cell::RefCell, rc::Rc};
 use syn::{
-    punctuated::Punctuated, spanned::Spanned, AttributeArgs, Expr, ExprAssign, ExprClosure, FnArg,
-    ImplItem, ImplItemMethod, Index, Item, ItemFn, ItemImpl, Lit, Local, Meta, MetaNameValue,
-    NestedMeta, Pat, PatIdent, PatType, Stmt
+    parse::{Parse, ParseStream, Parser},
+    punctuated::Punctuated,
+    spanned::Spanned,
+    visit::Visit,
+    visit_mut::{self, VisitMut},
+    Attribute, AttributeArgs, Block, Data, DataStruct, DeriveInput, Expr, ExprAssign, ExprAsync,
+    ExprClosure, ExprReturn, ExprStruct, ExprTry, Field, Fields, FnArg, GenericArgument, ImplItem,
+    ImplItemMethod, Index, Item, ItemFn, ItemImpl, ItemMod, ItemStruct, ItemTrait, Lit, LitFloat,
+    LitStr, Local, Member, Meta, MetaList, MetaNameValue, NestedMeta, Pat, PatIdent, PatType,
+    PathArguments, PathSegment, Receiver, ReturnType, Signature, Stmt, TraitItem, TraitItemConst,
+    TraitItemMethod, TraitItemType, Type, TypeImplTrait, TypeParamBound, TypePath, TypeReference,
+    Visibility
 };
 
 #[inline]
 fn unsupported() -> TokenStream {
     quote! {
-        compile_error!("Unsupported attribute target. 'track_with' only supports functions, impl blocks and closures.");
+        compile_error!("Unsupported attribute target. 'track_with' only supports functions, impl blocks, modules and closures.");
     }
 }
 
 /// Track the target with the tracker specified in the arguments.
 /// Requires one argument containing the path to the tracker.
+/// Calls that leave early through `return` or `?` are logged too, with the value they returned.
+/// Other attributes on the target and its methods, like `#[inline]` or `#[must_use]`, are kept in their original order,
+/// whether they're above or below `track_with`.
+/// Qualifiers like `unsafe` and `extern "C"` are kept as they are, so tracked FFI callbacks keep their ABI, and the body
+/// of an `unsafe fn` stays in an unsafe context. Raw pointers can't be captured since they aren't `Send`, so FFI shims
+/// need to skip them or capture them as an address, e.g. `#[track(capture_with = "ptr as usize")] ptr: *const u8`.
 ///
 /// # Arguments
 ///
 /// * `tracked_path` - The path to the tracker. This must be the first unnamed argument. Required.
-/// * `exclude` - A comma separated list of methods to exclude. This only does something on impl blocks.
+///   Nested fields are written like field accesses, e.g. `deps.tracker`, `deps.0.tracker` or `0.1`.
+///   Can also be a method without arguments returning the tracker, like `tracker()` for structs deriving `Tracked`,
+///   or any expression given as `expr = "..."`, e.g. `expr = "self.deps().tracker()"`. Expressions that mention
+///   `self` are used as they are, otherwise they're accessed on the receiver like paths if `include_receiver` is true.
+///   The tracker can also be given by name anywhere in the arguments, as any expression, e.g. `tracker = self.0` or
+///   `tracker = TRACKER`. It's used like the first argument would be, so giving both is an error.
+/// * `derived` - Track an impl block of a struct with [`inject_tracker`](macro@inject_tracker) through its injected
+///   tracker instead of giving one, e.g. `#[track_with(derived = true)]`. The tracker is used like an `optional`
+///   tracker, so calls are only logged once `set_tracker` was called. Struct expressions like `Self { ... }` in the
+///   impl block get the injected field filled in, so constructors don't need to know about it. A bare `derived` is
+///   rejected as ambiguous, so a tracker named `derived` has to be given as `tracker = derived`.
+/// * `exclude` - The methods to exclude, either as a list like `exclude(new, other)` or as a comma separated string
+///   like `exclude = "new, other"`. This only does something on impl blocks and modules.
+/// * `include` - The only methods to track, in the same formats as `exclude`. Every other method is left untouched.
+///   This only does something on impl blocks and modules and can't be combined with `exclude`.
+///   Names in the list form are checked, so naming a method that doesn't exist is a compile error.
 /// * `include_receiver` - Include the receiver (self). If false, the tracker must be available in the scope of the relevant method.
-///     If no receiver was found and this is true, the method will be skipped. Defaults to true.
+///   If no receiver was found and this is true, the method will be skipped. Defaults to true.
+///   Associated functions without a receiver are tracked with `static_tracker` if it's set. Otherwise, the ones taking
+///   a tracker as an `Arc<Tracker>`, `&Tracker` or `&Arc<Tracker>` parameter, like constructors, log to that tracker.
+///   The parameter isn't captured, and neither is a return type mentioning `Self`, since it usually can't be cloned.
+///   For methods taking `self` by value or with a typed receiver like `self: Arc<Self>`, `self: Box<Self>` or
+///   `self: Pin<&mut Self>`, the tracker is cloned before the body runs, since the body may move `self`.
+/// * `optional` - The tracker is an `Option<Arc<Tracker>>`, e.g. a field that's only set in tests. Calls are only
+///   logged if it's `Some`, and nothing is captured otherwise, so there's no overhead. Not supported on closures.
+///   Defaults to false.
+/// * `weak` - The tracker is a `Weak<Tracker>`, e.g. from `Arc::downgrade(&tracker)`, so tracked objects don't keep it
+///   alive. It's upgraded at the start of each call, and if the tracker was dropped, the call isn't logged and nothing
+///   is captured. Can be combined with `optional` for `Option<Weak<Tracker>>`. Not supported on closures.
+///   Defaults to false.
+/// * `log_before` - Log each call before the body runs instead of after it returns, so calls that block forever or
+///   deadlock still show up. The return value is filled in once the body returns, so each call is still counted once.
+///   `and_returned` only looks at the calls that have returned, but `nth_returned` fails if the call it picks hasn't
+///   returned yet. Not supported on closures. Defaults to false.
+/// * `static_tracker` - The path to a tracker for the associated functions of an impl block tracked with
+///   `include_receiver`, like constructors, e.g. `static_tracker = "crate::TRACKER"`. Methods with a receiver still
+///   log through the receiver. Without it, associated functions aren't tracked.
 /// * `namespace` - Override the namespace of the tracked item. Tracked key will be namespace::function_name.
-///     Defaults to the struct name for impl blocks, None for functions and closures.
+///   Defaults to the struct name for impl blocks, the module name for modules and None for functions and closures.
+///   Type names are written without spaces around punctuation, so the methods of `impl Cache<String>` are logged as
+///   `Cache<String>::method` and `impl crate::cache::Cache<T>` as `crate::cache::Cache<T>::method`.
+/// * `namespace_trait` - Qualify the namespace of trait impls with the trait, e.g. `<Foo as MyTrait>::method`.
+///   This keeps methods with the same name from different traits apart. Has no effect if `namespace` is set.
+///   Defaults to false.
+/// * `namespace_module` - Prefix the default namespace with the `module_path!()` of the tracked item, so types and
+///   functions with the same name in different modules are logged under different keys, e.g. `my_crate::http::Client::get`
+///   instead of `Client::get`, or `my_crate::util::parse` for a function. The functions of a tracked module are logged
+///   under their full module path. Has no effect if `namespace` is set or if a method is renamed to a whole key.
+///   Defaults to false.
+/// * `namespace_field` - A field of the receiver whose value is added to the namespace of every call with `Display`,
+///   e.g. `namespace_field = "id"` logs the calls of a `Worker` with an `id` of 3 as `Worker#3::run`, so each instance
+///   is tracked separately. The key is formatted before the body runs. Only does something on methods with a receiver
+///   in impl blocks, and can't be combined with `keys` or `typed_assertions`, since the keys aren't known until runtime.
+/// * `name` - Replace the function name in the key of a tracked function. If it contains `::`, it is used as the whole
+///   key.
+/// * `key_format` - How the key is built from the namespace and the function name, with the placeholders
+///   `{namespace}` and `{name}`, e.g. `key_format = "{namespace}.{name}"` logs `Service::get` as `Service.get`.
+///   `{name}` is required, and other placeholders or braces are a compile error. Keys without a namespace are just the
+///   name, and the module path added by `namespace_module` is still joined with `::`. The constants generated by `keys`
+///   use the same format, so they always match the logged keys. Defaults to `"{namespace}::{name}"`.
+/// * `capture_args` - Log the arguments. Set this to false for arguments that can't or shouldn't be cloned, like
+///   large buffers or handles. Calls are still counted. Defaults to true.
+/// * `capture_return` - Log the return value. Set this to false for return types that can't be captured, like
+///   results with errors that can't be cloned, like `std::io::Result`. The arguments are still logged.
+///   Return types containing `impl Trait`, like `impl Iterator<Item = u32>`, are never captured, so their calls are
+///   logged with a `returned` of `None`. Defaults to true.
+/// * `capture_self` - Log a clone of the receiver, taken before the body runs, as the `receiver` of each call.
+///   Requires `Self: Clone`. Only does something on methods with a receiver. Defaults to false.
+/// * `capture` - How arguments and return values are captured. One of `"to_owned"`, `"clone"` or `"debug"`, see
+///   [Captures](#captures). Defaults to `"to_owned"`.
+/// * `capture_fields` - Fields of the receiver to capture after the arguments, as a comma separated string like
+///   `capture_fields = "state, counter"`. The values are captured like the arguments before the body runs, so the fields
+///   must be `Clone`, and they're appended to the argument tuple, e.g. `fn set(&self, value: u32)` is logged as
+///   `(value, state, counter)`. Only does something on methods with a receiver, which get an untyped key from `keys`.
+/// * `capture_generics` - Capture arguments and return values whose type mentions a type parameter of the function,
+///   like `value: T` in `fn insert<T: Serialize>(&self, value: T)`. These need to be `Clone + Send + Sync + 'static`
+///   (or `Debug` with `capture = "debug"`), which generic functions often don't require. Set this to false to leave
+///   them out of the capture like `#[track_skip]` parameters. Defaults to true.
+/// * `key_generics` - Log the calls of generic functions under a key with the type arguments of each instantiation,
+///   formatted with `std::any::type_name` at runtime, e.g. `Store::insert::<my_crate::User>`. Functions without type
+///   parameters keep their key. Can't be combined with `keys` or `typed_assertions`. Defaults to false.
+/// * `keys` - Generate a unit struct with this name next to a tracked impl block, with a typed
+///   `racetrack::key::CallKey` constant for every tracked method, e.g. `keys = "TrackedKeys"`
+///   lets you write `tracker.assert_that(TrackedKeys::tracked_method)`. With `keys = true`, the struct is named after
+///   the type with a `Keys` suffix, e.g. `TrackedStructKeys`. Methods with generic or unnameable argument or return
+///   types get the key as a plain `&'static str` instead, so typos in keys are always caught by the compiler.
+///   Not supported on generic impl blocks.
+/// * `typed_assertions` - Generate a struct with this name next to a tracked impl block, with a method starting a typed
+///   assertion for every tracked method, e.g. with `typed_assertions = true`, the assertions on `TrackedStruct` are
+///   started with `TrackedStructAssertions::new(&tracker).tracked_method()`. Like assertions started with a key from
+///   `keys`, `with` and `and_returned` only accept the captured types, so a wrong argument tuple is a compile error
+///   instead of a panic. Can also be given a name like `keys`. Not supported on generic impl blocks.
+/// * `vis` - The visibility of the structs generated by `keys` and `typed_assertions` and of their members, e.g.
+///   `vis = "pub"` to use them from other crates, like the tests of a workspace. Defaults to `pub(crate)`.
+/// * `cfg` - Only track when the given `cfg` predicate holds, e.g. `cfg = "test"` or `cfg = "feature = \"tracking\""`.
+///   Otherwise the item is emitted unchanged, so it doesn't need a tracker and has no overhead: nothing is captured,
+///   cloned or logged. The tracker field can be gated with the same predicate, e.g.
+///   `#[cfg(test)] tracker: Option<Arc<Tracker>>` together with `optional = true, cfg = "test"`.
+///   Note that `cfg(test)` only holds for the crate that's being tested, not for its dependencies or for the library
+///   when running integration tests.
+/// * `debug_expand` - Print the generated code and the key of every tracked function to stderr while compiling, to
+///   see what the macro did without expanding the whole crate. The emitted code doesn't change. The code is printed
+///   as tokens, not formatted like `rustfmt` would. Remove the option once you're done, since the output is printed on
+///   every build of the crate. For example, `#[track_with(0, debug_expand = true)]` on
+///   `impl Tracked { fn tracked(&self) {} }` prints
+///
+///   ```text
+///   ===== racetrack: track_with expansion =====
+///   Keys:
+///       tracked => "Tracked::tracked"
+///   Generated code:
+///   impl Tracked
+///   {
+///       fn tracked(& self)
+///       {
+///           ...
+///       }
+///   }
+///   ===== racetrack: end of track_with expansion =====
+///   ```
+///
+///   Defaults to false.
+///
+/// # Captures
+///
+/// The arguments are logged as a tuple of their `to_owned` values in declaration order, skipping the receiver.
+/// Functions without parameters besides the receiver log `()`, which is asserted with `with_no_args`.
+/// Borrowed parameters are logged as their owned counterparts, so `&str` is logged as a `String` and `&[T]` as a
+/// `Vec<T>`, e.g. `fn write(&self, data: &[u8])` is asserted with `.with(vec![1u8, 2, 3])`. The owned values must be
+/// `'static`, so slices of references like `&[&str]` can't be captured.
+/// With `capture = "clone"`, the values are captured with `clone` instead. This keeps `to_owned` from resolving to an
+/// unexpected impl, but only works for owned parameters, since borrowed values aren't `'static`.
+///
+/// With `capture = "debug"`, the arguments and the return value are captured as their `Debug` output instead, so they
+/// only need to implement `Debug`. The arguments are formatted as a tuple of references, like
+/// `format!("{:?}", (&a, &b))`, so a call `f("x", 3)` is logged as the `String` `("x", 3)`. Assert them with
+/// `with_debug` and `and_returned_debug`, e.g. `.with_debug(r#"("x", 3)"#)`.
+/// Parameters of type `impl Into<T>` are converted to `T` before the body runs and captured as `T`, so
+/// `fn set(&self, key: impl Into<String>)` is asserted with `.with("key".to_string())`. Since the parameter is a `T` in
+/// the body, calls to `into` on it may need a type annotation. Use `#[track_skip]` to keep the original parameter.
+/// Parameters of any other `impl Trait` type can't be captured and are left out.
+/// Parameters with destructuring patterns like `(a, b): (i32, i32)` are captured as a whole, while wildcard (`_`)
+/// parameters aren't captured at all.
+///
+/// Parameters of functions and methods can be left out of the capture with `#[track_skip]`, e.g.
+/// `fn exec(&self, #[track_skip] conn: &mut Conn, sql: String)`. Skipped parameters are removed from the tuple, so
+/// calls to `exec` are logged as `sql` alone and asserted with `.with(sql)`.
+///
+/// Parameters that can't be captured but have a cheap projection can be captured as an expression instead with
+/// `#[track(capture_with = "...")]`. The expression refers to the parameter by name and its result takes the
+/// parameter's place in the tuple, so `fn exec(&self, #[track(capture_with = "conn.id()")] conn: &mut Conn, s: String)`
+/// is logged as `(conn.id(), s)`. The result is stored as is, so it must be owned. Functions with projected
+/// parameters get an untyped key from the `keys` option, since the type of the expression isn't known to the macro.
+///
+/// # Call sites
+///
+/// Each call records the location it was logged from, which is inside the tracked function. Mark the function
+/// `#[track_caller]` to record where it was called from instead.
+///
+/// # Async traits
+///
+/// Impl blocks using `#[async_trait]` can be tracked with `track_with` on either side of it. Put `track_with` above
+/// `#[async_trait]`, so it tracks the async methods like any other async method before they're boxed. Below
+/// `#[async_trait]`, methods returning `Pin<Box<dyn Future<Output = T> + ...>>` are tracked as async methods returning
+/// `T`, so the awaited value is captured either way, and the call is logged once the future completes.
+/// The captured arguments and the return value must be `Send`, like everything else held by the future.
+///
+/// # Method overrides
+///
+/// Methods inside a tracked impl block or module can override their own settings with a `#[track(...)]` attribute, or
+/// equivalently a `track_with` attribute that has no tracker path. A method-level `track_with` attribute *with* a tracker
+/// path tracks the method on its own instead.
+///
+/// * `rename` - Replace the method name in the key, e.g. `#[track(rename = "save")]` logs under `Namespace::save`.
+///   If the new name contains `::`, it is used as the whole key.
+/// * `skip` - Don't track the method, e.g. `#[track(skip)]`. This takes precedence over `include` and `exclude`.
+/// * `no_args` - Don't log the arguments, e.g. `#[track(no_args)]`. See `capture_args`.
+/// * `no_return` - Don't log the return value, e.g. `#[track(no_return)]`. See `capture_return`.
+/// * `capture` - Capture the method's arguments and return value differently, e.g. `#[track(capture = "debug")]`.
+///   See `capture`.
+/// * `capture_self` - Log a clone of the receiver, e.g. `#[track(capture_self)]`. See `capture_self`.
+///
+/// Modules nested in a tracked module aren't tracked along with it. They're a compile error unless they're tracked on
+/// their own with `track_with` or left untracked with `#[track(skip)]`.
+///
+/// # Spies
+///
+/// `#[track_with(spy = true)]` on a trait definition generates a spy next to it, named like the trait with a `Spy`
+/// suffix.
+/// `RepoSpy<T: Repo>` holds the real implementation in `inner` and the tracker in `tracker`, and implements `Repo` by
+/// delegating every method to `inner`, including default methods, while logging the calls as `Repo::method`.
+/// Wrap an implementation with `RepoSpy::new(repo, tracker)` and pass it on, e.g. as a `Box<dyn Repo>`.
+/// The other options and the method overrides on the trait's methods apply to the spy. Methods without a receiver
+/// are delegated, but not tracked.
+/// Like `derived`, a bare `spy` is rejected as ambiguous.
+/// Captured associated types must be `Clone + Send + Sync + 'static`, which can be required by the trait, e.g.
+/// `type Id: Clone + Send + Sync + 'static`. Generic traits, async methods, typed receivers like `self: Box<Self>` and
+/// methods taking or returning `Self` aren't supported.
 ///
 /// # Example
 ///
@@ -47,26 +256,72 @@ pub fn track_with(
     args: proc_macro::TokenStream,
     item_tokens: proc_macro::TokenStream
 ) -> proc_macro::TokenStream {
-    let args = syn::parse_macro_input!(args as AttributeArgs);
-    let args = parse_args(args);
+    let args = syn::parse_macro_input!(args as TrackWithArgs);
+    let mut args = match parse_args(args) {
+        Ok(args) => args,
+        Err(error) => return error.to_compile_error().into()
+    };
     //println!("{:?}", args);
 
     let item = syn::parse::<Item>(item_tokens.clone());
 
+    match item {
+        Ok(Item::Impl(ref item)) => check_listed(&mut args, &method_names(item)),
+        Ok(Item::Mod(ItemMod {
+            content: Some((_, ref items)),
+            ..
+        })) => {
+            let names: Vec<_> = items
+                .iter()
+                .flat_map(|item| match item {
+                    Item::Fn(fun) => vec![fun.sig.ident.to_string()],
+                    Item::Impl(item) => method_names(item),
+                    _ => Vec::new()
+                })
+                .collect();
+            check_listed(&mut args, &names);
+        }
+        _ => {}
+    }
+
     let tokens = match item {
+        Ok(Item::Impl(mut item)) if args.derived => {
+            fill_injected_tracker(&mut item);
+            track_impl(&args, item)
+        }
+        Ok(item) if args.derived => quote! {
+            compile_error!("track_with(derived = true) only supports impl blocks of structs with #[inject_tracker].");
+            #item
+        },
         Ok(Item::Fn(fun)) => track_function(&args, fun),
         Ok(Item::Impl(item)) => track_impl(&args, item),
+        Ok(Item::Mod(module)) => track_mod(&args, module),
         Ok(Item::Struct(_)) => quote! {
             compile_error!("Structs aren't a supported attribute target. To track methods, put this attribute on an impl block.")
         },
+        Ok(Item::Trait(item)) if args.spy => spy_trait(&args, item),
+        // The trait is kept so its uses don't cause more errors
+        Ok(Item::Trait(item)) => quote! {
+            compile_error!("Traits aren't a supported attribute target. To track trait methods, put this attribute on the impl block of each implementor, or generate a spy with `track_with(spy = true)`.");
+            #item
+        },
         Err(_) => {
             if let Ok(stmt) = syn::parse::<Stmt>(item_tokens.clone()) {
+                let assigned = |ExprAssign { left, right, .. }| {
+                    if let Expr::Closure(closure) = *right {
+                        let name = quote!(#left).to_string();
+                        let closure = track_closure(&args, closure, name);
+                        quote!(#left = #closure)
+                    } else {
+                        unsupported()
+                    }
+                };
                 let tokens = match stmt {
                     Stmt::Local(Local {
                         pat, init, attrs, ..
                     }) => {
                         if let Some(Expr::Closure(closure)) = init.map(|expr| *expr.1) {
-                            let name = quote!(#pat).to_string();
+                            let name = binding_name(&pat);
                             let closure = track_closure(&args, closure, name);
                             quote! {
                                 #(#attrs)*
@@ -76,18 +331,14 @@ pub fn track_with(
                             unsupported()
                         }
                     }
-                    Stmt::Expr(Expr::Assign(ExprAssign { left, right, .. })) => {
-                        if let Expr::Closure(closure) = *right {
-                            let name = quote!(#left).to_string();
-                            let closure = track_closure(&args, closure, name);
-                            quote!(#left = #closure)
-                        } else {
-                            unsupported()
-                        }
+                    Stmt::Expr(Expr::Assign(assign)) => assigned(assign),
+                    Stmt::Semi(Expr::Assign(assign), semi) => {
+                        let assigned = assigned(assign);
+                        quote!(#assigned #semi)
                     }
                     _ => unsupported()
                 };
-                tokens.into()
+                tokens
             } else {
                 unsupported()
             }
@@ -95,97 +346,835 @@ pub fn track_with(
         _ => unsupported()
     };
 
+    let errors = &args.errors;
+    let tokens = quote! {
+        #(#errors)*
+        #tokens
+    };
+    debug_expand(&args, "track_with", &tokens);
+    tokens.into()
+}
+
+/// Print the code generated by `macro_name` and the keys of the tracked functions to stderr if `debug_expand` is set
+fn debug_expand(args: &Arguments, macro_name: &str, tokens: &TokenStream) {
+    if let Some(expansion) = expansion(args, macro_name, tokens) {
+        eprint!("{}", expansion);
+    }
+}
+
+/// The report printed by `debug_expand`, or `None` if the option isn't set
+fn expansion(args: &Arguments, macro_name: &str, tokens: &TokenStream) -> Option<String> {
+    let keys = args.debug_keys.as_ref()?.borrow();
+    let mut expansion = format!("===== racetrack: {} expansion =====\nKeys:\n", macro_name);
+    for (name, key) in keys.iter() {
+        expansion.push_str(&format!("    {} => {}\n", name, key));
+    }
+    expansion.push_str(&format!(
+        "Generated code:\n{}\n===== racetrack: end of {} expansion =====\n",
+        tokens, macro_name
+    ));
+    Some(expansion)
+}
+
+/// Track a closure in expression position. This works on stable, unlike `track_with` on a `let` statement, which
+/// needs the nightly `proc_macro_hygiene` feature.
+/// Takes the tracker, the name to log the calls under and the closure, followed by any options `track_with` supports
+/// on closures. The closure is logged the same way as with `track_with`, so
+/// `let parse = tracked_closure!(tracker, parse, |input: &str| input.len());` is equivalent to
+/// `#[track_with(tracker)] let parse = |input: &str| input.len();`.
+/// Async closures are tracked too. The call is logged once the returned future completes, with the awaited value as
+/// the return.
+///
+/// # Example
+///
+/// ```
+/// use racetrack::{tracked_closure, Tracker};
+///
+/// let tracker = Tracker::new();
+/// let add = tracked_closure!(tracker, add, |a: u32, b: u32| a + b);
+/// add(1, 2);
+///
+/// tracker.assert_that("add").was_called_once().with((1u32, 2u32)).and_returned(3u32);
+/// ```
+#[proc_macro]
+pub fn tracked_closure(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let TrackedClosure {
+        tracker,
+        name,
+        closure,
+        options
+    } = syn::parse_macro_input!(input as TrackedClosure);
+    let args = match parse_args(TrackWithArgs {
+        fields: Some(quote!(#tracker)),
+        tracker: None,
+        derived: None,
+        spy: None,
+        args: options
+    }) {
+        Ok(args) => args,
+        Err(error) => return error.to_compile_error().into()
+    };
+    let closure = track_closure(&args, closure, name.to_string());
+    let errors = &args.errors;
+    let tokens = quote! {
+        {
+            #(#errors)*
+            #closure
+        }
+    };
+    debug_expand(&args, "tracked_closure", &tokens);
+    tokens.into()
+}
+
+/// The input of `tracked_closure!`
+struct TrackedClosure {
+    tracker: Expr,
+    name: Ident,
+    closure: ExprClosure,
+    options: AttributeArgs
+}
+
+impl Parse for TrackedClosure {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let tracker = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let closure = input.parse()?;
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+        }
+        let options = Punctuated::<NestedMeta, Token![,]>::parse_terminated(input)?;
+        Ok(Self {
+            tracker,
+            name,
+            closure,
+            options: options.into_iter().collect()
+        })
+    }
+}
+
+/// Generate a mock for a trait, a struct named like the trait with a `Mock` prefix that implements the trait without
+/// a real implementation behind it. `MockClock` holds the tracker in `tracker` and is created with
+/// `MockClock::new(tracker)`. Every call is tracked as `Clock::method` like with `track_with`, and returns the value
+/// stubbed for that key with `tracker.when("Clock::method").return_value(...)`. Without a stub, a method returns the
+/// `Default` value of its return type, or panics if it doesn't have one.
+///
+/// Takes the same options as `track_with`, without the tracker path, e.g. `#[mock(capture = "clone")]`, and the trait's
+/// methods can override them with `#[track(...)]`. Stubbed return types must be `Clone + Send + Sync + 'static`.
+/// Generic traits, associated types, associated constants without a default, async methods, methods without a receiver
+/// and methods returning `impl Trait` aren't supported.
+///
+/// # Example
+///
+/// ```
+/// use racetrack::{mock, Tracker};
+///
+/// #[mock]
+/// trait Clock {
+///     fn now(&self) -> u64;
+/// }
+///
+/// let tracker = Tracker::new();
+/// let clock: Box<dyn Clock> = Box::new(MockClock::new(tracker.clone()));
+/// tracker.when("Clock::now").return_value(42u64);
+///
+/// assert_eq!(clock.now(), 42);
+/// tracker.assert_that("Clock::now").was_called_once().and_returned(42u64);
+/// ```
+#[proc_macro_attribute]
+pub fn mock(
+    args: proc_macro::TokenStream,
+    item_tokens: proc_macro::TokenStream
+) -> proc_macro::TokenStream {
+    let options =
+        syn::parse_macro_input!(args with Punctuated::<NestedMeta, Token![,]>::parse_terminated);
+    let args = match parse_args(TrackWithArgs {
+        fields: Some(quote!(tracker)),
+        tracker: None,
+        derived: None,
+        spy: None,
+        args: options.into_iter().collect()
+    }) {
+        Ok(args) => args,
+        Err(error) => return error.to_compile_error().into()
+    };
+    let tokens = match syn::parse::<Item>(item_tokens) {
+        Ok(Item::Trait(item)) => mock_trait(&args, item),
+        Ok(item) => quote_spanned! {
+            item.span() =>
+            compile_error!("Unsupported attribute target. 'mock' only supports trait definitions.");
+            #item
+        },
+        Err(error) => error.to_compile_error()
+    };
+    let errors = &args.errors;
+    let tokens = quote! {
+        #(#errors)*
+        #tokens
+    };
+    debug_expand(&args, "mock", &tokens);
     tokens.into()
 }
 
+/// The names of the tracked functions and their keys, collected for `debug_expand`
+type DebugKeys = Rc<RefCell<Vec<(String, String)>>>;
+
 /// Arguments that can be passed to the proc macro
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Arguments {
     /// The path to the tracker. This must be the first unnamed argument.
     tracker_path: TokenStream,
-    /// A comma separated list of methods to exclude. This only does something on impl blocks.
+    /// Track through the tracker injected by `inject_tracker`, set with `derived = true`. Only used for impl blocks.
+    derived: bool,
+    /// Generate a spy for a trait, set with `spy = true`. Only used for traits.
+    spy: bool,
+    /// A comma separated list of methods to exclude. This only does something on impl blocks and modules.
     exclude: Vec<String>,
+    /// A comma separated list of the only methods to track. Can't be combined with `exclude`.
+    include: Option<Vec<String>>,
+    /// The methods named in the list form of `exclude` or `include`, kept to check that they exist
+    listed: Vec<Ident>,
     /// Include the receiver (self). If false, the tracker must be available in the scope of the relevant method.
     /// If no receiver was found and this is true, the method will be skipped. Defaults to true.
     include_receiver: bool,
+    /// The tracker is an `Option`. Defaults to false.
+    optional: bool,
+    /// The tracker is a `Weak`. Defaults to false.
+    weak: bool,
+    /// Log calls before the body runs. Defaults to false.
+    log_before: bool,
+    /// The path to the tracker for associated functions without a receiver when `include_receiver` is true
+    static_tracker: Option<TokenStream>,
     /// Override the namespace of the tracked item. Tracked key will be namespace::function_name.
-    /// Defaults to the struct name for impl blocks, None for functions and closures.
-    namespace: Option<String>
+    /// Defaults to the struct name for impl blocks, the module name for modules and None for functions and closures.
+    namespace: Option<String>,
+    /// Qualify the namespace of trait impls with the trait, e.g. `<Foo as MyTrait>`. Defaults to false.
+    namespace_trait: bool,
+    /// Prefix the key with the `module_path!()` of the tracked item. Cleared wherever the key is set explicitly.
+    namespace_module: bool,
+    /// The field of the receiver whose value is added to the namespace of each call. Only used for impl blocks.
+    namespace_field: Option<Member>,
+    /// How the key is built from `{namespace}` and `{name}`. Defaults to `{namespace}::{name}`.
+    key_format: String,
+    /// Replace the function name in the key, or the whole key if it contains `::`. Only used for functions.
+    name: Option<String>,
+    /// Log the arguments. Defaults to true.
+    capture_args: bool,
+    /// Log the return value. Defaults to true.
+    capture_return: bool,
+    /// Log a clone of the receiver. Defaults to false.
+    capture_self: bool,
+    /// How arguments and return values are captured. Defaults to `to_owned`.
+    capture: Capture,
+    /// Capture values whose type mentions a type parameter of the function. Defaults to true.
+    capture_generics: bool,
+    /// Add the type arguments of generic functions to the key at runtime. Defaults to false.
+    key_generics: bool,
+    /// Fields of the receiver captured after the arguments. Only used for methods with a receiver.
+    capture_fields: Vec<Member>,
+    /// The positions of the parameters marked `#[track_skip]` in the current function. Set per function.
+    skipped_params: Vec<usize>,
+    /// The positions of the parameters with a `#[track(capture_with = "...")]` attribute in the current function and
+    /// the expressions they're captured with. Set per function.
+    projected_params: Vec<(usize, TokenStream)>,
+    /// Whether the body moves the receiver even though the method borrows it, like the `async move` block of a method
+    /// desugared by `#[async_trait]`. Set per function.
+    moves_receiver: bool,
+    /// Generate a struct with this name containing a key for each tracked method. `Some(None)` names it after the type
+    /// with a `Keys` suffix. Only used for impl blocks.
+    keys: Option<Option<Ident>>,
+    /// Generate a struct with this name starting a typed assertion for each tracked method. `Some(None)` names it after
+    /// the type with an `Assertions` suffix. Only used for impl blocks.
+    typed_assertions: Option<Option<Ident>>,
+    /// The visibility of the structs generated by `keys` and `typed_assertions`. Defaults to `pub(crate)`.
+    vis: Visibility,
+    /// Only track when this `cfg` predicate holds.
+    cfg: Option<TokenStream>,
+    /// The names and keys of the tracked functions, collected to print them with the generated code. `None` unless
+    /// `debug_expand` is set. Shared by the clones of the arguments made per function.
+    debug_keys: Option<DebugKeys>,
+    /// Errors in the arguments, emitted alongside the tracked item
+    errors: Vec<TokenStream>
 }
 
-fn parse_args(mut args: AttributeArgs) -> Arguments {
-    args.reverse();
-    let tracker_path = {
-        if args.len() == 0 {
-            quote_spanned! {
-                Span::call_site() =>
-                compile_error!("Invalid number of arguments. Expected one argument with the path of the tracker.");
+/// How arguments and return values are captured
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Capture {
+    ToOwned,
+    Clone,
+    Debug
+}
+
+impl Capture {
+    /// Parse the value of a `capture = "..."` entry
+    fn parse(lit: Lit) -> syn::Result<Self> {
+        match lit {
+            Lit::Str(ref str) if str.value() == "to_owned" => Ok(Capture::ToOwned),
+            Lit::Str(ref str) if str.value() == "clone" => Ok(Capture::Clone),
+            Lit::Str(ref str) if str.value() == "debug" => Ok(Capture::Debug),
+            lit => Err(syn::Error::new_spanned(
+                lit,
+                "capture expects \"to_owned\", \"clone\" or \"debug\"."
+            ))
+        }
+    }
+
+    /// The expression capturing `value`. In debug mode this is only a reference, see `captured`.
+    fn capture(self, value: &impl ToTokens) -> TokenStream {
+        match self {
+            Capture::ToOwned => quote_spanned! {
+                value.span() =>
+                #value.to_owned()
+            },
+            Capture::Clone => quote_spanned! {
+                value.span() =>
+                ::std::clone::Clone::clone(&#value)
+            },
+            Capture::Debug => quote_spanned! {
+                value.span() =>
+                &#value
+            }
+        }
+    }
+
+    /// The value logged for the captured `values`. Single values aren't wrapped in a tuple.
+    fn captured(self, values: &[TokenStream]) -> TokenStream {
+        match self {
+            Capture::Debug => quote!(::std::format!("{:?}", (#(#values),*))),
+            _ => quote!((#(#values),*))
+        }
+    }
+
+    /// The type captured for a value of type `ty`, or `None` if it can't be named outside of the function.
+    fn captured_type(self, ty: &Type) -> Option<TokenStream> {
+        fn nameable(tokens: TokenStream) -> bool {
+            tokens.into_iter().all(|token| match token {
+                TokenTree::Group(group) => nameable(group.stream()),
+                TokenTree::Ident(ident) => ident != "impl" && ident != "Self",
+                TokenTree::Punct(punct) => punct.as_char() != '\'',
+                TokenTree::Literal(_) => true
+            })
+        }
+        match (self, ty) {
+            // References are dereferenced first by method resolution, so `&str` becomes `String`
+            (Capture::ToOwned, Type::Reference(TypeReference { elem, .. })) => {
+                self.captured_type(elem)
+            }
+            (Capture::ToOwned, ty) if nameable(ty.to_token_stream()) => {
+                Some(quote!(<#ty as ::std::borrow::ToOwned>::Owned))
+            }
+            (Capture::Clone, Type::Reference(_)) => None,
+            (Capture::Clone, ty) if nameable(ty.to_token_stream()) => Some(quote!(#ty)),
+            (Capture::Debug, _) => Some(quote!(::std::string::String)),
+            _ => None
+        }
+    }
+}
+
+impl Arguments {
+    /// Emit `tracked` if the `cfg` predicate holds and `original` otherwise.
+    /// Without a predicate this is just `tracked`.
+    fn cfg_gated(&self, tracked: TokenStream, original: impl ToTokens) -> TokenStream {
+        match self.cfg {
+            Some(ref predicate) => quote! {
+                #[cfg(#predicate)]
+                #tracked
+                #[cfg(not(#predicate))]
+                #original
+            },
+            None => tracked
+        }
+    }
+
+    /// Whether the method or function `name` should be tracked according to `include` and `exclude`.
+    fn tracks(&self, name: &str) -> bool {
+        match self.include {
+            Some(ref include) => include.iter().any(|included| included == name),
+            None => !self.exclude.iter().any(|excluded| excluded == name)
+        }
+    }
+}
+
+/// Check that every method named in the list form of `exclude` or `include` exists in `names`.
+fn check_listed(args: &mut Arguments, names: &[String]) {
+    for ident in &args.listed {
+        if !names.contains(&ident.to_string()) {
+            let message = format!("No method or function named '{}' to track.", ident);
+            args.errors.push(quote_spanned! {
+                ident.span() =>
+                compile_error!(#message);
+            });
+        }
+    }
+}
+
+/// The names of the methods in an impl block
+fn method_names(item: &ItemImpl) -> Vec<String> {
+    item.items
+        .iter()
+        .filter_map(|item| match item {
+            ImplItem::Method(method) => Some(method.sig.ident.to_string()),
+            _ => None
+        })
+        .collect()
+}
+
+fn split_list(str: &LitStr) -> Vec<String> {
+    str.value()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .collect()
+}
+
+/// The arguments of `track_with`. A tracker behind nested fields, like `deps.tracker` or `deps.0.tracker`, isn't valid
+/// in `AttributeArgs`, so it's parsed into `fields` first. `tracked_closure!` passes its tracker expression the same
+/// way.
+/// The named form `tracker = ...` takes any expression, which isn't valid in `AttributeArgs` either, so it's parsed
+/// into `tracker`.
+/// `derived = true` and `spy = true` replace the tracker, so they're parsed into `derived` and `spy` before the tracker
+/// is looked for. The ident is kept for the span of errors.
+struct TrackWithArgs {
+    fields: Option<TokenStream>,
+    tracker: Option<Expr>,
+    derived: Option<Ident>,
+    spy: Option<Ident>,
+    args: AttributeArgs
+}
+
+impl Parse for TrackWithArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let fields = if input.peek2(Token![.]) || input.peek(LitFloat) {
+            let mut members = Vec::new();
+            loop {
+                if input.peek(LitFloat) {
+                    // Nested tuple indices like `0.1` are lexed as a float
+                    let float: LitFloat = input.parse()?;
+                    for index in float.to_string().split('.') {
+                        let index = index
+                            .parse::<u32>()
+                            .map_err(|_| syn::Error::new(float.span(), "Invalid tracker field."))?;
+                        members.push(Member::Unnamed(Index {
+                            index,
+                            span: float.span()
+                        }));
+                    }
+                } else {
+                    members.push(input.parse::<Member>()?);
+                }
+                if !input.peek(Token![.]) {
+                    break;
+                }
+                input.parse::<Token![.]>()?;
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
             }
+            Some(quote!(#(#members).*))
         } else {
-            //println!("{:#?}", args);
-            let arg = args.pop().unwrap();
-            if let NestedMeta::Meta(Meta::Path(path)) = arg {
-                quote!(#path)
-            } else if let NestedMeta::Lit(Lit::Int(int)) = arg {
-                // Tuple struct ident
-                let value = int.base10_parse::<usize>().unwrap();
-                let index: Index = value.into();
-                quote!(#index)
-            } else {
-                quote_spanned! {
-                    arg.span() =>
-                    compile_error!("Invalid argument. Should be path of tracker.");
+            None
+        };
+        let mut tracker = None;
+        let mut derived = None;
+        let mut spy = None;
+        let mut args = Vec::new();
+        while !input.is_empty() {
+            let fork = input.fork();
+            let named = fork.parse::<Ident>().ok().filter(|_| fork.peek(Token![=]));
+            let source = match named {
+                Some(ref ident) if ident == "derived" => Some(&mut derived),
+                Some(ref ident) if ident == "spy" => Some(&mut spy),
+                _ => None
+            };
+            if let Some(source) = source {
+                let ident: Ident = input.parse()?;
+                input.parse::<Token![=]>()?;
+                let key = ident.to_string();
+                if bool_option(&key, input.parse()?)? {
+                    *source = Some(ident);
+                }
+            } else if matches!(named, Some(ref ident) if ident == "tracker") {
+                let ident: Ident = input.parse()?;
+                input.parse::<Token![=]>()?;
+                if tracker.is_some() {
+                    return Err(syn::Error::new(
+                        ident.span(),
+                        "The tracker can only be given once."
+                    ));
                 }
+                tracker = Some(input.parse()?);
+            } else {
+                args.push(input.parse()?);
+            }
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        Ok(Self {
+            fields,
+            tracker,
+            derived,
+            spy,
+            args
+        })
+    }
+}
+
+/// The value of a boolean option like `optional = true`
+fn bool_option(key: &str, lit: Lit) -> syn::Result<bool> {
+    match lit {
+        Lit::Bool(bool) => Ok(bool.value),
+        lit => Err(syn::Error::new_spanned(
+            lit,
+            format!("{0} expects a boolean literal, e.g. `{0} = true`.", key)
+        ))
+    }
+}
+
+/// The value of a string option like `namespace = "Tracked"`
+fn str_option(key: &str, lit: Lit) -> syn::Result<LitStr> {
+    match lit {
+        Lit::Str(str) => Ok(str),
+        lit => Err(syn::Error::new_spanned(
+            lit,
+            format!("{0} expects a string literal, e.g. `{0} = \"...\"`.", key)
+        ))
+    }
+}
+
+/// The value of an option like `keys = "TrackedKeys"` that takes a type name or a boolean
+fn ident_option(key: &str, lit: Lit) -> syn::Result<Option<Option<Ident>>> {
+    match lit {
+        Lit::Str(str) => Ok(Some(Some(Ident::new(&str.value(), str.span())))),
+        Lit::Bool(bool) if bool.value => Ok(Some(None)),
+        Lit::Bool(_) => Ok(None),
+        lit => Err(syn::Error::new_spanned(
+            lit,
+            format!(
+                "{} expects a string literal with the name of the generated struct or a boolean literal.",
+                key
+            )
+        ))
+    }
+}
+
+/// The error for a tracker given both as the first argument and as `tracker = ...`
+fn tracker_twice(tracker: Expr) -> syn::Error {
+    syn::Error::new_spanned(
+        tracker,
+        "The tracker is given twice, as the first argument and as `tracker = ...`. Use one or the other."
+    )
+}
+
+/// The tracker expression from the first argument of `track_with`
+fn tracker_path(arg: Option<NestedMeta>) -> syn::Result<TokenStream> {
+    let arg = arg.ok_or_else(|| {
+        syn::Error::new(
+            Span::call_site(),
+            "Missing tracker. Expected the path of the tracker as the first argument, e.g. `#[track_with(tracker)]`, \
+             or as `tracker = ...`."
+        )
+    })?;
+    match arg {
+        NestedMeta::Meta(Meta::Path(path)) if path.is_ident("derived") || path.is_ident("spy") => {
+            let name = quote!(#path).to_string();
+            Err(syn::Error::new_spanned(
+                path,
+                format!(
+                    "`{0}` is ambiguous. Use `{0} = true` to {1}, or `tracker = {0}` for a tracker named `{0}`.",
+                    name,
+                    if name == "derived" {
+                        "track with the tracker injected by `inject_tracker`"
+                    } else {
+                        "generate a spy for the trait"
+                    }
+                )
+            ))
+        }
+        NestedMeta::Meta(Meta::Path(path)) => Ok(quote!(#path)),
+        NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+            ref path,
+            lit: Lit::Str(ref str),
+            ..
+        })) if path.is_ident("expr") => match str.parse::<Expr>() {
+            Ok(expr) => Ok(quote!(#expr)),
+            Err(_) => Err(syn::Error::new_spanned(
+                str,
+                "Invalid tracker expression. expr expects a string literal with an expression."
+            ))
+        },
+        NestedMeta::Meta(Meta::List(MetaList { path, nested, .. })) => {
+            if nested.is_empty() {
+                // Accessor method, like the one generated by `#[derive(Tracked)]`
+                Ok(quote!(#path()))
+            } else {
+                Err(syn::Error::new_spanned(
+                    nested,
+                    "Invalid argument. The tracker accessor can't take arguments."
+                ))
             }
         }
+        NestedMeta::Lit(Lit::Int(int)) => {
+            // Tuple struct ident
+            let index: Index = int.base10_parse::<usize>()?.into();
+            Ok(quote!(#index))
+        }
+        arg => Err(syn::Error::new_spanned(
+            arg,
+            "Invalid tracker. Expected the path of the tracker as the first argument, e.g. `#[track_with(tracker)]`."
+        ))
+    }
+}
+
+/// Whether `arg` is one of the positional forms of the tracker, rather than an option
+fn is_positional_tracker(arg: &NestedMeta) -> bool {
+    match arg {
+        NestedMeta::Meta(Meta::Path(_)) | NestedMeta::Lit(Lit::Int(_)) => true,
+        NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, .. })) => path.is_ident("expr"),
+        NestedMeta::Meta(Meta::List(MetaList { path, .. })) => {
+            !path.is_ident("exclude") && !path.is_ident("include")
+        }
+        NestedMeta::Lit(_) => false
+    }
+}
+
+fn parse_args(
+    TrackWithArgs {
+        fields,
+        tracker,
+        derived,
+        spy,
+        mut args
+    }: TrackWithArgs
+) -> syn::Result<Arguments> {
+    args.reverse();
+    if let (Some(_), Some(spy)) = (&derived, &spy) {
+        return Err(syn::Error::new_spanned(
+            spy,
+            "`derived` and `spy` can't be used together. Use one or the other."
+        ));
+    }
+    if let Some(source) = derived.as_ref().or(spy.as_ref()) {
+        let positional = matches!(args.last(), Some(arg) if is_positional_tracker(arg));
+        if fields.is_some() || tracker.is_some() || positional {
+            return Err(syn::Error::new_spanned(
+                source,
+                format!(
+                    "`{} = true` provides the tracker, so no other tracker can be given.",
+                    source
+                )
+            ));
+        }
+    }
+    let tracker_path = match (fields, tracker) {
+        // The tracker injected by `inject_tracker` is the first field of the `TrackerSlot`
+        _ if derived.is_some() => quote!(__tracker.0),
+        // The spy logs to its own `tracker` field
+        _ if spy.is_some() => quote!(tracker),
+        (Some(_), Some(tracker)) => return Err(tracker_twice(tracker)),
+        (None, Some(tracker)) => match args.last() {
+            Some(arg) if is_positional_tracker(arg) => return Err(tracker_twice(tracker)),
+            _ => quote!(#tracker)
+        },
+        (Some(fields), None) => fields,
+        (None, None) => tracker_path(args.pop())?
     };
     let mut arguments = Arguments {
         tracker_path,
+        derived: derived.is_some(),
+        spy: spy.is_some(),
         exclude: Vec::new(),
+        include: None,
+        listed: Vec::new(),
         include_receiver: true,
-        namespace: None
+        optional: false,
+        weak: false,
+        log_before: false,
+        static_tracker: None,
+        namespace: None,
+        namespace_trait: false,
+        namespace_module: false,
+        namespace_field: None,
+        key_format: DEFAULT_KEY_FORMAT.to_string(),
+        name: None,
+        capture_args: true,
+        capture_return: true,
+        capture_self: false,
+        capture: Capture::ToOwned,
+        capture_generics: true,
+        key_generics: false,
+        capture_fields: Vec::new(),
+        skipped_params: Vec::new(),
+        projected_params: Vec::new(),
+        moves_receiver: false,
+        keys: None,
+        typed_assertions: None,
+        vis: parse_quote!(pub(crate)),
+        cfg: None,
+        debug_keys: None,
+        errors: Vec::new()
     };
+    let mut exclude_span = None;
+    let mut include_span = None;
     while let Some(next) = args.pop() {
-        if let NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) = next {
-            if let Some(key) = path.segments.first().map(|path| path.ident.to_string()) {
-                match key.as_str() {
-                    "exclude" => {
-                        if let Lit::Str(str) = lit {
-                            let token = str.value();
-                            let value: Vec<_> =
-                                token.split(",").map(|s| s.trim().to_string()).collect();
-                            arguments.exclude = value;
-                        } else {
-                            panic!("Invalid value for exclude config. Should be comma separated string.");
-                        }
-                    }
-                    "include_receiver" => {
-                        if let Lit::Bool(bool) = lit {
-                            arguments.include_receiver = bool.value;
-                        } else {
-                            panic!("Invalid value for include_receiver config. Should be boolean.");
-                        }
-                    }
-                    "namespace" => {
-                        if let Lit::Str(str) = lit {
-                            arguments.namespace = Some(str.value());
-                        } else {
-                            panic!("Invalid value for namespace config. Should be a string.");
+        let (path, lit) = match next {
+            NestedMeta::Meta(Meta::List(list)) => {
+                let names = if list.path.is_ident("exclude") {
+                    exclude_span = Some(list.path.span());
+                    &mut arguments.exclude
+                } else if list.path.is_ident("include") {
+                    include_span = Some(list.path.span());
+                    arguments.include.get_or_insert_with(Vec::new)
+                } else {
+                    return Err(syn::Error::new_spanned(
+                        list.path,
+                        "Unexpected list in track_with attribute. Only exclude and include take a list of method names."
+                    ));
+                };
+                for name in list.nested {
+                    match name {
+                        NestedMeta::Meta(Meta::Path(ref path)) if path.get_ident().is_some() => {
+                            let ident = path.get_ident().unwrap();
+                            names.push(ident.to_string());
+                            arguments.listed.push(ident.clone());
                         }
+                        name => arguments.errors.push(quote_spanned! {
+                            name.span() =>
+                            compile_error!("Expected a method name.");
+                        })
                     }
-                    _ => {
-                        panic!("Unexpected config entry in track_with attribute.");
+                }
+                continue;
+            }
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) => (path, lit),
+            next => {
+                return Err(syn::Error::new_spanned(
+                    next,
+                    "Unexpected argument in track_with attribute. Expected an option like `namespace = \"...\"`."
+                ))
+            }
+        };
+        let key = match path.get_ident() {
+            Some(ident) => ident.to_string(),
+            None => {
+                return Err(syn::Error::new_spanned(
+                    path,
+                    "Invalid option in track_with attribute. Expected a single identifier."
+                ))
+            }
+        };
+        match key.as_str() {
+            "exclude" => {
+                exclude_span = Some(path.span());
+                arguments.exclude = split_list(&str_option(&key, lit)?);
+            }
+            "include" => {
+                include_span = Some(path.span());
+                arguments.include = Some(split_list(&str_option(&key, lit)?));
+            }
+            "include_receiver" => arguments.include_receiver = bool_option(&key, lit)?,
+            "optional" => arguments.optional = bool_option(&key, lit)?,
+            "weak" => arguments.weak = bool_option(&key, lit)?,
+            "log_before" => arguments.log_before = bool_option(&key, lit)?,
+            "static_tracker" => {
+                let str = str_option(&key, lit)?;
+                match str.parse::<syn::Path>() {
+                    Ok(path) => arguments.static_tracker = Some(quote!(#path)),
+                    Err(_) => arguments.errors.push(quote_spanned! {
+                        str.span() =>
+                        compile_error!("Invalid static tracker. Should be the path of a tracker.");
+                    })
+                }
+            }
+            "namespace" => arguments.namespace = Some(str_option(&key, lit)?.value()),
+            "name" => arguments.name = Some(str_option(&key, lit)?.value()),
+            "capture_args" => arguments.capture_args = bool_option(&key, lit)?,
+            "capture_return" => arguments.capture_return = bool_option(&key, lit)?,
+            "capture_self" => arguments.capture_self = bool_option(&key, lit)?,
+            "capture" => arguments.capture = Capture::parse(lit)?,
+            "capture_fields" => {
+                let str = str_option(&key, lit)?;
+                for field in split_list(&str) {
+                    match syn::parse_str::<Member>(&field) {
+                        Ok(member) => arguments.capture_fields.push(member),
+                        Err(_) => arguments.errors.push(quote_spanned! {
+                            str.span() =>
+                            compile_error!("Invalid field in capture_fields. Expected a field name or index.");
+                        })
                     }
                 }
-            } else {
-                panic!("Invalid config entry in track_with attribute.");
             }
-        } else {
-            panic!("Unexpected argument in track_with attribute.");
+            "keys" => arguments.keys = ident_option(&key, lit)?,
+            "typed_assertions" => arguments.typed_assertions = ident_option(&key, lit)?,
+            "vis" => {
+                let str = str_option(&key, lit)?;
+                match str.parse::<Visibility>() {
+                    Ok(vis) => arguments.vis = vis,
+                    Err(_) => arguments.errors.push(quote_spanned! {
+                        str.span() =>
+                        compile_error!("Invalid visibility. Expected a visibility like `pub` or `pub(crate)`.");
+                    })
+                }
+            }
+            "cfg" => {
+                let str = str_option(&key, lit)?;
+                match str.parse::<NestedMeta>() {
+                    Ok(predicate) => arguments.cfg = Some(quote!(#predicate)),
+                    Err(_) => arguments.errors.push(quote_spanned! {
+                        str.span() =>
+                        compile_error!("Invalid cfg predicate.");
+                    })
+                }
+            }
+            "namespace_trait" => arguments.namespace_trait = bool_option(&key, lit)?,
+            "namespace_field" => {
+                let str = str_option(&key, lit)?;
+                match str.parse::<Member>() {
+                    Ok(member) => arguments.namespace_field = Some(member),
+                    Err(_) => arguments.errors.push(quote_spanned! {
+                        str.span() =>
+                        compile_error!("Invalid namespace_field. Expected a field name or index.");
+                    })
+                }
+            }
+            "namespace_module" => arguments.namespace_module = bool_option(&key, lit)?,
+            "capture_generics" => arguments.capture_generics = bool_option(&key, lit)?,
+            "key_generics" => arguments.key_generics = bool_option(&key, lit)?,
+            "debug_expand" => {
+                arguments.debug_keys = if bool_option(&key, lit)? {
+                    Some(Default::default())
+                } else {
+                    None
+                }
+            }
+            "key_format" => {
+                let str = str_option(&key, lit)?;
+                match validate_key_format(&str.value()) {
+                    Ok(()) => arguments.key_format = str.value(),
+                    Err(error) => arguments.errors.push(quote_spanned! {
+                        str.span() =>
+                        compile_error!(#error);
+                    })
+                }
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    path,
+                    format!("Unknown option '{}' in track_with attribute.", key)
+                ))
+            }
         }
     }
-    //println!("{:?}", arguments);
-    arguments
+    if let (Some(_), Some(span)) = (exclude_span, include_span) {
+        arguments.errors.push(quote_spanned! {
+            span =>
+            compile_error!("'include' and 'exclude' can't be used together. Use one or the other.");
+        });
+    }
+    // The injected tracker is only set in tests
+    arguments.optional |= arguments.derived;
+    Ok(arguments)
 }
 
 fn track_impl(args: &Arguments, item: ItemImpl) -> TokenStream {
@@ -200,131 +1189,1195 @@ fn track_impl(args: &Arguments, item: ItemImpl) -> TokenStream {
         items,
         ..
     } = item;
-    let namespace = args
-        .namespace
-        .as_ref()
-        .map(|s| s.clone())
-        .unwrap_or_else(|| quote!(#self_ty).to_string());
-    let trait_ = trait_.map(|(bang, trait_, for_)| quote!(#bang#trait_ #for_));
-
-    let items = items.iter().map(|item| {
-        if let ImplItem::Method(method) = item {
-            track_method(&args, method, &namespace)
-        } else {
-            quote!(#item)
-        }
+    let namespace = args.namespace.clone().unwrap_or_else(|| match trait_ {
+        Some((_, ref path, _)) if args.namespace_trait => format!(
+            "<{} as {}>",
+            normalized_name(&self_ty),
+            normalized_name(path)
+        ),
+        _ => normalized_name(&self_ty)
     });
+    let trait_ = trait_.map(|(bang, path, for_)| quote!(#bang #path #for_));
+    let args = &Arguments {
+        namespace_module: args.namespace_module && args.namespace.is_none(),
+        ..args.clone()
+    };
+
+    let mut tracked = Vec::new();
+    let items: Vec<_> = items
+        .iter()
+        .map(|item| {
+            if let ImplItem::Method(method) = item {
+                track_method(args, method, &namespace, &mut tracked)
+            } else {
+                quote!(#item)
+            }
+        })
+        .collect();
+
+    // Generics only print the angle brackets, the where clause has to be emitted separately
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    // The keys of generic impl blocks can't be named and the keys of instances aren't known until runtime
+    let unsupported = if !generics.params.is_empty() {
+        Some("generic impl blocks")
+    } else if args.namespace_field.is_some() {
+        Some("impl blocks with namespace_field")
+    } else if args.key_generics {
+        Some("impl blocks with key_generics")
+    } else {
+        None
+    };
+    let vis = &args.vis;
+    let keys = match companion_name(&args.keys, &self_ty, "Keys", "keys", unsupported) {
+        Some(Ok(ident)) => {
+            let keys = tracked.iter().map(|method| call_key(vis, method));
+            quote! {
+                #vis struct #ident;
+
+                impl #ident {
+                    #(#keys)*
+                }
+            }
+        }
+        Some(Err(error)) => error,
+        None => quote!()
+    };
+    let assertions = match companion_name(
+        &args.typed_assertions,
+        &self_ty,
+        "Assertions",
+        "typed_assertions",
+        unsupported
+    ) {
+        Some(Ok(ident)) => {
+            let assertions = tracked.iter().map(|method| typed_assertion(vis, method));
+            quote! {
+                #vis struct #ident<'a>(#vis &'a ::racetrack::Tracker);
+
+                #[allow(dead_code)]
+                impl<'a> #ident<'a> {
+                    #vis fn new(tracker: &'a ::racetrack::Tracker) -> Self {
+                        Self(tracker)
+                    }
+
+                    #(#assertions)*
+                }
+            }
+        }
+        Some(Err(error)) => error,
+        None => quote!()
+    };
 
     let tokens = quote! {
         #(#attrs)*
-        #defaultness #unsafety impl #generics #trait_ #self_ty {
+        #defaultness #unsafety impl #impl_generics #trait_ #self_ty #where_clause {
             #(#items)*
         }
+
+        #keys
+        #assertions
     };
 
     //println!("{}", tokens);
     tokens
 }
 
-fn track_method(args: &Arguments, method: &ImplItemMethod, namespace: &str) -> TokenStream {
-    let name = method.sig.ident.to_string();
-    if args.exclude.contains(&name) {
-        return quote!(#method);
+/// Generate a spy for the trait, a wrapper named like the trait with a `Spy` suffix that implements the trait by
+/// delegating to an inner implementation and tracks every call under `Trait::method`.
+fn spy_trait(args: &Arguments, mut item: ItemTrait) -> TokenStream {
+    let ident = &item.ident;
+    if !item.generics.params.is_empty() {
+        let error = quote_spanned! {
+            item.generics.span() =>
+            compile_error!("Spies can't be generated for generic traits yet.");
+        };
+        return quote!(#error #item);
     }
-    let name = format!("{}::{}", namespace, name);
-
-    let ImplItemMethod {
-        attrs,
-        vis,
-        defaultness,
-        sig,
-        block
-    } = method;
+    let spy = Ident::new(&format!("{}Spy", ident), ident.span());
 
-    let receiver = sig.inputs.iter().find_map(|arg| {
-        if let FnArg::Receiver(recv) = arg {
-            Some(recv)
-        } else {
-            None
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    for trait_item in item.items.iter_mut() {
+        match trait_item {
+            TraitItem::Method(method) => match spy_method(ident, method) {
+                Ok(method) => items.push(method),
+                Err(error) => errors.push(error)
+            },
+            TraitItem::Type(TraitItemType {
+                ident: name,
+                generics,
+                ..
+            }) if generics.params.is_empty() => {
+                items.push(quote!(type #name = <T as #ident>::#name;))
+            }
+            TraitItem::Const(TraitItemConst {
+                ident: name, ty, ..
+            }) => items.push(quote!(const #name: #ty = <T as #ident>::#name;)),
+            trait_item => errors.push(quote_spanned! {
+                trait_item.span() =>
+                compile_error!("Spies only support methods, associated types without generics and constants.");
+            })
         }
-    });
+    }
 
-    if args.include_receiver && receiver.is_none() {
-        // Skip static methods since the tracker path won't be valid
+    let args = Arguments {
+        tracker_path: quote!(tracker),
+        include_receiver: true,
+        static_tracker: None,
+        namespace: Some(args.namespace.clone().unwrap_or_else(|| ident.to_string())),
+        ..args.clone()
+    };
+    let unsafety = &item.unsafety;
+    // Without the impl, the errors aren't followed by errors about missing trait items
+    let spied = if errors.is_empty() {
+        track_impl(
+            &args,
+            parse_quote! {
+                #unsafety impl<T: #ident> #ident for #spy<T> {
+                    #(#items)*
+                }
+            }
+        )
+    } else {
+        quote!(#(#errors)*)
+    };
+    let vis = &item.vis;
+    let doc = format!(
+        "A spy on an implementation of [`{0}`]. Calls are delegated to `inner` and logged to `tracker` as `{1}::method`.",
+        ident,
+        args.namespace.as_deref().unwrap_or_default()
+    );
+
+    quote! {
+        #item
+
+        #[doc = #doc]
+        #[allow(dead_code)]
+        #vis struct #spy<T> {
+            pub inner: T,
+            pub tracker: ::std::sync::Arc<::racetrack::Tracker>
+        }
+
+        #[allow(dead_code)]
+        impl<T> #spy<T> {
+            /// Spy on `inner`, logging its calls to `tracker`
+            pub fn new(inner: T, tracker: ::std::sync::Arc<::racetrack::Tracker>) -> Self {
+                Self { inner, tracker }
+            }
+
+            /// The spied on implementation
+            pub fn into_inner(self) -> T {
+                self.inner
+            }
+        }
+
+        #spied
+    }
+}
+
+/// The method of a spy delegating to the trait method `method` of the inner implementation.
+fn spy_method(
+    trait_ident: &Ident,
+    method: &mut TraitItemMethod
+) -> Result<TokenStream, TokenStream> {
+    let (attrs, sig, forwarded) = implemented_method(method, "Spies")?;
+    for arg in sig.inputs.iter() {
+        if let FnArg::Typed(PatType { ty, .. }) = arg {
+            if mentions_self_type(quote!(#ty)) {
+                return Err(quote_spanned! {
+                    ty.span() =>
+                    compile_error!("Spies don't support methods taking `Self`, since the spy isn't the inner implementation.");
+                });
+            }
+        }
+    }
+    if let ReturnType::Type(_, ref ty) = sig.output {
+        if mentions_self_type(quote!(#ty)) {
+            return Err(quote_spanned! {
+                ty.span() =>
+                compile_error!("Spies don't support methods returning `Self`, since the spy isn't the inner implementation.");
+            });
+        }
+    }
+
+    let name = &sig.ident;
+    Ok(quote! {
+        #(#attrs)*
+        #sig {
+            <T as #trait_ident>::#name(#(#forwarded),*)
+        }
+    })
+}
+
+/// The attributes and signature of the trait method `method` in a generated impl, along with the arguments forwarding
+/// the call to an inner implementation called `inner`. `kind` names the generated type in errors.
+/// The `track` attributes of the method and its parameters are moved from the trait to the impl, and parameters with
+/// patterns are given a name so they can be forwarded.
+fn implemented_method(
+    method: &mut TraitItemMethod,
+    kind: &str
+) -> Result<(Vec<Attribute>, Signature, Vec<TokenStream>), TokenStream> {
+    let mut sig = method.sig.clone();
+    let attrs = method.attrs.clone();
+    let overrides = method_overrides(&method.attrs);
+    match overrides {
+        Ok(Some((ref remaining, _))) => method.attrs = remaining.clone(),
+        Ok(None) => {}
+        Err(_) => strip_track_attrs(&mut method.attrs)
+    }
+    let params = param_overrides(&mut method.sig.inputs);
+    overrides
+        .and(params)
+        .map_err(|error| error.to_compile_error())?;
+
+    if let Some(asyncness) = sig.asyncness {
+        let error = format!("{} don't support async methods yet.", kind);
+        return Err(quote_spanned! {
+            asyncness.span() =>
+            compile_error!(#error);
+        });
+    }
+    let mut forwarded = Vec::new();
+    for (i, arg) in sig.inputs.iter_mut().enumerate() {
+        match arg {
+            FnArg::Receiver(Receiver {
+                reference: Some(_),
+                mutability: Some(_),
+                ..
+            }) => forwarded.push(quote!(&mut self.inner)),
+            FnArg::Receiver(Receiver {
+                reference: Some(_), ..
+            }) => forwarded.push(quote!(&self.inner)),
+            FnArg::Receiver(receiver) => {
+                receiver.mutability = None;
+                forwarded.push(quote!(self.inner))
+            }
+            FnArg::Typed(PatType { pat, .. }) if is_self(pat) => {
+                let error = format!(
+                    "{} don't support typed receivers like `self: Box<Self>`.",
+                    kind
+                );
+                return Err(quote_spanned! {
+                    arg.span() =>
+                    compile_error!(#error);
+                });
+            }
+            FnArg::Typed(PatType { pat, .. }) => {
+                let ident = match **pat {
+                    Pat::Ident(PatIdent { ref ident, .. }) => ident.clone(),
+                    _ => Ident::new(&format!("__racetrack_arg{}", i), pat.span())
+                };
+                forwarded.push(quote!(#ident));
+                **pat = parse_quote!(#ident);
+            }
+        }
+    }
+    Ok((attrs, sig, forwarded))
+}
+
+/// Generate a mock for the trait, a struct named like the trait with a `Mock` prefix that implements the trait by
+/// returning stubbed values and tracks every call under `Trait::method`.
+fn mock_trait(args: &Arguments, mut item: ItemTrait) -> TokenStream {
+    let ident = &item.ident;
+    if !item.generics.params.is_empty() {
+        let error = quote_spanned! {
+            item.generics.span() =>
+            compile_error!("Mocks can't be generated for generic traits yet.");
+        };
+        return quote!(#error #item);
+    }
+    let mock = Ident::new(&format!("Mock{}", ident), ident.span());
+    let namespace = args.namespace.clone().unwrap_or_else(|| ident.to_string());
+
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    for trait_item in item.items.iter_mut() {
+        match trait_item {
+            TraitItem::Method(method) => match mock_method(&namespace, &args.key_format, method) {
+                Ok(method) => items.push(method),
+                Err(error) => errors.push(error)
+            },
+            // Constants with a default don't need to be implemented
+            TraitItem::Const(TraitItemConst {
+                default: Some(_), ..
+            }) => {}
+            trait_item => errors.push(quote_spanned! {
+                trait_item.span() =>
+                compile_error!("Mocks only support methods and constants with a default, since nothing else can be stubbed.");
+            })
+        }
+    }
+
+    let args = Arguments {
+        include_receiver: true,
+        static_tracker: None,
+        namespace: Some(namespace),
+        ..args.clone()
+    };
+    let unsafety = &item.unsafety;
+    // Without the impl, the errors aren't followed by errors about missing trait items
+    let mocked = if errors.is_empty() {
+        track_impl(
+            &args,
+            parse_quote! {
+                #unsafety impl #ident for #mock {
+                    #(#items)*
+                }
+            }
+        )
+    } else {
+        quote!(#(#errors)*)
+    };
+    let vis = &item.vis;
+    let doc = format!(
+        "A mock of [`{0}`]. Calls are logged to `tracker` as `{1}::method` and return the value stubbed with \
+         `tracker.when(\"{1}::method\")`.",
+        ident,
+        args.namespace.as_deref().unwrap_or_default()
+    );
+
+    quote! {
+        #item
+
+        #[doc = #doc]
+        #[allow(dead_code)]
+        #vis struct #mock {
+            pub tracker: ::std::sync::Arc<::racetrack::Tracker>
+        }
+
+        #[allow(dead_code)]
+        impl #mock {
+            /// Mock the trait, logging calls to `tracker`
+            pub fn new(tracker: ::std::sync::Arc<::racetrack::Tracker>) -> Self {
+                Self { tracker }
+            }
+        }
+
+        #mocked
+    }
+}
+
+/// The method of a mock returning the value stubbed for the trait method `method`, or its fallback
+fn mock_method(
+    namespace: &str,
+    key_format: &str,
+    method: &mut TraitItemMethod
+) -> Result<TokenStream, TokenStream> {
+    let (attrs, sig, _) = implemented_method(method, "Mocks")?;
+    if sig.receiver().is_none() {
+        return Err(quote_spanned! {
+            sig.ident.span() =>
+            compile_error!("Mocks only support methods with a receiver, since the tracker is reached through it.");
+        });
+    }
+    let returned = match sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ref ty) if mentions(quote!(#ty), "impl") => {
+            return Err(quote_spanned! {
+                ty.span() =>
+                compile_error!("Mocks don't support methods returning `impl Trait`, since they can't be stubbed.");
+            })
+        }
+        ReturnType::Type(_, ref ty) => quote!(#ty)
+    };
+    // The attributes were already validated by `implemented_method`
+    let rename = method_overrides(&attrs)
+        .ok()
+        .flatten()
+        .and_then(|(_, overrides)| overrides.rename);
+    let key = key(
+        key_format,
+        Some(namespace),
+        rename.as_deref(),
+        &sig.ident.to_string()
+    );
+
+    Ok(quote! {
+        #(#attrs)*
+        #[allow(unused_variables)]
+        #sig {
+            use ::racetrack::stub::{DefaultFallback as _, PanicFallback as _};
+            match self.tracker.stubbed::<#returned>(#key) {
+                Some(value) => value,
+                None => (&&::racetrack::stub::Fallback::<#returned>::new()).fallback(#key)
+            }
+        }
+    })
+}
+
+/// Whether `tokens` refer to the `Self` type itself, not to one of its associated items like `Self::Item`
+fn mentions_self_type(tokens: TokenStream) -> bool {
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Group(group) if mentions_self_type(group.stream()) => return true,
+            TokenTree::Ident(ident) if ident == "Self" => match tokens.peek() {
+                Some(TokenTree::Punct(punct)) if punct.as_char() == ':' => {}
+                _ => return true
+            },
+            _ => {}
+        }
+    }
+    false
+}
+
+fn track_mod(args: &Arguments, module: ItemMod) -> TokenStream {
+    let ItemMod {
+        attrs,
+        vis,
+        mod_token,
+        ident,
+        content,
+        semi,
+        ..
+    } = module;
+    let items = if let Some((_, items)) = content {
+        items
+    } else {
+        return quote_spanned! {
+            ident.span() =>
+            compile_error!("Only inline modules can be tracked.");
+        };
+    };
+
+    // The module path of the functions already ends with the module
+    let namespace = match args.namespace {
+        None if args.namespace_module => None,
+        ref namespace => namespace.clone().or_else(|| Some(ident.to_string()))
+    };
+    let fn_args = Arguments {
+        namespace,
+        ..args.clone()
+    };
+    let items = items.into_iter().map(|item| match item {
+        Item::Fn(mut fun) => {
+            let (attrs, overrides) = match method_overrides(&fun.attrs) {
+                Ok(Some(overrides)) => overrides,
+                Ok(None) => return quote!(#fun),
+                Err(error) => {
+                    let error = error.to_compile_error();
+                    strip_track_attrs(&mut fun.attrs);
+                    let _ = param_overrides(&mut fun.sig.inputs);
+                    return quote!(#error #fun);
+                }
+            };
+            let mut fun = ItemFn { attrs, ..fun };
+            if overrides.skip || !args.tracks(&fun.sig.ident.to_string()) {
+                if let Err(error) = param_overrides(&mut fun.sig.inputs) {
+                    let error = error.to_compile_error();
+                    return quote!(#error #fun);
+                }
+                return quote!(#fun);
+            }
+            let fn_args = Arguments {
+                name: overrides.rename,
+                capture_args: fn_args.capture_args && !overrides.no_args,
+                capture_return: fn_args.capture_return && !overrides.no_return,
+                capture: overrides.capture.unwrap_or(fn_args.capture),
+                ..fn_args.clone()
+            };
+            track_function(&fn_args, fun)
+        }
+        // Keys and typed assertions are only generated for impl blocks that are tracked on their own
+        Item::Impl(item) => track_impl(
+            &Arguments {
+                keys: None,
+                typed_assertions: None,
+                ..args.clone()
+            },
+            item
+        ),
+        // Nested modules aren't tracked yet, so they have to opt out or be tracked on their own
+        Item::Mod(mut module) => match method_overrides(&module.attrs) {
+            Ok(Some((attrs, overrides))) if overrides.skip => {
+                let module = ItemMod { attrs, ..module };
+                quote!(#module)
+            }
+            Err(error) => {
+                let error = error.to_compile_error();
+                strip_track_attrs(&mut module.attrs);
+                quote!(#error #module)
+            }
+            // Also matches paths like `racetrack::track_with`, since the attribute usually isn't in scope in the module
+            _ if module.attrs.iter().any(|attr| {
+                matches!(attr.path.segments.last(), Some(segment) if segment.ident == "track_with")
+            }) =>
+            {
+                quote!(#module)
+            }
+            _ => quote_spanned! {
+                module.ident.span() =>
+                compile_error!("Nested modules aren't tracked. Add `#[track_with(...)]` to track this module on its own, or `#[track(skip)]` to leave it untracked.");
+            }
+        },
+        item => quote!(#item)
+    });
+
+    let tokens = quote! {
+        #(#attrs)*
+        #vis #mod_token #ident {
+            #(#items)*
+        }#semi
+    };
+
+    tokens
+}
+
+/// Generate a `tracker(&self)` accessor for the field holding the tracker, so impl blocks can be tracked with
+/// `#[track_with(tracker())]` regardless of the field's name or position.
+///
+/// The field is the one marked `#[tracker]`, or otherwise the only field of type `Arc<Tracker>`.
+///
+/// # Example
+///
+/// ```
+/// # use std::sync::Arc;
+/// use racetrack::{Tracker, Tracked, track_with};
+///
+/// #[derive(Tracked)]
+/// struct TrackedStruct {
+///     name: String,
+///     #[tracker]
+///     calls: Arc<Tracker>
+/// }
+///
+/// #[track_with(tracker())]
+/// impl TrackedStruct {
+///     fn tracked_fn(&self, arg: String) {}
+/// }
+/// ```
+#[proc_macro_derive(Tracked, attributes(tracker))]
+pub fn derive_tracked(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let item = syn::parse_macro_input!(item as DeriveInput);
+    let fields = match item.data {
+        Data::Struct(DataStruct { fields, .. }) => fields,
+        _ => {
+            return quote! {
+                compile_error!("Tracked can only be derived for structs.");
+            }
+            .into()
+        }
+    };
+    let fields: Vec<_> = match fields {
+        Fields::Named(fields) => fields.named.into_iter().collect(),
+        Fields::Unnamed(fields) => fields.unnamed.into_iter().collect(),
+        Fields::Unit => Vec::new()
+    };
+    let marked: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| field.attrs.iter().any(|attr| attr.path.is_ident("tracker")))
+        .collect();
+    let typed: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| is_arc_tracker(&field.ty))
+        .collect();
+    let (index, field) = match (marked.as_slice(), typed.as_slice()) {
+        ([field], _) | ([], [field]) => *field,
+        ([], []) => {
+            return quote! {
+                compile_error!("No tracker field found. Mark the field holding the tracker with #[tracker].");
+            }
+            .into()
+        }
+        ([], _) => {
+            return quote! {
+                compile_error!("Multiple tracker fields found. Mark the one to use with #[tracker].");
+            }
+            .into()
+        }
+        _ => {
+            return quote! {
+                compile_error!("Only one field can be marked with #[tracker].");
+            }
+            .into()
+        }
+    };
+    let member = match field.ident {
+        Some(ref ident) => quote!(#ident),
+        None => {
+            let index: Index = index.into();
+            quote!(#index)
+        }
+    };
+    let ty = &field.ty;
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+
+    let tokens = quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// The tracker the calls to this struct are logged to
+            pub fn tracker(&self) -> &#ty {
+                &self.#member
+            }
+        }
+    };
+    tokens.into()
+}
+
+/// Add a hidden tracker field to a struct, so its impl blocks can be tracked with `#[track_with(derived = true)]` without a
+/// tracker in its constructors. The tracker is `None` until it's set with the generated `set_tracker`, so calls made
+/// in production code aren't logged and nothing is captured for them. `tracker()` returns the tracker if it's set.
+///
+/// The field doesn't affect derived traits like `PartialEq` or `Hash`, as long as `inject_tracker` comes before the
+/// `derive` attribute. Struct expressions in impl blocks tracked with `derived = true` get the field filled in, but struct
+/// expressions anywhere else need to add `..Default::default()` or construct the struct through one of those
+/// constructors. Only structs with named fields are supported.
+///
+/// # Example
+///
+/// ```
+/// use racetrack::{inject_tracker, track_with, Tracker};
+///
+/// #[inject_tracker]
+/// #[derive(Debug, Clone, PartialEq)]
+/// struct Counter {
+///     count: u32
+/// }
+///
+/// #[track_with(derived = true)]
+/// impl Counter {
+///     fn new() -> Self {
+///         Self { count: 0 }
+///     }
+///
+///     fn add(&mut self, amount: u32) {
+///         self.count += amount;
+///     }
+/// }
+///
+/// let tracker = Tracker::new();
+/// let mut counter = Counter::new();
+/// counter.add(1);
+/// counter.set_tracker(tracker.clone());
+/// counter.add(2);
+///
+/// tracker.assert_that("Counter::add").was_called_once().with(2u32);
+/// ```
+#[proc_macro_attribute]
+pub fn inject_tracker(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream
+) -> proc_macro::TokenStream {
+    let args = TokenStream::from(args);
+    if !args.is_empty() {
+        return syn::Error::new_spanned(args, "inject_tracker doesn't take arguments.")
+            .to_compile_error()
+            .into();
+    }
+    let mut item = syn::parse_macro_input!(item as ItemStruct);
+    match item.fields {
+        Fields::Named(ref mut fields) => {
+            let field = Field::parse_named.parse2(quote! {
+                #[doc(hidden)]
+                __tracker: ::racetrack::tracker::TrackerSlot
+            });
+            match field {
+                Ok(field) => fields.named.push(field),
+                Err(error) => return error.to_compile_error().into()
+            }
+        }
+        _ => {
+            return syn::Error::new_spanned(
+                &item.fields,
+                "inject_tracker only supports structs with named fields."
+            )
+            .to_compile_error()
+            .into()
+        }
+    }
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+
+    let tokens = quote! {
+        #item
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Log the calls of the impl blocks tracked with `#[track_with(derived = true)]` to `tracker`
+            pub fn set_tracker(&mut self, tracker: ::std::sync::Arc<::racetrack::Tracker>) {
+                self.__tracker.0 = ::std::option::Option::Some(tracker);
+            }
+
+            /// The tracker set with `set_tracker`, if any
+            pub fn tracker(&self) -> ::std::option::Option<&::std::sync::Arc<::racetrack::Tracker>> {
+                self.__tracker.0.as_ref()
+            }
+        }
+    };
+    tokens.into()
+}
+
+/// Fill in the field added by `inject_tracker` in the struct expressions of the type in an impl block tracked with
+/// `derived`, unless they already set it or take the rest of the fields from another value.
+fn fill_injected_tracker(item: &mut ItemImpl) {
+    struct Filler(Option<Ident>);
+
+    impl VisitMut for Filler {
+        fn visit_expr_struct_mut(&mut self, expr: &mut ExprStruct) {
+            visit_mut::visit_expr_struct_mut(self, expr);
+            let constructs_self = match expr.path.segments.last() {
+                Some(segment) => segment.ident == "Self" || Some(&segment.ident) == self.0.as_ref(),
+                None => false
+            };
+            let sets_tracker = expr.fields.iter().any(|field| match field.member {
+                Member::Named(ref ident) => ident == "__tracker",
+                Member::Unnamed(_) => false
+            });
+            if constructs_self && expr.rest.is_none() && !sets_tracker {
+                expr.fields
+                    .push(parse_quote!(__tracker: ::std::default::Default::default()));
+            }
+        }
+    }
+
+    let ident = match *item.self_ty {
+        Type::Path(TypePath { ref path, .. }) => {
+            path.segments.last().map(|segment| segment.ident.clone())
+        }
+        _ => None
+    };
+    Filler(ident).visit_item_impl_mut(item);
+}
+
+/// Whether `ty` looks like `Arc<Tracker>`, with any path prefix
+fn is_arc_tracker(ty: &Type) -> bool {
+    let last_segment = |ty: &Type| match ty {
+        Type::Path(TypePath { path, .. }) => path.segments.last().cloned(),
+        _ => None
+    };
+    match last_segment(ty) {
+        Some(PathSegment {
+            ident,
+            arguments: PathArguments::AngleBracketed(arguments)
+        }) if ident == "Arc" => match arguments.args.first() {
+            Some(GenericArgument::Type(ty)) if arguments.args.len() == 1 => {
+                last_segment(ty).is_some_and(|segment| segment.ident == "Tracker")
+            }
+            _ => false
+        },
+        _ => false
+    }
+}
+
+/// Per-method overrides, set with a `#[track(...)]` attribute or a `#[track_with(...)]` attribute without a tracker
+/// path on a method inside a tracked impl block or module.
+#[derive(Debug, Default)]
+struct MethodOverrides {
+    /// Replaces the method name in the key. If it contains `::`, it replaces the whole key instead.
+    rename: Option<String>,
+    /// Don't track the method, set with `#[track(skip)]`. Takes precedence over `include` and `exclude`.
+    skip: bool,
+    /// Don't log the arguments, set with `#[track(no_args)]`
+    no_args: bool,
+    /// Don't log the return value, set with `#[track(no_return)]`
+    no_return: bool,
+    /// Log a clone of the receiver, set with `#[track(capture_self)]`
+    capture_self: bool,
+    /// Capture the arguments and return value differently, set with `#[track(capture = "...")]`
+    capture: Option<Capture>
+}
+
+const DEFAULT_KEY_FORMAT: &str = "{namespace}::{name}";
+
+/// The key a function or method called `name` is logged under, built with `format`. `rename` replaces `name`, or the
+/// whole key if it contains `::`.
+fn key(format: &str, namespace: Option<&str>, rename: Option<&str>, name: &str) -> String {
+    match (namespace, rename) {
+        (_, Some(rename)) if is_whole_key(Some(rename)) => rename.to_string(),
+        (Some(namespace), rename) => format
+            .replace("{namespace}", namespace)
+            .replace("{name}", rename.unwrap_or(name)),
+        (None, rename) => rename.unwrap_or(name).to_string()
+    }
+}
+
+/// Check that a `key_format` only contains the `{namespace}` and `{name}` placeholders, and contains `{name}`. This
+/// also makes it a valid format string with named arguments.
+fn validate_key_format(format: &str) -> Result<(), String> {
+    let mut rest = format;
+    while let Some(start) = rest.find(['{', '}']) {
+        let end = match rest[start..].find('}') {
+            Some(end) if rest[start..].starts_with('{') => start + end,
+            _ => return Err("Unmatched brace in key_format.".to_string())
+        };
+        match &rest[start + 1..end] {
+            "namespace" | "name" => {}
+            placeholder => {
+                return Err(format!(
+                    "Unknown placeholder '{{{}}}' in key_format. Expected '{{namespace}}' or '{{name}}'.",
+                    placeholder
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    if format.contains("{name}") {
+        Ok(())
+    } else {
+        Err("key_format must contain '{name}'.".to_string())
+    }
+}
+
+/// Whether `rename` replaces the whole key instead of only the function name
+fn is_whole_key(rename: Option<&str>) -> bool {
+    matches!(rename, Some(rename) if rename.contains("::"))
+}
+
+/// The expression for `key` in the generated code. With `namespace_module`, it's prefixed with the module path of the
+/// place it's expanded in.
+fn key_tokens(args: &Arguments, key: &str) -> TokenStream {
+    if args.namespace_module {
+        quote!(::std::concat!(::std::module_path!(), "::", #key))
+    } else {
+        quote!(#key)
+    }
+}
+
+/// Parse a `rename = "..."` or `capture = "..."` override
+fn parse_override(
+    overrides: &mut MethodOverrides,
+    path: &syn::Path,
+    lit: Lit,
+    attribute: &str
+) -> syn::Result<()> {
+    if path.is_ident("capture") {
+        overrides.capture = Some(Capture::parse(lit)?);
+    } else if path.is_ident("rename") {
+        overrides.rename = Some(str_option("rename", lit)?.value());
+    } else {
+        return Err(syn::Error::new_spanned(
+            path,
+            format!(
+                "Unknown option in method {} attribute. Expected rename or capture.",
+                attribute
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// Remove the `track` and `track_with` attributes of an item whose attributes are invalid, so the error is the only one
+/// reported
+fn strip_track_attrs(attrs: &mut Vec<Attribute>) {
+    attrs.retain(|attr| !attr.path.is_ident("track") && !attr.path.is_ident("track_with"));
+}
+
+/// Split the per-method overrides off the method's attributes.
+/// Returns `None` if the method has its own `track_with` attribute with a tracker path, since that attribute tracks the
+/// method instead.
+fn method_overrides(attrs: &[Attribute]) -> syn::Result<Option<(Vec<Attribute>, MethodOverrides)>> {
+    let mut overrides = MethodOverrides::default();
+    let mut remaining = Vec::new();
+    for attr in attrs {
+        if attr.path.is_ident("track") {
+            let list =
+                match attr.parse_meta()? {
+                    Meta::List(list) => list,
+                    meta => return Err(syn::Error::new_spanned(
+                        meta,
+                        "Invalid track attribute on method. Expected a list like `#[track(skip)]`."
+                    ))
+                };
+            for arg in list.nested {
+                match arg {
+                    NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("skip") => {
+                        overrides.skip = true
+                    }
+                    NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("no_args") => {
+                        overrides.no_args = true
+                    }
+                    NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("no_return") => {
+                        overrides.no_return = true
+                    }
+                    NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("capture_self") => {
+                        overrides.capture_self = true
+                    }
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) => {
+                        parse_override(&mut overrides, &path, lit, "track")?
+                    }
+                    arg => {
+                        return Err(syn::Error::new_spanned(
+                            arg,
+                            "Unexpected argument in track attribute. Expected skip, no_args, no_return, capture_self, \
+                             rename or capture."
+                        ))
+                    }
+                }
+            }
+            continue;
+        }
+        if !attr.path.is_ident("track_with") {
+            remaining.push(attr.clone());
+            continue;
+        }
+        let nested = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list.nested,
+            // Nested tracker fields and named trackers aren't valid meta
+            Err(_)
+                if matches!(
+                    attr.parse_args(),
+                    Ok(TrackWithArgs {
+                        fields: Some(_),
+                        ..
+                    }) | Ok(TrackWithArgs {
+                        tracker: Some(_),
+                        ..
+                    })
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(error) => return Err(error),
+            Ok(meta) => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "Invalid track_with attribute on method. Expected a tracker or options like `#[track_with(rename = \"...\")]`."
+                ))
+            }
+        };
+        match nested.first() {
+            Some(NestedMeta::Meta(Meta::Path(_)))
+            | Some(NestedMeta::Meta(Meta::List(_)))
+            | Some(NestedMeta::Lit(Lit::Int(_))) => return Ok(None),
+            Some(NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, .. })))
+                if path.is_ident("expr") =>
+            {
+                return Ok(None)
+            }
+            _ => {}
+        }
+        for arg in nested {
+            match arg {
+                NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) => {
+                    parse_override(&mut overrides, &path, lit, "track_with")?
+                }
+                arg => {
+                    return Err(syn::Error::new_spanned(
+                        arg,
+                        "Unexpected argument in method track_with attribute. Expected rename or capture."
+                    ))
+                }
+            }
+        }
+    }
+    Ok(Some((remaining, overrides)))
+}
+
+fn track_method(
+    args: &Arguments,
+    method: &ImplItemMethod,
+    namespace: &str,
+    tracked: &mut Vec<TrackedMethod>
+) -> TokenStream {
+    let (attrs, overrides) = match method_overrides(&method.attrs) {
+        Ok(Some(overrides)) => overrides,
+        Ok(None) => return quote!(#method),
+        Err(error) => {
+            let error = error.to_compile_error();
+            let mut method = method.clone();
+            strip_track_attrs(&mut method.attrs);
+            let _ = param_overrides(&mut method.sig.inputs);
+            return quote!(#error #method);
+        }
+    };
+    let mut method = ImplItemMethod {
+        attrs,
+        ..method.clone()
+    };
+    let (mut skipped, projected) = match param_overrides(&mut method.sig.inputs) {
+        Ok(params) => params,
+        Err(error) => {
+            let error = error.to_compile_error();
+            return quote!(#error #method);
+        }
+    };
+    let method = &method;
+
+    let method_name = method.sig.ident.to_string();
+    if overrides.skip || !args.tracks(&method_name) {
         return quote!(#method);
     }
+    if let Some(constness) = method.sig.constness {
+        let error = const_error(constness.span, "exclude the method");
+        return quote!(#error #method);
+    }
+    let name = key(
+        &args.key_format,
+        Some(namespace),
+        overrides.rename.as_deref(),
+        &method_name
+    );
 
-    let inputs_cloned = cloned_inputs(&sig.inputs);
-    let result_cloned = quote_spanned! {
-        sig.output.span() =>
-        returned.to_owned()
+    let ImplItemMethod {
+        attrs,
+        vis,
+        defaultness,
+        sig,
+        block
+    } = method;
+
+    let receiver = sig.inputs.iter().find(|arg| is_receiver(arg));
+
+    // Associated functions taking a tracker, like constructors, log to the tracker they're given. It's copied before
+    // the body runs, since the body usually moves it into `Self`.
+    let tracker_param = match receiver {
+        None if args.include_receiver && args.static_tracker.is_none() => {
+            tracker_param(&sig.inputs)
+        }
+        _ => None
+    };
+    let (copy_tracker, tracker_param) = match tracker_param {
+        Some((i, ident)) => {
+            skipped.push(i);
+            (
+                quote!(let __racetrack_param_tracker = ::std::clone::Clone::clone(&#ident);),
+                Some(quote!(__racetrack_param_tracker))
+            )
+        }
+        None => (quote!(), None)
     };
-    let statements = &block.stmts;
     let tracker_path = &args.tracker_path;
-    let tracker_path = if args.include_receiver {
-        quote!(self.#tracker_path)
-    } else {
+    let (tracker_path, include_receiver) = match (&args.static_tracker, &tracker_param, receiver) {
+        (Some(static_tracker), _, None) if args.include_receiver => (static_tracker, false),
+        (None, Some(tracker_param), None) => (tracker_param, false),
+        // Skip static methods since the tracker path won't be valid
+        (None, None, None) if args.include_receiver => return quote!(#method),
+        _ => (tracker_path, args.include_receiver)
+    };
+    // Expressions like `self.deps().tracker()` already say how to get to the tracker from `self`
+    let tracker_path = if !include_receiver || mentions(tracker_path.clone(), "self") {
         tracker_path.clone()
+    } else {
+        quote!(self.#tracker_path)
+    };
+    let args = &Arguments {
+        capture_args: args.capture_args && !overrides.no_args,
+        // Constructors taking a tracker rarely return something that can be cloned
+        capture_return: args.capture_return
+            && !overrides.no_return
+            && !(tracker_param.is_some() && returns_self(&sig.output)),
+        capture_self: args.capture_self || overrides.capture_self,
+        capture: overrides.capture.unwrap_or(args.capture),
+        namespace_module: args.namespace_module && !is_whole_key(overrides.rename.as_deref()),
+        // A tracker parameter is always a plain tracker
+        optional: args.optional && tracker_param.is_none(),
+        weak: args.weak && tracker_param.is_none(),
+        skipped_params: skipped,
+        projected_params: projected,
+        moves_receiver: boxed_future_output(&sig.output).is_some(),
+        ..args.clone()
+    };
+    // Methods already desugared by `#[async_trait]` are tracked like the async method they were, so the awaited value
+    // is captured instead of the boxed future
+    let (mut sig, block) = match boxed_future_output(&sig.output) {
+        Some(output) => {
+            let mut sig = sig.clone();
+            sig.asyncness = Some(Default::default());
+            sig.output = ReturnType::Type(Default::default(), Box::new(output));
+            (sig, parse_quote!({ (#block).await }))
+        }
+        None => (sig.clone(), block.clone())
     };
-
-    let body = quote_spanned! {
-        block.span() =>
-        let args = (#(#inputs_cloned),*);
-        let returned = {
-            #(#statements)*
+    tracked.push(TrackedMethod {
+        ident: sig.ident.clone(),
+        key: key_tokens(args, &name),
+        ty: typed_call_key(args, &sig)
+    });
+    let depth_key = key_tokens(args, &name);
+    // The key of an instance is formatted once up front, since the body may move `self`
+    let (instance_key, name) = match args.namespace_field {
+        Some(ref field)
+            if include_receiver
+                && receiver.is_some()
+                && !is_whole_key(overrides.rename.as_deref()) =>
+        {
+            let namespace = key_tokens(args, namespace);
+            let method_name = overrides.rename.as_deref().unwrap_or(&method_name);
+            let format = &args.key_format;
+            let namespace = if format.contains("{namespace}") {
+                quote!(namespace = ::std::format_args!("{}#{}", #namespace, self.#field),)
+            } else {
+                quote!()
+            };
+            (
+                quote! {
+                    let __racetrack_key = ::std::format!(#format, #namespace name = #method_name);
+                },
+                quote!(__racetrack_key.as_str())
+            )
+        }
+        _ => (quote!(), key_tokens(args, &name))
+    };
+    let body = tracked_body(args, &tracker_path, &name, &depth_key, &mut sig, &block);
+    let (sig, body) = if sig.asyncness.is_some() && method.sig.asyncness.is_none() {
+        let sig = Signature {
+            inputs: sig.inputs,
+            ..method.sig.clone()
         };
-        #tracker_path.log_call(#name, ::racetrack::CallInfo {
-            arguments: Some(Box::new(args)),
-            returned: Some(Box::new(#result_cloned))
-        });
-        returned
+        (sig, quote!(::std::boxed::Box::pin(async move { #body })))
+    } else {
+        (sig, body)
     };
 
     let attrs = spanned_vec(attrs);
     let vis = spanned(vis);
     let defaultness = spanned_opt(defaultness.as_ref());
-    let sig = spanned(sig);
+    let sig = spanned(&sig);
 
     let tokens = quote! {
         #(#attrs)*
         #vis #defaultness #sig {
+            #instance_key
+            #copy_tracker
             #body
         }
     };
 
-    tokens
+    args.cfg_gated(tokens, method)
 }
 
-fn track_function(args: &Arguments, fun: ItemFn) -> TokenStream {
+fn track_function(args: &Arguments, mut fun: ItemFn) -> TokenStream {
     //println!("{:#?}", fun);
-    let attrs = fun.attrs;
-    let visibility = fun.vis;
-    let signature = fun.sig;
-    let name = if let Some(ref namespace) = args.namespace {
-        format!("{}::{}", namespace, signature.ident.to_string())
-    } else {
-        signature.ident.to_string()
-    };
-    let arg_idents = cloned_inputs(&signature.inputs);
-    let returned_clone = quote_spanned! {
-        signature.output.span() =>
-        returned.to_owned()
+    let (skipped, projected) = match param_overrides(&mut fun.sig.inputs) {
+        Ok(params) => params,
+        Err(error) => {
+            let error = error.to_compile_error();
+            return quote!(#error #fun);
+        }
     };
-    let block = &fun.block;
-    let statements = &fun.block.stmts;
-    let tracker_path = &args.tracker_path;
-    let body = quote_spanned! {
-        block.span() =>
-            let args = (#(#arg_idents),*);
-            let returned = {
-                #(#statements)*
-            };
-            #tracker_path.log_call(#name, ::racetrack::CallInfo {
-                arguments: Some(Box::new(args)),
-                returned: Some(Box::new(#returned_clone))
-            });
-            returned
+    if fun.sig.constness.is_some() {
+        let error = const_error(Span::call_site(), "remove the attribute");
+        return quote!(#error #fun);
+    }
+    let args = &Arguments {
+        namespace_module: args.namespace_module
+            && args.namespace.is_none()
+            && !is_whole_key(args.name.as_deref()),
+        skipped_params: skipped,
+        projected_params: projected,
+        ..args.clone()
     };
+    let original = fun.clone();
+    let attrs = fun.attrs;
+    let visibility = fun.vis;
+    let mut signature = fun.sig;
+    let name = key(
+        &args.key_format,
+        args.namespace.as_deref(),
+        args.name.as_deref(),
+        &signature.ident.to_string()
+    );
+    let key = key_tokens(args, &name);
+    let body = tracked_body(
+        args,
+        &args.tracker_path,
+        &key,
+        &key,
+        &mut signature,
+        &fun.block
+    );
 
     let tokens = quote! {
         #(#attrs)*
@@ -334,10 +2387,281 @@ fn track_function(args: &Arguments, fun: ItemFn) -> TokenStream {
     };
 
     //println!("{}", tokens);
-    tokens
+    args.cfg_gated(tokens, original)
+}
+
+/// The error for a tracked `const fn`, which can't log its calls in a const context
+fn const_error(span: Span, alternative: &str) -> TokenStream {
+    let message = format!(
+        "const fns can't be tracked, since calls can't be logged in a const context. Remove `const` or {}.",
+        alternative
+    );
+    quote_spanned! {
+        span =>
+        compile_error!(#message);
+    }
+}
+
+/// Generate the body of a tracked function or method, logging each call under `name`.
+/// Recursion depths are counted under `depth_key`, which has to be `'static` so entering the guard doesn't allocate.
+/// Destructured parameters in `sig` are rebound to plain identifiers so they can be captured.
+fn tracked_body(
+    args: &Arguments,
+    tracker_path: &TokenStream,
+    name: &TokenStream,
+    depth_key: &TokenStream,
+    sig: &mut Signature,
+    block: &Block
+) -> TokenStream {
+    if let Some(ref keys) = args.debug_keys {
+        keys.borrow_mut()
+            .push((sig.ident.to_string(), name.to_string()));
+    }
+    let type_params: Vec<_> = sig
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let generic = |tokens: TokenStream| {
+        type_params
+            .iter()
+            .any(|param| mentions(tokens.clone(), &param.to_string()))
+    };
+    // Without `capture_generics`, values of a generic type are left out like `#[track_skip]` parameters
+    let args = &if args.capture_generics {
+        args.clone()
+    } else {
+        let generic_params = sig
+            .inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, arg)| match arg {
+                FnArg::Typed(PatType { ty, .. }) if generic(quote!(#ty)) => Some(i),
+                _ => None
+            });
+        Arguments {
+            skipped_params: args
+                .skipped_params
+                .iter()
+                .copied()
+                .chain(generic_params)
+                .collect(),
+            capture_return: args.capture_return
+                && !matches!(sig.output, ReturnType::Type(_, ref ty) if generic(quote!(#ty))),
+            ..args.clone()
+        }
+    };
+    // With `key_generics`, each instantiation is logged under its own key, like `Store::insert::<my_crate::User>`
+    let (generic_key, name) = if args.key_generics && !type_params.is_empty() {
+        (
+            quote! {
+                let __racetrack_generic_key = ::std::format!(
+                    "{}::<{}>",
+                    #name,
+                    [#(::std::any::type_name::<#type_params>()),*].join(", ")
+                );
+            },
+            quote!(__racetrack_generic_key.as_str())
+        )
+    } else {
+        (quote!(), name.clone())
+    };
+    let name = &name;
+    // Optional and weak trackers only capture anything if they're available, so the captures are wrapped in an `Option`
+    let conditional = args.optional || args.weak;
+    let optional = |tokens: TokenStream| {
+        if conditional {
+            quote!(if __racetrack_tracker.is_some() { Some(#tokens) } else { None })
+        } else {
+            tokens
+        }
+    };
+    let boxed = |value: TokenStream| {
+        if conditional {
+            quote!(#value.map(|value| Box::new(value) as Box<dyn ::std::any::Any + Send + Sync>))
+        } else {
+            quote!(Some(Box::new(#value)))
+        }
+    };
+    let (capture_args, rebinds, converted) = if args.capture_args {
+        let (inputs_cloned, conversions, rebinds) = cloned_inputs(&mut sig.inputs, args);
+        let captured = optional(args.capture.captured(&inputs_cloned));
+        let converted = !conversions.is_empty();
+        (
+            quote! {
+                #(#conversions)*
+                let __racetrack_args = #captured;
+            },
+            rebinds,
+            converted
+        )
+    } else {
+        (quote!(), Vec::new(), false)
+    };
+    let arguments_cloned = if args.capture_args {
+        boxed(quote!(__racetrack_args))
+    } else {
+        captured_arguments(args)
+    };
+    let result_cloned = captured_return(args, &sig.output);
+    let (capture_receiver, receiver) = if args.capture_self && sig.receiver().is_some() {
+        let receiver = optional(quote!(<Self as ::std::clone::Clone>::clone(&self)));
+        (
+            quote!(let __racetrack_receiver = #receiver;),
+            boxed(quote!(__racetrack_receiver))
+        )
+    } else {
+        (quote!(), quote!(None))
+    };
+    let statements = &block.stmts;
+    // `into` on a converted `impl Into<T>` parameter is now a `T` to `T` conversion
+    let allow_conversion = if converted {
+        quote!(#[allow(clippy::useless_conversion)])
+    } else {
+        quote!()
+    };
+    // Typed receivers like `self: Arc<Self>` or `Pin<&mut Self>` are owned by the method like `self`
+    let moves_receiver = match sig.receiver() {
+        Some(FnArg::Receiver(receiver)) => args.moves_receiver || receiver.reference.is_none(),
+        Some(FnArg::Typed(_)) => true,
+        None => false
+    };
+    // `return` and `?` would leave the function before the call is logged, so the body gets its own scope to return
+    // from. This is only done when needed since it moves everything the body uses, and changes the location reported
+    // by panics in `#[track_caller]` functions. The closure has to be `FnOnce` so the body can return borrows of `&mut`
+    // parameters.
+    let exits_early = exits_early(statements);
+    let body = if !exits_early {
+        quote!({ #(#statements)* })
+    } else if sig.asyncness.is_some() {
+        quote!(async move { #(#statements)* }.await)
+    } else {
+        quote! {{
+            fn __racetrack_once<R>(body: impl FnOnce() -> R) -> R {
+                body()
+            }
+            __racetrack_once(move || { #(#statements)* })
+        }}
+    };
+    // The type of the return value is spelled out so `?` knows what to convert errors into, and bodies that never
+    // return still have a type to capture
+    let returned_type = match &sig.output {
+        ReturnType::Default => quote!(: ()),
+        ReturnType::Type(_, ty)
+            if !matches!(**ty, Type::Never(_)) && !mentions(quote!(#ty), "impl") =>
+        {
+            quote!(: #ty)
+        }
+        ReturnType::Type(..) => quote!()
+    };
+
+    let acquire = match (args.optional, args.weak) {
+        (true, true) => quote!(#tracker_path.as_ref().and_then(::std::sync::Weak::upgrade)),
+        (false, true) => quote!(#tracker_path.upgrade()),
+        _ => quote!(#tracker_path.clone())
+    };
+    let (enter, depth, log_with) = if conditional {
+        (
+            quote! {
+                let __racetrack_tracker = #acquire;
+                let __racetrack_depth = __racetrack_tracker
+                    .as_ref()
+                    .map(|_| ::racetrack::depth::DepthGuard::enter(#depth_key));
+            },
+            quote!(__racetrack_depth
+                .as_ref()
+                .map_or(0, ::racetrack::depth::DepthGuard::depth)),
+            quote!(if let Some(__racetrack_tracker) = &__racetrack_tracker)
+        )
+    } else if exits_early || moves_receiver {
+        // The body may move the tracker's owner, so the tracker is cloned before it runs
+        (
+            quote! {
+                let __racetrack_tracker = #tracker_path.clone();
+                let __racetrack_depth = ::racetrack::depth::DepthGuard::enter(#depth_key);
+            },
+            quote!(__racetrack_depth.depth()),
+            quote!()
+        )
+    } else {
+        (
+            quote!(let __racetrack_depth = ::racetrack::depth::DepthGuard::enter(#depth_key);),
+            quote!(__racetrack_depth.depth()),
+            quote!()
+        )
+    };
+    let logger = if conditional || exits_early || moves_receiver {
+        quote!(__racetrack_tracker)
+    } else {
+        tracker_path.clone()
+    };
+    let log_call = |returned: TokenStream| {
+        quote! {
+            #logger.log_call(#name, {
+                let mut __racetrack_call = ::racetrack::CallInfo::new();
+                __racetrack_call.arguments = #arguments_cloned;
+                __racetrack_call.returned = #returned;
+                __racetrack_call.receiver = #receiver;
+                __racetrack_call.depth = #depth;
+                __racetrack_call.location = Some(__racetrack_location);
+                __racetrack_call
+            })
+        }
+    };
+    let (log_entry, log_exit) = if args.log_before {
+        // The call is logged before the body runs so calls that never return show up, and the return value is filled
+        // in afterwards
+        let log_entry = log_call(quote!(None));
+        let otherwise = if conditional {
+            quote!(else { None })
+        } else {
+            quote!()
+        };
+        (
+            quote! {
+                #[allow(clippy::unit_arg)]
+                let __racetrack_sequence = #log_with { #log_entry } #otherwise;
+            },
+            quote! {
+                #log_with {
+                    if let Some(__racetrack_sequence) = __racetrack_sequence {
+                        #logger.log_return(#name, __racetrack_sequence, #result_cloned);
+                    }
+                }
+            }
+        )
+    } else {
+        let log_exit = log_call(result_cloned);
+        (
+            quote!(),
+            quote! {
+                #log_with {
+                    #[allow(clippy::unit_arg)]
+                    #log_exit;
+                }
+            }
+        )
+    };
+
+    quote_spanned! {
+        block.span() =>
+        let __racetrack_location = ::std::panic::Location::caller();
+        #generic_key
+        #enter
+        #capture_receiver
+        #capture_args
+        #(#rebinds)*
+        #log_entry
+        #[allow(clippy::let_unit_value)]
+        #allow_conversion
+        let __racetrack_returned #returned_type = #body;
+        #log_exit
+        __racetrack_returned
+    }
 }
 
 fn track_closure(args: &Arguments, closure: ExprClosure, name: String) -> TokenStream {
+    let original = closure.clone();
     let ExprClosure {
         attrs,
         asyncness,
@@ -348,47 +2672,268 @@ fn track_closure(args: &Arguments, closure: ExprClosure, name: String) -> TokenS
         body,
         ..
     } = closure;
+    if let Some(ref keys) = args.debug_keys {
+        keys.borrow_mut()
+            .push((name.clone(), format!("{:?}", name)));
+    }
     let tracker_path = &args.tracker_path;
     let attrs = spanned_vec(&attrs);
     let asyncness = spanned_opt(asyncness);
     let movability = spanned_opt(movability);
     let capture = spanned_opt(capture);
-    let cloned_inputs = cloned_inputs_pat(&inputs);
-    let cloned_return = quote_spanned! {
-        output.span() =>
-        returned.to_owned()
+    let (outer_inputs, idents, captured) = closure_inputs(&inputs);
+    let capture_args = if args.capture_args {
+        let captured: Vec<_> = captured
+            .iter()
+            .map(|ident| args.capture.capture(ident))
+            .collect();
+        let captured = args.capture.captured(&captured);
+        quote!(let __racetrack_args = #captured;)
+    } else {
+        quote!()
+    };
+    let arguments_cloned = captured_arguments(args);
+    let cloned_return = captured_return(args, &output);
+    let inputs = spanned_vec(&inputs.into_iter().collect::<Vec<_>>());
+    let outer_inputs = spanned_vec(&outer_inputs);
+    // Async closures return a future, so the outer closure has to be async too and await the inner one
+    let call = if asyncness.is_empty() {
+        quote!(__racetrack_inner(#(#idents),*))
+    } else {
+        quote!(__racetrack_inner(#(#idents),*).await)
     };
-    let inputs: Vec<_> = inputs.iter().map(|input| {
-        quote_spanned! {
-            input.span() =>
-            #input
-        }
-    }).collect();
-    let arguments = &inputs;
     let body_outer = quote_spanned! {
         body.span() =>
-        let args = (#(#cloned_inputs),*);
-        let returned = inner(#(#arguments)*);
-        tracker.log_call(#name, ::racetrack::CallInfo {
-            arguments: Some(Box::new(args)),
-            returned: Some(Box::new(#cloned_return))
+        let __racetrack_depth = ::racetrack::depth::DepthGuard::enter(#name);
+        #capture_args
+        #[allow(clippy::let_unit_value)]
+        let __racetrack_returned = #call;
+        #[allow(clippy::unit_arg)]
+        __racetrack_tracker.log_call(#name, {
+            let mut __racetrack_call = ::racetrack::CallInfo::new();
+            __racetrack_call.arguments = #arguments_cloned;
+            __racetrack_call.returned = #cloned_return;
+            __racetrack_call.depth = __racetrack_depth.depth();
+            __racetrack_call
         });
-        returned
+        __racetrack_returned
     };
 
     let tokens = quote! {
         {
-            let inner = #(#attrs)*
-            #asyncness #movability #capture |#(#arguments)*| #output {
+            // `mut` so `FnMut` closures can be called, which makes the wrapper `FnMut` as well
+            #[allow(unused_mut)]
+            let mut __racetrack_inner = #(#attrs)*
+            #asyncness #movability #capture |#(#inputs),*| #output {
                 #body
             };
-            let tracker = #tracker_path.clone();
-            #asyncness #movability move |#(#arguments)*| #output {
+            let __racetrack_tracker = #tracker_path.clone();
+            #asyncness #movability move |#(#outer_inputs),*| #output {
                 #body_outer
             }
         }
     };
-    tokens
+    if args.cfg.is_some() {
+        // Attributes on expressions aren't stable, so the closure is selected with attributes on statements instead
+        let closure = args.cfg_gated(
+            quote!(let __racetrack_closure = #tokens;),
+            quote!(let __racetrack_closure = #original;)
+        );
+        quote! {
+            {
+                #closure
+                __racetrack_closure
+            }
+        }
+    } else {
+        tokens
+    }
+}
+
+/// The value logged as the arguments, `None` if they aren't captured
+fn captured_arguments(args: &Arguments) -> TokenStream {
+    if args.capture_args {
+        quote!(Some(Box::new(__racetrack_args)))
+    } else {
+        quote!(None)
+    }
+}
+
+/// The value logged as the return value, `None` if it isn't captured
+fn captured_return(args: &Arguments, output: &ReturnType) -> TokenStream {
+    if captures_return(args, output) {
+        let returned = args
+            .capture
+            .capture(&Ident::new("__racetrack_returned", output.span()));
+        let returned = args.capture.captured(&[returned]);
+        quote!(Some(Box::new(#returned)))
+    } else {
+        quote!(None)
+    }
+}
+
+/// Whether the return value is captured. Opaque `impl Trait` return types can't be, so they're always left out.
+fn captures_return(args: &Arguments, output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => args.capture_return && !mentions(quote!(#ty), "impl"),
+        ReturnType::Default => args.capture_return
+    }
+}
+
+/// A tracked method of an impl block, kept to generate the structs of `keys` and `typed_assertions`
+struct TrackedMethod {
+    ident: Ident,
+    /// The key the method is logged under
+    key: TokenStream,
+    /// The `CallKey` type of the method, or `None` if its captured types can't be named
+    ty: Option<TokenStream>
+}
+
+/// The name of the struct generated next to an impl block for the `option` named `name`. `Some(None)` names it after
+/// the type with `suffix`. Returns `None` if the option isn't set and an error if the struct can't be generated, either
+/// because it can't be named or because it's `unsupported` for the impl block.
+fn companion_name(
+    option: &Option<Option<Ident>>,
+    self_ty: &Type,
+    suffix: &str,
+    name: &str,
+    unsupported: Option<&str>
+) -> Option<Result<Ident, TokenStream>> {
+    let ident = match option.as_ref()? {
+        Some(ident) => ident.clone(),
+        None => match self_ty {
+            Type::Path(TypePath { path, .. }) => {
+                let segment = path.segments.last()?;
+                Ident::new(
+                    &format!("{}{}", segment.ident, suffix),
+                    segment.ident.span()
+                )
+            }
+            _ => {
+                let error = format!(
+                    "The {} struct can't be named after this type. Name it with `{} = \"...\"` instead.",
+                    name, name
+                );
+                return Some(Err(quote_spanned! {
+                    self_ty.span() =>
+                    compile_error!(#error);
+                }));
+            }
+        }
+    };
+    if let Some(unsupported) = unsupported {
+        let error = format!(
+            "The {} struct can't be generated for {}.",
+            name, unsupported
+        );
+        return Some(Err(quote_spanned! {
+            ident.span() =>
+            compile_error!(#error);
+        }));
+    }
+    Some(Ok(ident))
+}
+
+/// The constant for `method` in the struct generated by `keys`. This is a typed `CallKey` if the captured types can be
+/// named, and the plain key otherwise.
+fn call_key(vis: &Visibility, method: &TrackedMethod) -> TokenStream {
+    let TrackedMethod { ident, key, ty } = method;
+    match ty {
+        Some(ty) => quote! {
+            #[allow(non_upper_case_globals)]
+            #vis const #ident: #ty = ::racetrack::key::CallKey::new(#key);
+        },
+        None => quote! {
+            #[allow(non_upper_case_globals)]
+            #vis const #ident: &'static str = #key;
+        }
+    }
+}
+
+/// The method starting an assertion on `method` in the struct generated by `typed_assertions`. The assertion only
+/// accepts the captured types if they can be named, like one started with a typed `CallKey`.
+fn typed_assertion(vis: &Visibility, method: &TrackedMethod) -> TokenStream {
+    let TrackedMethod { ident, key, ty } = method;
+    match ty {
+        Some(ty) => quote! {
+            #vis fn #ident(&self) -> ::racetrack::tracker::Assertion<
+                <#ty as ::racetrack::key::IntoKey>::Args,
+                <#ty as ::racetrack::key::IntoKey>::Ret
+            > {
+                self.0.assert_that(<#ty>::new(#key))
+            }
+        },
+        None => quote! {
+            #vis fn #ident(&self) -> ::racetrack::tracker::Assertion {
+                self.0.assert_that(#key)
+            }
+        }
+    }
+}
+
+/// The `CallKey` type for the method with the signature `sig`, or `None` if the captured types can't be named
+fn typed_call_key(args: &Arguments, sig: &Signature) -> Option<TokenStream> {
+    // The types of generic parameters and captured fields aren't known to the macro
+    if !sig.generics.params.is_empty()
+        || (!args.capture_fields.is_empty() && sig.receiver().is_some())
+    {
+        return None;
+    }
+    let mut arguments = Vec::new();
+    for (i, arg) in sig.inputs.iter().enumerate() {
+        match arg {
+            _ if args.skipped_params.contains(&i) => {}
+            // The type of a projection can't be known without type checking
+            _ if args.projected_params.iter().any(|(pos, _)| *pos == i) => return None,
+            FnArg::Typed(PatType { pat, .. }) if matches!(**pat, Pat::Wild(_)) => {}
+            arg if is_receiver(arg) => {}
+            FnArg::Typed(PatType { ty, .. }) if matches!(**ty, Type::ImplTrait(_)) => {
+                if let Some(target) = into_target(ty) {
+                    arguments.push(args.capture.captured_type(target)?);
+                }
+            }
+            FnArg::Typed(PatType { ty, .. }) => arguments.push(args.capture.captured_type(ty)?),
+            FnArg::Receiver(_) => {}
+        }
+    }
+    // Single arguments aren't captured as tuples, and debug captures are always strings
+    let arguments = if !args.capture_args {
+        quote!(())
+    } else if args.capture == Capture::Debug {
+        quote!(::std::string::String)
+    } else if arguments.len() == 1 {
+        arguments.remove(0)
+    } else {
+        quote!((#(#arguments),*))
+    };
+    let returned = match sig.output {
+        _ if !captures_return(args, &sig.output) => quote!(()),
+        _ if args.capture == Capture::Debug => quote!(::std::string::String),
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ref ty) => args.capture.captured_type(ty)?
+    };
+    Some(quote!(::racetrack::key::CallKey<#arguments, #returned>))
+}
+
+/// Print tokens the way they'd usually be written, e.g. `crate::cache::Cache<String>` instead of
+/// `crate :: cache :: Cache < String >`. Whitespace is only kept between two identifier characters, like in `dyn Trait`.
+fn normalized_name(tokens: impl ToTokens) -> String {
+    let printed = tokens.to_token_stream().to_string();
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut name = String::with_capacity(printed.len());
+    let mut chars = printed.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            let after_ident = name.chars().last().is_some_and(is_ident_char);
+            let before_ident = chars.peek().is_some_and(|next| is_ident_char(*next));
+            if after_ident && before_ident {
+                name.push(' ');
+            }
+        } else {
+            name.push(c);
+        }
+    }
+    name
 }
 
 fn spanned(item: impl ToTokens + Spanned) -> TokenStream {
@@ -398,7 +2943,7 @@ fn spanned(item: impl ToTokens + Spanned) -> TokenStream {
     }
 }
 
-fn spanned_vec<T: ToTokens + Spanned>(item: &Vec<T>) -> Vec<TokenStream> {
+fn spanned_vec<T: ToTokens + Spanned>(item: &[T]) -> Vec<TokenStream> {
     item.iter()
         .map(|item| {
             quote_spanned! {
@@ -419,54 +2964,420 @@ fn spanned_opt<T: ToTokens + Spanned>(item: Option<T>) -> TokenStream {
     .unwrap_or_else(|| quote!())
 }
 
-fn cloned_inputs<'a>(inputs: &Punctuated<FnArg, Token![,]>) -> Vec<TokenStream> {
-    inputs
-        .iter()
-        .filter_map(|arg| {
-            if let FnArg::Typed(PatType { ref pat, .. }) = arg {
-                Some(pat)
-            } else {
-                None
+/// Clone the inputs of a function for logging.
+/// Parameters with destructuring patterns are replaced by a generated identifier so the whole value can be cloned.
+/// The statements returned alongside the clones destructure them again and must run before the original body.
+/// Wildcard (`_`) parameters and the parameters marked `#[track_skip]` aren't captured, while parameters with a
+/// `capture_with` expression are captured as its result.
+fn cloned_inputs(
+    inputs: &mut Punctuated<FnArg, Token![,]>,
+    args: &Arguments
+) -> (Vec<TokenStream>, Vec<TokenStream>, Vec<TokenStream>) {
+    let mut cloned = Vec::new();
+    let mut conversions = Vec::new();
+    let mut rebinds = Vec::new();
+    let typed = inputs
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(i, arg)| match arg {
+            FnArg::Typed(PatType {
+                ref mut pat,
+                ref ty,
+                ..
+            }) if !args.skipped_params.contains(&i) && !is_self(pat) => Some((i, pat, ty)),
+            _ => None
+        });
+    for (i, pat, ty) in typed {
+        if let Some((_, projection)) = args.projected_params.iter().find(|(pos, _)| *pos == i) {
+            cloned.push(match args.capture {
+                Capture::Debug => quote!(&(#projection)),
+                _ => quote!((#projection))
+            });
+            continue;
+        }
+        let ident = match **pat {
+            // `impl Into<T>` is converted to `T` up front, which the body can still use like the original value.
+            // Other `impl Trait` types can't be captured.
+            Pat::Ident(PatIdent {
+                ref ident,
+                ref mutability,
+                ..
+            }) if matches!(**ty, Type::ImplTrait(_)) => match into_target(ty) {
+                Some(target) => {
+                    conversions.push(quote! {
+                        let #mutability #ident: #target = ::std::convert::Into::into(#ident);
+                    });
+                    ident.clone()
+                }
+                None => continue
+            },
+            Pat::Ident(PatIdent { ref ident, .. }) => ident.clone(),
+            Pat::Wild(_) => continue,
+            ref destructured => {
+                let ident = Ident::new(&format!("__racetrack_arg{}", i), destructured.span());
+                rebinds.push(quote_spanned! {
+                    destructured.span() =>
+                    let #destructured = #ident;
+                });
+                **pat = Pat::Ident(PatIdent {
+                    attrs: Vec::new(),
+                    by_ref: None,
+                    mutability: None,
+                    ident: ident.clone(),
+                    subpat: None
+                });
+                ident
             }
-        })
-        .filter_map(|arg| {
-            if let &Pat::Ident(PatIdent { ref ident, .. }) = &**arg {
-                Some(ident)
-            } else {
-                None
+        };
+        cloned.push(args.capture.capture(&ident));
+    }
+    if inputs.iter().any(is_receiver) {
+        for field in &args.capture_fields {
+            cloned.push(
+                args.capture
+                    .capture(&quote_spanned!(field.span() => self.#field))
+            );
+        }
+    }
+    (cloned, conversions, rebinds)
+}
+
+/// The `T` of a parameter type that's only `impl Into<T>`
+fn into_target(ty: &Type) -> Option<&Type> {
+    let bounds = match ty {
+        Type::ImplTrait(TypeImplTrait { bounds, .. }) => bounds,
+        _ => return None
+    };
+    let mut traits = bounds.iter().filter_map(|bound| match bound {
+        TypeParamBound::Trait(bound) => Some(bound),
+        TypeParamBound::Lifetime(_) => None
+    });
+    let segment = match (traits.next(), traits.next()) {
+        (Some(bound), None) => bound.path.segments.last()?,
+        _ => return None
+    };
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments)
+            if segment.ident == "Into" && arguments.args.len() == 1 =>
+        {
+            match arguments.args.first() {
+                Some(GenericArgument::Type(target)) => Some(target),
+                _ => None
             }
+        }
+        _ => None
+    }
+}
+
+/// The name of the variable bound by `pat`, without `mut` or a type annotation
+fn binding_name(pat: &Pat) -> String {
+    match pat {
+        Pat::Ident(PatIdent { ident, .. }) => ident.to_string(),
+        Pat::Type(PatType { pat, .. }) => binding_name(pat),
+        pat => quote!(#pat).to_string()
+    }
+}
+
+/// Whether the identifier `name` appears anywhere in `tokens`
+fn mentions(tokens: TokenStream, name: &str) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Group(group) => mentions(group.stream(), name),
+        TokenTree::Ident(ident) => ident == name,
+        _ => false
+    })
+}
+
+/// Whether `statements` can leave the function early through `return` or `?`. Closures, async blocks and nested items
+/// are skipped, since they're returned from on their own.
+fn exits_early(statements: &[Stmt]) -> bool {
+    struct EarlyExit(bool);
+
+    impl<'ast> Visit<'ast> for EarlyExit {
+        fn visit_expr_return(&mut self, _: &'ast ExprReturn) {
+            self.0 = true;
+        }
+
+        fn visit_expr_try(&mut self, _: &'ast ExprTry) {
+            self.0 = true;
+        }
+
+        fn visit_expr_closure(&mut self, _: &'ast ExprClosure) {}
+
+        fn visit_expr_async(&mut self, _: &'ast ExprAsync) {}
+
+        fn visit_item(&mut self, _: &'ast Item) {}
+
+        fn visit_type_param_bound(&mut self, _: &'ast TypeParamBound) {}
+
+        // The arguments of macros aren't parsed, so they're scanned for the tokens instead
+        fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+            self.0 |= mentions_exit(mac.tokens.clone());
+        }
+    }
+
+    /// Whether `tokens` contain `return` or `?` anywhere
+    fn mentions_exit(tokens: TokenStream) -> bool {
+        tokens.into_iter().any(|token| match token {
+            TokenTree::Group(group) => mentions_exit(group.stream()),
+            TokenTree::Ident(ident) => ident == "return",
+            TokenTree::Punct(punct) => punct.as_char() == '?',
+            _ => false
         })
-        .map(|ident| {
-            quote_spanned! {
-                ident.span() =>
-                #ident.to_owned()
+    }
+
+    let mut visitor = EarlyExit(false);
+    for statement in statements {
+        visitor.visit_stmt(statement);
+    }
+    visitor.0
+}
+
+/// The position and name of the first parameter that's a tracker, i.e. an `Arc<Tracker>`, `&Tracker` or
+/// `&Arc<Tracker>`. Paths to the types are matched by their last segment.
+fn tracker_param(inputs: &Punctuated<FnArg, Token![,]>) -> Option<(usize, Ident)> {
+    fn is_named(ty: &Type, name: &str) -> Option<PathArguments> {
+        match ty {
+            Type::Path(TypePath { qself: None, path }) => path
+                .segments
+                .last()
+                .filter(|segment| segment.ident == name)
+                .map(|segment| segment.arguments.clone()),
+            _ => None
+        }
+    }
+    fn is_arc_tracker(ty: &Type) -> bool {
+        match is_named(ty, "Arc") {
+            Some(PathArguments::AngleBracketed(arguments)) => matches!(
+                arguments.args.first(),
+                Some(GenericArgument::Type(ty)) if arguments.args.len() == 1 && is_named(ty, "Tracker").is_some()
+            ),
+            _ => false
+        }
+    }
+    inputs.iter().enumerate().find_map(|(i, arg)| match arg {
+        FnArg::Typed(PatType { pat, ty, .. }) => {
+            let is_tracker = match &**ty {
+                Type::Reference(reference) => {
+                    is_named(&reference.elem, "Tracker").is_some()
+                        || is_arc_tracker(&reference.elem)
+                }
+                ty => is_arc_tracker(ty)
+            };
+            match &**pat {
+                Pat::Ident(PatIdent {
+                    ident,
+                    by_ref: None,
+                    subpat: None,
+                    ..
+                }) if is_tracker => Some((i, ident.clone())),
+                _ => None
             }
-        })
-        .collect()
+        }
+        FnArg::Receiver(_) => None
+    })
 }
 
-fn cloned_inputs_pat<'a>(inputs: &Punctuated<Pat, Token![,]>) -> Vec<TokenStream> {
-    //println!("{:?}", inputs);
-    inputs
-        .iter()
-        .filter_map(|arg| {
-            if let Pat::Ident(PatIdent { ref ident, .. }) = arg {
-                Some(ident)
-            } else if let Pat::Type(PatType { pat, .. }) = arg {
-                if let Pat::Ident(PatIdent { ident, .. }) = &**pat {
-                    Some(ident)
-                } else {
-                    None
+/// The output of a method returning a boxed future like `Pin<Box<dyn Future<Output = T> + Send + 'async_trait>>`,
+/// which is what `#[async_trait]` turns async methods into
+fn boxed_future_output(output: &ReturnType) -> Option<Type> {
+    fn generic(ty: &Type, name: &str) -> Option<Type> {
+        let segment = match ty {
+            Type::Path(TypePath { qself: None, path }) => path.segments.last()?,
+            _ => return None
+        };
+        match segment.arguments {
+            PathArguments::AngleBracketed(ref arguments) if segment.ident == name => {
+                match arguments.args.first() {
+                    Some(GenericArgument::Type(ty)) => Some(ty.clone()),
+                    _ => None
                 }
-            } else {
-                None
             }
-        })
-        .map(|ident| {
-            quote_spanned! {
-                ident.span() =>
-                #ident.to_owned()
+            _ => None
+        }
+    }
+    let ty = match output {
+        ReturnType::Type(_, ty) => generic(&generic(ty, "Pin")?, "Box")?,
+        ReturnType::Default => return None
+    };
+    let bounds = match ty {
+        Type::TraitObject(object) => object.bounds,
+        _ => return None
+    };
+    bounds.into_iter().find_map(|bound| match bound {
+        TypeParamBound::Trait(bound) => {
+            let segment = bound.path.segments.last()?;
+            match segment.arguments {
+                PathArguments::AngleBracketed(ref arguments) if segment.ident == "Future" => {
+                    arguments.args.iter().find_map(|argument| match argument {
+                        GenericArgument::Binding(binding) if binding.ident == "Output" => {
+                            Some(binding.ty.clone())
+                        }
+                        _ => None
+                    })
+                }
+                _ => None
             }
-        })
-        .collect()
+        }
+        TypeParamBound::Lifetime(_) => None
+    })
+}
+
+/// Whether the return type mentions `Self`, like `Self` or `Result<Self, Error>`
+fn returns_self(output: &ReturnType) -> bool {
+    match output {
+        ReturnType::Type(_, ty) => mentions(quote!(#ty), "Self"),
+        ReturnType::Default => false
+    }
+}
+
+/// Whether `arg` is the receiver, either as `self`, `&self` and `&mut self` or typed like `self: Arc<Self>`
+fn is_receiver(arg: &FnArg) -> bool {
+    match arg {
+        FnArg::Receiver(_) => true,
+        FnArg::Typed(PatType { pat, .. }) => is_self(pat)
+    }
+}
+
+fn is_self(pat: &Pat) -> bool {
+    matches!(pat, Pat::Ident(PatIdent { ident, .. }) if ident == "self")
+}
+
+/// The positions of the skipped parameters, and the positions and expressions of the projected ones
+type ParamOverrides = (Vec<usize>, Vec<(usize, TokenStream)>);
+
+/// Strip the `#[track_skip]` and `#[track(capture_with = "...")]` attributes off the parameters, returning the
+/// positions of the skipped parameters and the positions and expressions of the projected ones.
+/// The attributes are stripped even if one of them is invalid, so the error is the only one reported.
+fn param_overrides(inputs: &mut Punctuated<FnArg, Token![,]>) -> syn::Result<ParamOverrides> {
+    let mut skipped = Vec::new();
+    let mut projected = Vec::new();
+    let mut error = None;
+    for (i, arg) in inputs.iter_mut().enumerate() {
+        if let FnArg::Typed(PatType { ref mut attrs, .. }) = arg {
+            let len = attrs.len();
+            attrs.retain(|attr| !attr.path.is_ident("track_skip"));
+            if attrs.len() != len {
+                skipped.push(i);
+            }
+            for attr in attrs.iter().filter(|attr| attr.path.is_ident("track")) {
+                match capture_with(attr) {
+                    Ok(expr) => projected.push((i, expr)),
+                    Err(err) => error = error.or(Some(err))
+                }
+            }
+            attrs.retain(|attr| !attr.path.is_ident("track"));
+        }
+    }
+    match error {
+        Some(error) => Err(error),
+        None => Ok((skipped, projected))
+    }
+}
+
+/// Parse the expression of a `#[track(capture_with = "...")]` parameter attribute
+fn capture_with(attr: &Attribute) -> syn::Result<TokenStream> {
+    let nested = match attr.parse_meta()? {
+        Meta::List(list) => list.nested,
+        meta => return Err(syn::Error::new_spanned(
+            meta,
+            "Invalid track attribute on parameter. Expected `#[track(capture_with = \"...\")]`."
+        ))
+    };
+    match nested.first() {
+        Some(NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+            path,
+            lit: Lit::Str(str),
+            ..
+        }))) if nested.len() == 1 && path.is_ident("capture_with") => {
+            let expr = str.parse::<Expr>().map_err(|_| {
+                syn::Error::new_spanned(
+                    str,
+                    "capture_with expects a string literal with an expression."
+                )
+            })?;
+            Ok(spanned(expr))
+        }
+        _ => Err(syn::Error::new_spanned(
+            nested,
+            "Unexpected argument in parameter track attribute. Only capture_with is supported."
+        ))
+    }
+}
+
+/// Split the parameters of a closure into the parameters of the tracking closure, the identifiers forwarded to the
+/// original closure and the identifiers that are captured.
+/// Parameters with destructuring patterns are replaced by a generated identifier so the whole value can be captured and
+/// forwarded. Wildcard (`_`) parameters are forwarded, but not captured, like for functions.
+fn closure_inputs(inputs: &Punctuated<Pat, Token![,]>) -> (Vec<Pat>, Vec<Ident>, Vec<Ident>) {
+    let mut outer = Vec::new();
+    let mut forwarded = Vec::new();
+    let mut captured = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        let pat = match input {
+            Pat::Type(PatType { pat, .. }) => &**pat,
+            pat => pat
+        };
+        if let Pat::Ident(PatIdent {
+            ident,
+            by_ref: None,
+            subpat: None,
+            ..
+        }) = pat
+        {
+            outer.push(input.clone());
+            forwarded.push(ident.clone());
+            captured.push(ident.clone());
+            continue;
+        }
+        let ident = Ident::new(&format!("__racetrack_arg{}", i), pat.span());
+        let generated = Pat::Ident(PatIdent {
+            attrs: Vec::new(),
+            by_ref: None,
+            mutability: None,
+            ident: ident.clone(),
+            subpat: None
+        });
+        outer.push(match input {
+            Pat::Type(typed) => Pat::Type(PatType {
+                pat: Box::new(generated),
+                ..typed.clone()
+            }),
+            _ => generated
+        });
+        if !matches!(pat, Pat::Wild(_)) {
+            captured.push(ident.clone());
+        }
+        forwarded.push(ident);
+    }
+    (outer, forwarded, captured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(args: &str) -> Arguments {
+        parse_args(syn::parse_str(args).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn debug_expand_reports_keys_without_changing_the_code() {
+        let fun: ItemFn = parse_quote! {
+            fn double(value: u32) -> u32 {
+                value * 2
+            }
+        };
+        let plain = track_function(&arguments("TRACKER"), fun.clone());
+        let args = arguments("TRACKER, debug_expand = true");
+        let expanded = track_function(&args, fun);
+        assert_eq!(expanded.to_string(), plain.to_string());
+
+        let report = expansion(&args, "track_with", &expanded).unwrap();
+        assert!(report.starts_with(
+            "===== racetrack: track_with expansion =====\nKeys:\n    double => \"double\"\n"
+        ));
+        assert!(report.contains(&format!("Generated code:\n{}\n", expanded)));
+        assert!(report.ends_with("===== racetrack: end of track_with expansion =====\n"));
+        assert_eq!(expansion(&arguments("TRACKER"), "track_with", &plain), None);
+    }
 }
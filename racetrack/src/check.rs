@@ -0,0 +1,873 @@
+//! Non-panicking assertions.
+//!
+//! Every assertion in [`tracker`](crate::tracker) is built on the checks in this module, so the messages in
+//! [`CheckError`] are exactly the ones the panicking assertions use.
+//! This is useful when running under a custom test harness where panics are undesirable.
+//!
+//! # Example
+//!
+//! ```
+//! use racetrack::{CallInfo, Tracker};
+//!
+//! let tracker = Tracker::new();
+//! tracker.log_call("my_fn", CallInfo::new().with_args("Test".to_string()));
+//!
+//! let result = tracker
+//!     .assert_that("my_fn")
+//!     .check_called_once()
+//!     .and_then(|check| check.with("Other".to_string()));
+//! assert_eq!(
+//!     result.err().unwrap().to_string(),
+//!     "my_fn wasn't called with the arguments specified."
+//! );
+//! ```
+//!
+//! For tests that return a `Result`, [`Tracker::check`](crate::Tracker::check) in [`checked`](crate::checked) runs
+//! the same checks with an API that mirrors the assertions, so each step can be propagated with `?`.
+
+use crate::tracker::{CallInfo, CallSet};
+use std::{
+    any::Any,
+    collections::HashSet,
+    error::Error,
+    fmt::{self, Debug},
+    hash::Hash,
+    iter::Sum,
+    sync::Arc
+};
+
+/// The error returned by a failed check. Displays as the message the equivalent assertion would panic with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckError {
+    message: String,
+    /// The recorded calls listed after the message, empty if there are none
+    summary: String
+}
+
+impl CheckError {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            summary: String::new()
+        }
+    }
+
+    /// The failure message, without the recorded calls
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The recorded calls listed after the message, or an empty string if there are none. Calls are only listed if
+    /// they were captured with `capture = "debug"`.
+    /// See [`AssertionBuilder::call_summaries`](crate::tracker::AssertionBuilder::call_summaries).
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    pub(crate) fn append(mut self, message: impl AsRef<str>) -> Self {
+        self.message.push_str(message.as_ref());
+        self
+    }
+
+    /// List `calls` after the message
+    pub(crate) fn with_summary(mut self, calls: &[&CallInfo]) -> Self {
+        self.summary = summarize_calls(calls);
+        self
+    }
+
+    /// Drop the recorded calls listed after the message, if any
+    pub(crate) fn without_summary(mut self) -> Self {
+        self.summary.clear();
+        self
+    }
+
+    /// Panic with the failure message. Used by the panicking assertions.
+    pub(crate) fn raise<T>(self) -> T {
+        panic!("{}", self)
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        f.write_str(&self.summary)
+    }
+}
+
+impl Error for CheckError {}
+
+/// A check object for checking additional metadata. This is the non-panicking equivalent of
+/// [`MetaAssertion`](crate::tracker::MetaAssertion).
+pub struct MetaCheck {
+    pub(crate) item: CallSet,
+    pub(crate) key: String
+}
+
+impl MetaCheck {
+    /// Check that the method was called at least once with `args`.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn with<T: PartialEq + 'static>(self, args: T) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| called_with(calls, key, &args))?;
+        Ok(self)
+    }
+
+    /// Check that the method was called at least once with an `Arc` or `Box` pointing to `value`.
+    /// See [`MetaAssertion::with_deref`](crate::tracker::MetaAssertion::with_deref).
+    pub fn with_deref<T: PartialEq + ?Sized + 'static>(
+        self,
+        value: &T
+    ) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| called_with_deref(calls, key, value))?;
+        Ok(self)
+    }
+
+    /// Check that the method was called at least once on a receiver equal to `receiver`.
+    /// See [`MetaAssertion::with_receiver`](crate::tracker::MetaAssertion::with_receiver).
+    pub fn with_receiver<T: PartialEq + 'static>(self, receiver: T) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| called_on(calls, key, &receiver))?;
+        Ok(self)
+    }
+
+    /// Check that the method was called at least once without arguments.
+    /// See [`MetaAssertion::with_no_args`](crate::tracker::MetaAssertion::with_no_args).
+    pub fn with_no_args(self) -> Result<Self, CheckError> {
+        self.item.check(&self.key, called_with_no_args)?;
+        Ok(self)
+    }
+
+    /// Check that the method was called at least once with arguments captured as `args`.
+    /// See [`MetaAssertion::with_debug`](crate::tracker::MetaAssertion::with_debug).
+    pub fn with_debug(self, args: &str) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| called_with_debug(calls, key, args))?;
+        Ok(self)
+    }
+
+    /// Check that the arguments of at least one call satisfy `predicate`.
+    /// See [`MetaAssertion::with_args_matching`](crate::tracker::MetaAssertion::with_args_matching).
+    pub fn with_args_matching<T: 'static>(
+        self,
+        predicate: impl Fn(&T) -> bool
+    ) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| args_matching(calls, key, predicate))?;
+        Ok(self)
+    }
+
+    /// Check that the arguments of every call satisfy `invariant`.
+    /// See [`MetaAssertion::with_invariant`](crate::tracker::MetaAssertion::with_invariant).
+    pub fn with_invariant<T: 'static>(
+        self,
+        invariant: impl Fn(&T) -> bool
+    ) -> Result<Self, CheckError> {
+        self.item.check(&self.key, |calls, key| {
+            args_invariant(calls, key, invariant)
+        })?;
+        Ok(self)
+    }
+
+    /// Check that the calls were made with exactly the arguments in `expected`, in order.
+    /// See [`MetaAssertion::with_args_seq`](crate::tracker::MetaAssertion::with_args_seq).
+    pub fn with_args_seq<T: PartialEq + 'static>(
+        self,
+        expected: Vec<T>
+    ) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| args_seq(calls, key, &expected))?;
+        Ok(self)
+    }
+
+    /// Check that the method was called at least once with arguments equal to `args` according to `eq`.
+    /// See [`MetaAssertion::with_eq`](crate::tracker::MetaAssertion::with_eq).
+    pub fn with_eq<T: 'static>(
+        self,
+        args: T,
+        eq: impl Fn(&T, &T) -> bool
+    ) -> Result<Self, CheckError> {
+        self.item.check(&self.key, |calls, key| {
+            called_with_eq(calls, key, &args, eq)
+        })?;
+        Ok(self)
+    }
+
+    /// Check that the values produced by `f` for the arguments of every call add up to `expected`.
+    /// See [`MetaAssertion::assert_args_sum`](crate::tracker::MetaAssertion::assert_args_sum).
+    pub fn args_sum<T: 'static, U: Sum + PartialEq + Debug>(
+        self,
+        f: impl Fn(&T) -> U,
+        expected: U
+    ) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| args_sum(calls, key, f, &expected))?;
+        Ok(self)
+    }
+
+    /// Check that the method was not ever called with `args`.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn not_with<T: PartialEq + 'static>(self, args: T) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| not_called_with(calls, key, &args))?;
+        Ok(self)
+    }
+
+    /// Check that no call was made at a recursion depth greater than `n`.
+    pub fn max_depth(self, n: usize) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| max_depth(calls, key, n))?;
+        Ok(self)
+    }
+
+    /// Check that the calls were made from at least `n` distinct threads.
+    pub fn from_distinct_threads(self, n: usize) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| from_distinct_threads(calls, key, n))?;
+        Ok(self)
+    }
+
+    /// Check that the calls were made with exactly `k` distinct arguments.
+    /// See [`MetaAssertion::with_distinct_count`](crate::tracker::MetaAssertion::with_distinct_count).
+    pub fn with_distinct_count<T: Eq + Hash + Clone + 'static>(
+        self,
+        k: usize
+    ) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| distinct_count::<T>(calls, key, k))?;
+        Ok(self)
+    }
+
+    /// Check that at least one call satisfies `predicate`.
+    /// See [`MetaAssertion::with_call_matching`](crate::tracker::MetaAssertion::with_call_matching).
+    pub fn with_call_matching(
+        self,
+        predicate: impl Fn(&CallInfo) -> bool
+    ) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| call_matching(calls, key, predicate))?;
+        Ok(self)
+    }
+
+    /// Check that at least one call was made from `file` at `line`.
+    pub fn called_from(self, file: &str, line: u32) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| called_from(calls, key, file, line))?;
+        Ok(self)
+    }
+
+    /// Check that the method returned `value` at least once.
+    /// T must be the return type.
+    ///
+    /// # Warning
+    ///
+    /// The return type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn and_returned<T: PartialEq + 'static>(self, value: T) -> Result<(), CheckError> {
+        self.item
+            .check(&self.key, |calls, key| returned(calls, key, &value))
+    }
+
+    /// Check that the `n`th call, counting from 0, returned `value`.
+    /// See [`MetaAssertion::nth_returned`](crate::tracker::MetaAssertion::nth_returned).
+    pub fn nth_returned<T: PartialEq + 'static>(
+        self,
+        n: usize,
+        value: T
+    ) -> Result<Self, CheckError> {
+        self.item
+            .check(&self.key, |calls, key| nth_returned(calls, key, n, &value))?;
+        Ok(self)
+    }
+
+    /// Check that the method returned a value captured as `value` at least once.
+    /// See [`MetaAssertion::and_returned_debug`](crate::tracker::MetaAssertion::and_returned_debug).
+    pub fn and_returned_debug(self, value: &str) -> Result<(), CheckError> {
+        self.item
+            .check(&self.key, |calls, key| returned_debug(calls, key, value))
+    }
+}
+
+pub(crate) fn called(calls: &[&CallInfo], key: &str) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        Err(CheckError::new(format!("{} wasn't called.", key)))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn called_once(calls: &[&CallInfo], key: &str) -> Result<(), CheckError> {
+    match calls.len() {
+        0 => Err(CheckError::new(format!("{} wasn't called.", key))),
+        1 => Ok(()),
+        len => Err(CheckError::new(format!(
+            "{} was called more than once. Was called {} times.",
+            key, len
+        ))
+        .with_summary(calls))
+    }
+}
+
+pub(crate) fn called_times(calls: &[&CallInfo], key: &str, n: usize) -> Result<(), CheckError> {
+    if n == 0 {
+        not_called(calls, key)
+    } else if calls.len() != n {
+        Err(CheckError::new(format!(
+            "{} should've been called {} times, but was called {} times.",
+            key,
+            n,
+            calls.len()
+        ))
+        .with_summary(calls))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn not_called(calls: &[&CallInfo], key: &str) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        Ok(())
+    } else {
+        Err(CheckError::new(format!(
+            "{} should not have been called but was called {} times.",
+            key,
+            calls.len()
+        ))
+        .with_summary(calls))
+    }
+}
+
+pub(crate) fn called_with<T: PartialEq + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    args: &T
+) -> Result<(), CheckError> {
+    called_with_eq(calls, key, args, T::eq)
+}
+
+pub(crate) fn called_with_eq<T: 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    args: &T,
+    eq: impl Fn(&T, &T) -> bool
+) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        return Err(CheckError::new(format!("{} wasn't called.", key)));
+    }
+    for call_info in calls {
+        if eq(arguments::<T>(call_info, key)?, args) {
+            return Ok(());
+        }
+    }
+    Err(CheckError::new(format!(
+        "{} wasn't called with the arguments specified.",
+        key
+    )))
+}
+
+pub(crate) fn called_with_deref<T: PartialEq + ?Sized + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    value: &T
+) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        return Err(CheckError::new(format!("{} wasn't called.", key)));
+    }
+    for call_info in calls {
+        let call_args = call_info.arguments.as_ref().ok_or_else(|| {
+            CheckError::new(format!(
+                "You didn't log any arguments for your calls to {}.",
+                key
+            ))
+        })?;
+        let pointee = call_args
+            .downcast_ref::<Arc<T>>()
+            .map(|arc| &**arc)
+            .or_else(|| call_args.downcast_ref::<Box<T>>().map(|boxed| &**boxed))
+            .ok_or_else(|| {
+                CheckError::new(format!(
+                    "The arguments logged for {} weren't an Arc or Box of that type.",
+                    key
+                ))
+            })?;
+        if pointee == value {
+            return Ok(());
+        }
+    }
+    Err(CheckError::new(format!(
+        "{} wasn't called with the arguments specified.",
+        key
+    ))
+    .with_summary(calls))
+}
+
+pub(crate) fn called_on<T: PartialEq + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    receiver: &T
+) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        return Err(CheckError::new(format!("{} wasn't called.", key)));
+    }
+    for call_info in calls {
+        let call_receiver = call_info.receiver.as_ref().ok_or_else(|| {
+            CheckError::new(format!(
+                "You didn't log the receiver for your calls to {}. Track them with capture_self.",
+                key
+            ))
+        })?;
+        let cast = call_receiver.downcast_ref::<T>().ok_or_else(|| {
+            CheckError::new(format!(
+                "The receiver logged for {} didn't have that type.",
+                key
+            ))
+        })?;
+        if cast == receiver {
+            return Ok(());
+        }
+    }
+    Err(CheckError::new(format!(
+        "{} wasn't called on the receiver specified.",
+        key
+    )))
+}
+
+pub(crate) fn called_with_no_args(calls: &[&CallInfo], key: &str) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        return Err(CheckError::new(format!("{} wasn't called.", key)));
+    }
+    for call_info in calls {
+        let call_args = call_info.arguments.as_ref().ok_or_else(|| {
+            CheckError::new(format!(
+                "You didn't log any arguments for your calls to {}.",
+                key
+            ))
+        })?;
+        if call_args.is::<()>() {
+            return Ok(());
+        }
+    }
+    Err(CheckError::new(format!("{} was only called with arguments.", key)).with_summary(calls))
+}
+
+pub(crate) fn called_with_debug(
+    calls: &[&CallInfo],
+    key: &str,
+    args: &str
+) -> Result<(), CheckError> {
+    called_with(calls, key, &args.to_string()).map_err(|err| err.with_summary(calls))
+}
+
+pub(crate) fn args_matching<T: 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    predicate: impl Fn(&T) -> bool
+) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        return Err(CheckError::new(format!("{} wasn't called.", key)));
+    }
+    for call_info in calls {
+        if predicate(arguments::<T>(call_info, key)?) {
+            return Ok(());
+        }
+    }
+    Err(CheckError::new(format!(
+        "{} wasn't called with arguments matching the pattern.",
+        key
+    ))
+    .with_summary(calls))
+}
+
+pub(crate) fn args_invariant<T: 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    invariant: impl Fn(&T) -> bool
+) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        return Err(CheckError::new(format!("{} wasn't called.", key)));
+    }
+    for (i, call_info) in calls.iter().enumerate() {
+        if !invariant(arguments::<T>(call_info, key)?) {
+            return Err(CheckError::new(format!(
+                "The arguments of call {} to {} broke the invariant.",
+                i + 1,
+                key
+            ))
+            .with_summary(calls));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn args_seq<T: PartialEq + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    expected: &[T]
+) -> Result<(), CheckError> {
+    if calls.len() != expected.len() {
+        return Err(CheckError::new(format!(
+            "{} should've been called {} times, but was called {} times.",
+            key,
+            expected.len(),
+            calls.len()
+        ))
+        .with_summary(calls));
+    }
+    for (i, (call_info, args)) in calls.iter().zip(expected).enumerate() {
+        if arguments::<T>(call_info, key)? != args {
+            return Err(CheckError::new(format!(
+                "Call {} to {} wasn't made with the arguments specified.",
+                i + 1,
+                key
+            ))
+            .with_summary(calls));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn not_called_with<T: PartialEq + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    args: &T
+) -> Result<(), CheckError> {
+    for call_info in calls {
+        if arguments::<T>(call_info, key)? == args {
+            return Err(CheckError::new(format!(
+                "{} was called with the argument when it should'nt have been.",
+                key
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn returned<T: PartialEq + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    value: &T
+) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        return Err(CheckError::new(format!("{} wasn't called.", key)));
+    }
+    // Calls logged with log_before don't have a return value until they return
+    let mut call_returns = calls
+        .iter()
+        .filter_map(|call_info| call_info.returned.as_ref())
+        .peekable();
+    if call_returns.peek().is_none() {
+        return Err(CheckError::new(format!(
+            "You didn't log any return values for your calls to {}.",
+            key
+        )));
+    }
+    for call_return in call_returns {
+        let cast = call_return.downcast_ref::<T>().ok_or_else(|| {
+            CheckError::new(format!(
+                "The return values logged for {} didn't have that type.",
+                key
+            ))
+        })?;
+        if cast == value {
+            return Ok(());
+        }
+    }
+    Err(CheckError::new(format!(
+        "{} never returned the value specified.",
+        key
+    )))
+}
+
+pub(crate) fn returned_debug(
+    calls: &[&CallInfo],
+    key: &str,
+    value: &str
+) -> Result<(), CheckError> {
+    returned(calls, key, &value.to_string()).map_err(|err| err.with_summary(calls))
+}
+
+pub(crate) fn nth_returned<T: PartialEq + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    n: usize,
+    value: &T
+) -> Result<(), CheckError> {
+    let call_info = calls.get(n).ok_or_else(|| {
+        CheckError::new(format!(
+            "{} was called {} times, so there's no call {}.",
+            key,
+            calls.len(),
+            n + 1
+        ))
+    })?;
+    let call_return = call_info.returned.as_ref().ok_or_else(|| {
+        CheckError::new(format!(
+            "You didn't log any return values for your calls to {}.",
+            key
+        ))
+    })?;
+    let cast = call_return.downcast_ref::<T>().ok_or_else(|| {
+        CheckError::new(format!(
+            "The return value logged for {} didn't have that type.",
+            key
+        ))
+    })?;
+    if cast == value {
+        Ok(())
+    } else {
+        Err(CheckError::new(format!(
+            "Call {} to {} returned a different value.",
+            n + 1,
+            key
+        ))
+        .with_summary(calls))
+    }
+}
+
+pub(crate) fn max_depth(calls: &[&CallInfo], key: &str, n: usize) -> Result<(), CheckError> {
+    match calls.iter().map(|call_info| call_info.depth).max() {
+        Some(depth) if depth > n => Err(CheckError::new(format!(
+            "{} should've recursed at most {} levels deep, but reached a depth of {}.",
+            key, n, depth
+        ))),
+        _ => Ok(())
+    }
+}
+
+pub(crate) fn from_distinct_threads(
+    calls: &[&CallInfo],
+    key: &str,
+    n: usize
+) -> Result<(), CheckError> {
+    let threads: HashSet<_> = calls
+        .iter()
+        .filter_map(|call_info| call_info.thread_id)
+        .collect();
+    if threads.len() < n {
+        Err(CheckError::new(format!(
+            "{} should've been called from at least {} distinct threads, but was called from {}.",
+            key,
+            n,
+            threads.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn distinct_count<T: Eq + Hash + 'static>(
+    calls: &[&CallInfo],
+    key: &str,
+    k: usize
+) -> Result<(), CheckError> {
+    let distinct = calls
+        .iter()
+        .map(|call_info| arguments::<T>(call_info, key))
+        .collect::<Result<HashSet<_>, _>>()?;
+    if distinct.len() == k {
+        Ok(())
+    } else {
+        Err(CheckError::new(format!(
+            "{} should've been called with {} distinct arguments, but was called with {}.",
+            key,
+            k,
+            distinct.len()
+        ))
+        .with_summary(calls))
+    }
+}
+
+pub(crate) fn call_matching(
+    calls: &[&CallInfo],
+    key: &str,
+    predicate: impl Fn(&CallInfo) -> bool
+) -> Result<(), CheckError> {
+    if calls.is_empty() {
+        Err(CheckError::new(format!("{} wasn't called.", key)))
+    } else if calls.iter().any(|call_info| predicate(call_info)) {
+        Ok(())
+    } else {
+        Err(CheckError::new(format!(
+            "None of the calls to {} matched the predicate.",
+            key
+        ))
+        .with_summary(calls))
+    }
+}
+
+pub(crate) fn called_from(
+    calls: &[&CallInfo],
+    key: &str,
+    file: &str,
+    line: u32
+) -> Result<(), CheckError> {
+    let locations: Vec<_> = calls
+        .iter()
+        .filter_map(|call_info| call_info.location)
+        .collect();
+    if locations
+        .iter()
+        .any(|location| location.file() == file && location.line() == line)
+    {
+        return Ok(());
+    }
+    let mut message = format!("{} wasn't called from {}:{}.", key, file, line);
+    if !locations.is_empty() {
+        let recorded: Vec<_> = locations
+            .iter()
+            .map(|location| format!("{}:{}", location.file(), location.line()))
+            .collect();
+        message.push_str(&format!(" Was called from {}.", recorded.join(", ")));
+    }
+    Err(CheckError::new(message))
+}
+
+/// Map the arguments of every call with `f`, reporting the number of the first call whose arguments can't be downcast.
+pub(crate) fn map_args<T: 'static, U>(
+    calls: &[&CallInfo],
+    key: &str,
+    f: impl Fn(&T) -> U
+) -> Result<Vec<U>, CheckError> {
+    calls
+        .iter()
+        .enumerate()
+        .map(|(i, call_info)| {
+            let call_args = call_info.arguments.as_ref().ok_or_else(|| {
+                CheckError::new(format!(
+                    "You didn't log any arguments for call {} to {}.",
+                    i + 1,
+                    key
+                ))
+            })?;
+            let cast = call_args.downcast_ref::<T>().ok_or_else(|| {
+                CheckError::new(format!(
+                    "The arguments logged for call {} to {} didn't have that type.",
+                    i + 1,
+                    key
+                ))
+            })?;
+            Ok(f(cast))
+        })
+        .collect()
+}
+
+pub(crate) fn args_sum<T: 'static, U: Sum + PartialEq + Debug>(
+    calls: &[&CallInfo],
+    key: &str,
+    f: impl Fn(&T) -> U,
+    expected: &U
+) -> Result<(), CheckError> {
+    let sum: U = map_args(calls, key, f)?.into_iter().sum();
+    if sum != *expected {
+        return Err(CheckError::new(format!(
+            "The arguments of the calls to {} should've added up to {:?}, but added up to {:?}.",
+            key, expected, sum
+        ))
+        .with_summary(calls));
+    }
+    Ok(())
+}
+
+/// The arguments of `last`, the most recent call to `key`
+pub(crate) fn last_args<'a, T: 'static>(
+    last: &'a CallInfo,
+    key: &str
+) -> Result<&'a T, CheckError> {
+    let call_args = last.arguments.as_ref().ok_or_else(|| {
+        CheckError::new(format!(
+            "You didn't log any arguments for the last call to {}.",
+            key
+        ))
+    })?;
+    call_args.downcast_ref::<T>().ok_or_else(|| {
+        CheckError::new(format!(
+            "The arguments logged for the last call to {} weren't of type {}.",
+            key,
+            std::any::type_name::<T>()
+        ))
+    })
+}
+
+fn arguments<'a, T: 'static>(call_info: &'a CallInfo, key: &str) -> Result<&'a T, CheckError> {
+    let call_args = call_info.arguments.as_ref().ok_or_else(|| {
+        CheckError::new(format!(
+            "You didn't log any arguments for your calls to {}.",
+            key
+        ))
+    })?;
+    call_args.downcast_ref::<T>().ok_or_else(|| {
+        CheckError::new(format!(
+            "The arguments logged for {} didn't have that type.",
+            key
+        ))
+    })
+}
+
+/// Find the key closest to `key` by edit distance, if any is close enough to plausibly be a typo.
+pub(crate) fn closest_key<'a>(key: &str, keys: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (key.chars().count() / 3).max(2);
+    keys.map(|candidate| (edit_distance(key, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut previous: Vec<_> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == *b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The first line of the recorded calls listed after a failure message
+const SUMMARY_HEADER: &str = "\nRecorded calls:";
+
+/// Build a numbered summary of the recorded calls for failure messages.
+/// Only payloads that were captured as strings (i.e. Debug captures) can be printed, so this is empty if there are none.
+fn summarize_calls(calls: &[&CallInfo]) -> String {
+    let has_debug_capture = calls.iter().any(|call_info| {
+        describe(&call_info.arguments).is_some() || describe(&call_info.returned).is_some()
+    });
+    if !has_debug_capture {
+        return String::new();
+    }
+
+    let mut summary = String::from(SUMMARY_HEADER);
+    for (i, call_info) in calls.iter().enumerate() {
+        let arguments = describe(&call_info.arguments).unwrap_or_else(|| "..".to_string());
+        let returned = describe(&call_info.returned).unwrap_or_else(|| "..".to_string());
+        summary.push_str(&format!(
+            "\n  {}. arguments: {}, returned: {}",
+            i + 1,
+            arguments,
+            returned
+        ));
+    }
+    summary
+}
+
+fn describe(value: &Option<Box<dyn Any + Send + Sync>>) -> Option<String> {
+    let value = value.as_ref()?;
+    if let Some(value) = value.downcast_ref::<String>() {
+        Some(value.clone())
+    } else {
+        value
+            .downcast_ref::<&'static str>()
+            .map(|value| value.to_string())
+    }
+}
@@ -0,0 +1,250 @@
+//! Assertions that return a `Result`, for tests that return one.
+//!
+//! [`Tracker::check`](crate::Tracker::check) mirrors [`Tracker::assert_that`](crate::Tracker::assert_that), but every step returns a `Result` instead of panicking, so a
+//! chain stops at the first failure with `?`. The failures are the ones the panicking assertions would panic with.
+//!
+//! # Example
+//!
+//! ```
+//! use racetrack::{checked::AssertionError, CallInfo, Tracker};
+//!
+//! fn check_calls(tracker: &Tracker) -> Result<(), AssertionError> {
+//!     tracker
+//!         .check("my_fn")
+//!         .called_once()?
+//!         .with("Test".to_string())?
+//!         .and_returned(3usize)?;
+//!     tracker.check("other_fn").not_called()
+//! }
+//!
+//! let tracker = Tracker::new();
+//! tracker.log_call("my_fn", CallInfo::new().with_args("Test".to_string()).with_ret(3usize));
+//! assert!(check_calls(&tracker).is_ok());
+//!
+//! tracker.log_call("other_fn", CallInfo::default());
+//! assert_eq!(
+//!     check_calls(&tracker).unwrap_err().to_string(),
+//!     "other_fn should not have been called but was called 1 times."
+//! );
+//! ```
+
+use crate::{
+    check::{CheckError, MetaCheck},
+    key::Untyped,
+    tracker::{Assertion, CallInfo, Checkpoint}
+};
+use std::{
+    error::Error,
+    fmt::{self, Debug},
+    hash::Hash,
+    iter::Sum,
+    time::Duration
+};
+
+/// The error returned by a failed checked assertion. Displays as the message the equivalent assertion would panic
+/// with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertionError(CheckError);
+
+impl AssertionError {
+    /// The failure message, without the recorded calls
+    pub fn message(&self) -> &str {
+        self.0.message()
+    }
+
+    /// The recorded calls listed after the message, or an empty string if there are none.
+    /// See [`CheckError::summary`].
+    pub fn summary(&self) -> &str {
+        self.0.summary()
+    }
+}
+
+impl From<CheckError> for AssertionError {
+    fn from(err: CheckError) -> Self {
+        Self(err)
+    }
+}
+
+impl fmt::Display for AssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Error for AssertionError {}
+
+/// Starts a checked assertion chain. See [`Tracker::check`](crate::Tracker::check).
+pub struct Checker<Args = Untyped, Ret = Untyped>(pub(crate) Assertion<Args, Ret>);
+
+impl<Args, Ret> Checker<Args, Ret> {
+    /// Only consider calls logged after `checkpoint`. See [`Assertion::since`].
+    pub fn since(self, checkpoint: &Checkpoint) -> Self {
+        Self(self.0.since(checkpoint))
+    }
+
+    /// Wait up to `timeout` for the expected number of calls. See [`Assertion::eventually`].
+    pub fn eventually(self, timeout: Duration) -> Self {
+        Self(self.0.eventually(timeout))
+    }
+
+    /// Check that the method was called exactly once.
+    /// Returns an object that lets you check more detailed metadata.
+    pub fn called_once(self) -> Result<CheckedCalls, AssertionError> {
+        Ok(CheckedCalls(self.0.check_called_once()?))
+    }
+
+    /// Check that the method was called exactly `n` times.
+    /// Returns an object that lets you check more detailed metadata.
+    pub fn called_times(self, n: usize) -> Result<CheckedCalls, AssertionError> {
+        Ok(CheckedCalls(self.0.check_called_times(n)?))
+    }
+
+    /// Check that the method wasn't called. Ends the chain.
+    pub fn not_called(self) -> Result<(), AssertionError> {
+        Ok(self.0.check_not_called()?)
+    }
+}
+
+/// Checks the metadata of the calls. This is the checked equivalent of
+/// [`MetaAssertion`](crate::tracker::MetaAssertion), see there for details on each method.
+pub struct CheckedCalls(MetaCheck);
+
+impl CheckedCalls {
+    /// Check that the method was called at least once with `args`.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn with<T: PartialEq + 'static>(self, args: T) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with(args)?))
+    }
+
+    /// Check that the method was called at least once with an `Arc` or `Box` pointing to `value`.
+    pub fn with_deref<T: PartialEq + ?Sized + 'static>(
+        self,
+        value: &T
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_deref(value)?))
+    }
+
+    /// Check that the method was called at least once on a receiver equal to `receiver`.
+    pub fn with_receiver<T: PartialEq + 'static>(
+        self,
+        receiver: T
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_receiver(receiver)?))
+    }
+
+    /// Check that the method was called at least once without arguments.
+    pub fn with_no_args(self) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_no_args()?))
+    }
+
+    /// Check that the method was called at least once with arguments captured as `args`.
+    pub fn with_debug(self, args: &str) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_debug(args)?))
+    }
+
+    /// Check that the arguments of at least one call satisfy `predicate`.
+    pub fn with_args_matching<T: 'static>(
+        self,
+        predicate: impl Fn(&T) -> bool
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_args_matching(predicate)?))
+    }
+
+    /// Check that the arguments of every call satisfy `invariant`.
+    pub fn with_invariant<T: 'static>(
+        self,
+        invariant: impl Fn(&T) -> bool
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_invariant(invariant)?))
+    }
+
+    /// Check that the calls were made with exactly the arguments in `expected`, in order.
+    pub fn with_args_seq<T: PartialEq + 'static>(
+        self,
+        expected: Vec<T>
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_args_seq(expected)?))
+    }
+
+    /// Check that the method was called at least once with arguments equal to `args` according to `eq`.
+    pub fn with_eq<T: 'static>(
+        self,
+        args: T,
+        eq: impl Fn(&T, &T) -> bool
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_eq(args, eq)?))
+    }
+
+    /// Check that the values produced by `f` for the arguments of every call add up to `expected`.
+    pub fn args_sum<T: 'static, U: Sum + PartialEq + Debug>(
+        self,
+        f: impl Fn(&T) -> U,
+        expected: U
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.args_sum(f, expected)?))
+    }
+
+    /// Check that the method was not ever called with `args`.
+    pub fn not_with<T: PartialEq + 'static>(self, args: T) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.not_with(args)?))
+    }
+
+    /// Check that no call was made at a recursion depth greater than `n`.
+    pub fn max_depth(self, n: usize) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.max_depth(n)?))
+    }
+
+    /// Check that the calls were made from at least `n` distinct threads.
+    pub fn from_distinct_threads(self, n: usize) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.from_distinct_threads(n)?))
+    }
+
+    /// Check that the calls were made with exactly `k` distinct arguments.
+    pub fn with_distinct_count<T: Eq + Hash + Clone + 'static>(
+        self,
+        k: usize
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_distinct_count::<T>(k)?))
+    }
+
+    /// Check that at least one call satisfies `predicate`.
+    pub fn with_call_matching(
+        self,
+        predicate: impl Fn(&CallInfo) -> bool
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.with_call_matching(predicate)?))
+    }
+
+    /// Check that at least one call was made from `file` at `line`.
+    pub fn called_from(self, file: &str, line: u32) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.called_from(file, line)?))
+    }
+
+    /// Check that the `n`th call, counting from 0, returned `value`.
+    pub fn nth_returned<T: PartialEq + 'static>(
+        self,
+        n: usize,
+        value: T
+    ) -> Result<Self, AssertionError> {
+        Ok(Self(self.0.nth_returned(n, value)?))
+    }
+
+    /// Check that the method returned `value` at least once. Ends the chain.
+    /// T must be the return type.
+    ///
+    /// # Warning
+    ///
+    /// The return type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn and_returned<T: PartialEq + 'static>(self, value: T) -> Result<(), AssertionError> {
+        Ok(self.0.and_returned(value)?)
+    }
+
+    /// Check that the method returned a value captured as `value` at least once. Ends the chain.
+    pub fn and_returned_debug(self, value: &str) -> Result<(), AssertionError> {
+        Ok(self.0.and_returned_debug(value)?)
+    }
+}
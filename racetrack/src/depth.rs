@@ -0,0 +1,60 @@
+//! Recursion depth tracking.
+//!
+//! The proc macro enters a [`DepthGuard`] before running the body of a tracked item and records its depth in the
+//! [`CallInfo`](crate::CallInfo). Depths are counted per tracked item and per thread, so concurrent calls on other
+//! threads don't affect each other. Items keyed per instance or per generic instantiation share one depth, since the
+//! proc macro always enters the guard with the static key of the item.
+//!
+//! # Warning
+//!
+//! Depths are stored in a thread local. Async functions that are moved between threads while suspended will
+//! report depths relative to whichever thread they're polled on.
+
+use std::{borrow::Cow, cell::RefCell, collections::HashMap};
+
+thread_local! {
+    static DEPTHS: RefCell<HashMap<Cow<'static, str>, usize>> = RefCell::new(HashMap::new());
+}
+
+/// Keeps the recursion depth of a key incremented for as long as it's alive.
+/// The depth is decremented again on drop, so this is panic safe.
+#[derive(Debug)]
+pub struct DepthGuard {
+    key: Cow<'static, str>,
+    depth: usize
+}
+
+impl DepthGuard {
+    /// Increment the depth of `key` on the current thread.
+    /// Static keys like the ones passed by the proc macro are borrowed, so entering never allocates for them.
+    pub fn enter(key: impl Into<Cow<'static, str>>) -> Self {
+        let key = key.into();
+        let depth = DEPTHS.with(|depths| {
+            let mut depths = depths.borrow_mut();
+            let depth = depths.entry(key.clone()).or_insert(0);
+            *depth += 1;
+            *depth
+        });
+        Self { key, depth }
+    }
+
+    /// The depth of this call. The outermost call has a depth of 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        // The thread local may already be gone if the guard is dropped during thread teardown
+        let _ = DEPTHS.try_with(|depths| {
+            let mut depths = depths.borrow_mut();
+            if let Some(depth) = depths.get_mut(self.key.as_ref()) {
+                *depth -= 1;
+                if *depth == 0 {
+                    depths.remove(self.key.as_ref());
+                }
+            }
+        });
+    }
+}
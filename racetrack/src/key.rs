@@ -0,0 +1,103 @@
+//! Typed keys.
+//!
+//! A [`CallKey`] carries the types of the arguments and the return value of a tracked method, so assertions started
+//! with it can infer the types of `with`, `and_returned` and friends instead of needing them spelled out.
+//! The proc macro can generate them for every tracked method with the `keys` argument.
+//!
+//! # Example
+//!
+//! ```
+//! use racetrack::{key::CallKey, CallInfo, Tracker};
+//!
+//! const PARSE: CallKey<String, u32> = CallKey::new("parse");
+//!
+//! let tracker = Tracker::new();
+//! tracker.log_call("parse", CallInfo::new().with_args("42".to_string()).with_ret(42u32));
+//!
+//! tracker
+//!     .assert_that(PARSE)
+//!     .was_called_once()
+//!     .with("42".into())
+//!     .and_returned(42);
+//! ```
+
+use std::{fmt, marker::PhantomData};
+
+/// A key with the types of the logged arguments and return value.
+/// `Args` and `Ret` are the types after `to_owned`, so `&str` becomes `String`.
+pub struct CallKey<Args, Ret> {
+    key: &'static str,
+    types: PhantomData<fn() -> (Args, Ret)>
+}
+
+impl<Args, Ret> CallKey<Args, Ret> {
+    /// Create a typed key for the method logged under `key`.
+    pub const fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            types: PhantomData
+        }
+    }
+
+    /// The untyped key
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+}
+
+impl<Args, Ret> Clone for CallKey<Args, Ret> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Args, Ret> Copy for CallKey<Args, Ret> {}
+
+impl<Args, Ret> fmt::Debug for CallKey<Args, Ret> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CallKey").field(&self.key).finish()
+    }
+}
+
+/// The types of an assertion started with a plain string key. Any type can be asserted on.
+#[derive(Debug)]
+pub struct Untyped;
+
+/// The types of an assertion started with a [`CallKey`]. Only `T` can be asserted on.
+pub struct Typed<T>(PhantomData<fn() -> T>);
+
+/// Implemented for the types that can be asserted on for a key.
+pub trait Accepts<T> {}
+
+impl<T> Accepts<T> for Untyped {}
+
+impl<T> Accepts<T> for Typed<T> {}
+
+/// Anything an assertion can be started with. This is either a string or a [`CallKey`].
+pub trait IntoKey {
+    /// The argument types that can be asserted on
+    type Args;
+    /// The return types that can be asserted on
+    type Ret;
+
+    /// The key the calls were logged under
+    fn into_key(self) -> String;
+}
+
+impl<S: Into<String>> IntoKey for S {
+    type Args = Untyped;
+    type Ret = Untyped;
+
+    fn into_key(self) -> String {
+        self.into()
+    }
+}
+
+impl<Args, Ret> IntoKey for CallKey<Args, Ret> {
+    type Args = Typed<Args>;
+    type Ret = Typed<Ret>;
+
+    fn into_key(self) -> String {
+        self.key.to_string()
+    }
+}
@@ -40,10 +40,7 @@
 //!
 //! impl TrackedStruct {
 //!     fn tracked_fn(&self, arg: String) {
-//!         let call_info = CallInfo {
-//!             arguments: Some(Box::new(arg)),
-//!             returned: None
-//!         };
+//!         let call_info = CallInfo::new().with_args(arg);
 //!         self.0.log_call("my_fn", call_info);
 //!     }
 //! }
@@ -58,7 +55,14 @@
 //!     .with("Test".to_string());
 //! ```
 
+pub mod check;
+pub mod checked;
+pub mod depth;
+pub mod key;
+pub mod pattern;
+pub mod stub;
 pub mod tracker;
 
-pub use tracker::{Tracker, CallInfo};
-pub use racetrack_proc_macro::track_with;
\ No newline at end of file
+pub use pattern::Wild;
+pub use racetrack_proc_macro::{inject_tracker, mock, track_with, tracked_closure, Tracked};
+pub use tracker::{CallInfo, Checkpoint, Expectation, Tracker};
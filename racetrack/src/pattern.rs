@@ -0,0 +1,148 @@
+//! Argument patterns with wildcards.
+//!
+//! Patterns are built with the [`with_pattern!`](crate::with_pattern) macro and checked with
+//! [`MetaAssertion::with_args_matching`](crate::tracker::MetaAssertion::with_args_matching).
+//! Every position of the pattern is either [`Wild`], which matches any value, or a value that's compared with `==`.
+//! For enums and other values that are easier to describe with a `match` pattern, use
+//! [`with_matches!`](crate::with_matches) instead.
+//!
+//! # Example
+//!
+//! ```
+//! use racetrack::{with_pattern, CallInfo, Tracker};
+//!
+//! let tracker = Tracker::new();
+//! tracker.log_call("connect", CallInfo::new().with_args((42u32, "localhost".to_string())));
+//!
+//! tracker
+//!     .assert_that("connect")
+//!     .was_called_once()
+//!     .with_args_matching(with_pattern!(Wild, "localhost".to_string(); (u32, String)));
+//! ```
+
+/// Matches any value in its position of a pattern.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wild;
+
+/// Matches values equal to the wrapped one. Used by [`with_pattern!`](crate::with_pattern) for every position that
+/// isn't [`Wild`].
+#[derive(Debug, Clone)]
+pub struct Exact<T>(pub T);
+
+/// A pattern that can be matched against logged arguments of type `T`.
+pub trait Pattern<T> {
+    /// Whether `value` matches the pattern
+    fn matches(&self, value: &T) -> bool;
+}
+
+impl<T> Pattern<T> for Wild {
+    fn matches(&self, _value: &T) -> bool {
+        true
+    }
+}
+
+impl<T: PartialEq> Pattern<T> for Exact<T> {
+    fn matches(&self, value: &T) -> bool {
+        self.0 == *value
+    }
+}
+
+macro_rules! tuple_pattern {
+    ($($pattern:ident $value:ident $index:tt),+) => {
+        impl<$($pattern: Pattern<$value>, $value),+> Pattern<($($value,)+)> for ($($pattern,)+) {
+            fn matches(&self, value: &($($value,)+)) -> bool {
+                $(self.$index.matches(&value.$index))&&+
+            }
+        }
+    };
+}
+
+tuple_pattern!(P0 T0 0, P1 T1 1);
+tuple_pattern!(P0 T0 0, P1 T1 1, P2 T2 2);
+tuple_pattern!(P0 T0 0, P1 T1 1, P2 T2 2, P3 T3 3);
+tuple_pattern!(P0 T0 0, P1 T1 1, P2 T2 2, P3 T3 3, P4 T4 4);
+tuple_pattern!(P0 T0 0, P1 T1 1, P2 T2 2, P3 T3 3, P4 T4 4, P5 T5 5);
+tuple_pattern!(P0 T0 0, P1 T1 1, P2 T2 2, P3 T3 3, P4 T4 4, P5 T5 5, P6 T6 6);
+tuple_pattern!(P0 T0 0, P1 T1 1, P2 T2 2, P3 T3 3, P4 T4 4, P5 T5 5, P6 T6 6, P7 T7 7);
+
+/// Build a predicate for [`MetaAssertion::with_args_matching`](crate::tracker::MetaAssertion::with_args_matching)
+/// from a comma separated list of values, where [`Wild`] matches anything in its position.
+///
+/// Wildcards don't say anything about the type in their position, so the type of the arguments usually has to be
+/// given after a semicolon, e.g. `with_pattern!(Wild, "expected".to_string(); (u32, String))`. `_` can be used for
+/// positions that aren't wildcards. A single value matches arguments that were logged without a tuple.
+///
+/// `Wild` is matched by name, so it doesn't need to be imported and can't be written as a path.
+#[macro_export]
+macro_rules! with_pattern {
+    (@collect [$($done:expr),*] Wild ; $ty:ty) => {
+        $crate::with_pattern!(@finish [$($done,)* $crate::pattern::Wild] $ty)
+    };
+    (@collect [$($done:expr),*] Wild $(, $($rest:tt)*)?) => {
+        $crate::with_pattern!(@collect [$($done,)* $crate::pattern::Wild] $($($rest)*)?)
+    };
+    (@collect [$($done:expr),*] $value:expr ; $ty:ty) => {
+        $crate::with_pattern!(@finish [$($done,)* $crate::pattern::Exact($value)] $ty)
+    };
+    (@collect [$($done:expr),*] $value:expr $(, $($rest:tt)*)?) => {
+        $crate::with_pattern!(@collect [$($done,)* $crate::pattern::Exact($value)] $($($rest)*)?)
+    };
+    (@collect [$($done:expr),*] $(; $ty:ty)?) => {
+        $crate::with_pattern!(@finish [$($done),*] $($ty)?)
+    };
+    (@finish [$single:expr] $($ty:ty)?) => {
+        $crate::with_pattern!(@predicate $single, $($ty)?)
+    };
+    (@finish [$($done:expr),*] $($ty:ty)?) => {
+        $crate::with_pattern!(@predicate ($($done,)*), $($ty)?)
+    };
+    (@predicate $pattern:expr, $ty:ty) => {{
+        let pattern = $pattern;
+        move |args: &$ty| $crate::pattern::Pattern::matches(&pattern, args)
+    }};
+    (@predicate $pattern:expr,) => {
+        $crate::with_pattern!(@predicate $pattern, _)
+    };
+    ($($pattern:tt)+) => {
+        $crate::with_pattern!(@collect [] $($pattern)+)
+    };
+}
+
+/// Build a predicate for [`MetaAssertion::with_args_matching`](crate::tracker::MetaAssertion::with_args_matching)
+/// from a pattern like the ones used in `match` and [`matches!`], e.g. to check the variant of an enum argument
+/// without comparing its fields.
+///
+/// The type of the arguments can't be inferred from the pattern, so it always has to be given after a semicolon, e.g.
+/// `with_matches!(Event::Click { .. }; Event)`. Patterns can have guards, like
+/// `with_matches!(Event::Click { x, .. } if *x > 10; Event)`.
+///
+/// # Example
+///
+/// ```
+/// use racetrack::{with_matches, CallInfo, Tracker};
+///
+/// enum Event {
+///     Click { x: i32, y: i32 },
+///     Key(char)
+/// }
+///
+/// let tracker = Tracker::new();
+/// tracker.log_call("handle", CallInfo::new().with_args(Event::Key('a')));
+///
+/// tracker
+///     .assert_that("handle")
+///     .was_called_once()
+///     .with_args_matching(with_matches!(Event::Key(_); Event));
+/// ```
+#[macro_export]
+macro_rules! with_matches {
+    (@collect [$($pattern:tt)*] ; $ty:ty) => {
+        |args: &$ty| matches!(args, $($pattern)*)
+    };
+    (@collect [$($pattern:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::with_matches!(@collect [$($pattern)* $next] $($rest)*)
+    };
+    ($($pattern:tt)+) => {
+        $crate::with_matches!(@collect [] $($pattern)+)
+    };
+}
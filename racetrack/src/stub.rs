@@ -0,0 +1,66 @@
+//! Stubbed return values for mocks.
+//!
+//! Mocks generated with [`mock`](crate::mock) return the value stubbed for each key with [`Tracker::when`]. Methods
+//! without a stub return their `Default` value if the return type has one, and panic otherwise.
+
+use crate::tracker::Tracker;
+use std::marker::PhantomData;
+
+/// Configures the value returned for a key. See [`Tracker::when`].
+#[must_use = "nothing is stubbed until a return value is set"]
+pub struct Stub<'a> {
+    pub(crate) tracker: &'a Tracker,
+    pub(crate) key: String
+}
+
+impl Stub<'_> {
+    /// Return a clone of `value` from every following call to the key, replacing any value stubbed before.
+    /// The type must match the return type of the mocked method exactly, e.g. `42u64` for a method returning `u64`.
+    pub fn return_value<T: Clone + Send + Sync + 'static>(self, value: T) {
+        self.tracker.stub(self.key, Box::new(value));
+    }
+}
+
+/// The value a mock returns without a stub, resolved with autoref specialization.
+/// `(&&Fallback::<R>::new()).fallback(key)` returns `R::default()` if `R` implements `Default`, and panics otherwise.
+#[doc(hidden)]
+pub struct Fallback<R>(PhantomData<fn() -> R>);
+
+impl<R> Fallback<R> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<R> Default for Fallback<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[doc(hidden)]
+pub trait DefaultFallback<R> {
+    fn fallback(&self, key: &str) -> R;
+}
+
+impl<R: Default> DefaultFallback<R> for &Fallback<R> {
+    fn fallback(&self, _key: &str) -> R {
+        R::default()
+    }
+}
+
+#[doc(hidden)]
+pub trait PanicFallback<R> {
+    fn fallback(&self, key: &str) -> R;
+}
+
+impl<R> PanicFallback<R> for Fallback<R> {
+    fn fallback(&self, key: &str) -> R {
+        panic!(
+            "No return value was stubbed for {0}, and {1} doesn't implement Default. Stub it with \
+             tracker.when(\"{0}\").return_value(...).",
+            key,
+            std::any::type_name::<R>()
+        )
+    }
+}
@@ -1,21 +1,354 @@
+use crate::{
+    check::{self, CheckError, MetaCheck},
+    checked::Checker,
+    depth::DepthGuard,
+    key::{Accepts, IntoKey, Untyped},
+    stub::Stub
+};
+use parking_lot::{Mutex, RwLock};
 use std::{
     any::Any,
+    cell::RefCell,
     collections::HashMap,
-    sync::Arc
+    fmt::Debug,
+    hash::Hash,
+    iter::Sum,
+    marker::PhantomData,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc
+    },
+    thread::{self, Thread, ThreadId},
+    time::{Duration, Instant}
 };
-use parking_lot::{Mutex, RwLock};
 
 /// Stores call info for the method call.
-/// This is usually constructed via the proc-macro, but can be done manually.
-#[derive(Debug)]
+/// This is usually constructed via the proc-macro, but can be done manually with [`CallInfo::new`] and the `with_*`
+/// methods. New metadata may be added in any release, so it can't be constructed with a struct literal.
+///
+/// # Example
+///
+/// ```
+/// use racetrack::{CallInfo, Tracker};
+///
+/// let tracker = Tracker::new();
+/// tracker.log_call("parse", CallInfo::new().with_args("42".to_string()).with_ret(42u32));
+///
+/// tracker.assert_that("parse").was_called_once().with("42".to_string()).and_returned(42u32);
+/// ```
+#[derive(Debug, Default)]
+#[non_exhaustive]
 pub struct CallInfo {
     /// The boxed arguments as a tuple
     pub arguments: Option<Box<dyn Any + Send + Sync>>,
     /// The boxed return value
-    pub returned: Option<Box<dyn Any + Send + Sync>>
+    pub returned: Option<Box<dyn Any + Send + Sync>>,
+    /// A boxed clone of the receiver, taken before the call. Only recorded by the proc macro with `capture_self`.
+    pub receiver: Option<Box<dyn Any + Send + Sync>>,
+    /// The recursion depth of the call, starting at 1 for the outermost call. 0 if the depth wasn't recorded.
+    /// See [`depth`](crate::depth) for details.
+    pub depth: usize,
+    /// The thread the call was logged from. Filled in by [`log_call`](Tracker::log_call) if missing.
+    ///
+    /// Under async, this is the executor thread that polled the call, not the logical task.
+    pub thread_id: Option<ThreadId>,
+    /// A handle to the thread the call was logged from. Filled in together with `thread_id`.
+    /// The handle is reference counted, so recording it doesn't copy the thread's name. See
+    /// [`thread_name`](CallInfo::thread_name).
+    pub thread: Option<Thread>,
+    /// The position of the call across all keys of the tracker. Always set by [`log_call`](Tracker::log_call).
+    pub sequence: usize,
+    /// Where the call was made. Filled in by [`log_call`](Tracker::log_call) with its caller if missing.
+    ///
+    /// The proc macro records the location it's expanded in, which is inside the tracked function. To record where the
+    /// tracked function itself was called from, mark it `#[track_caller]`.
+    pub location: Option<&'static Location<'static>>,
+    /// When the call was logged. Filled in by [`log_call`](Tracker::log_call) if missing.
+    pub timestamp: Option<Instant>
 }
 
-type Calls = Arc<RwLock<Vec<CallInfo>>>;
+impl CallInfo {
+    /// A call without arguments or a return value. [`log_call`](Tracker::log_call) fills in the thread, sequence,
+    /// location and timestamp when it's logged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `arguments` as the arguments of the call. Use a tuple for several arguments.
+    pub fn with_args<T: Any + Send + Sync>(mut self, arguments: T) -> Self {
+        self.arguments = Some(Box::new(arguments));
+        self
+    }
+
+    /// Record `returned` as the return value of the call.
+    pub fn with_ret<T: Any + Send + Sync>(mut self, returned: T) -> Self {
+        self.returned = Some(Box::new(returned));
+        self
+    }
+
+    /// Record when the call was made instead of when it's logged.
+    pub fn with_timestamp(mut self, timestamp: Instant) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// The logged arguments, if they were logged as a `T`. Functions with several arguments log them as a tuple, and
+    /// functions with one argument log it on its own.
+    /// Returns `None` if no arguments were logged or they have a different type.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::CallInfo;
+    ///
+    /// let call = CallInfo::new().with_args((1u32, "a".to_string()));
+    /// assert_eq!(call.args::<(u32, String)>(), Some(&(1, "a".to_string())));
+    /// assert_eq!(call.args::<u32>(), None);
+    /// ```
+    pub fn args<T: 'static>(&self) -> Option<&T> {
+        self.arguments.as_ref()?.downcast_ref()
+    }
+
+    /// The logged return value, if it was logged as a `T`.
+    /// Returns `None` if no return value was logged or it has a different type.
+    pub fn ret<T: 'static>(&self) -> Option<&T> {
+        self.returned.as_ref()?.downcast_ref()
+    }
+
+    /// The name of the thread the call was logged from, if it has one.
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread.as_ref()?.name()
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Arc<Tracker>> = RefCell::new(Tracker::new());
+}
+
+/// Restores the previous current tracker on drop, so [`Tracker::scoped`] is panic safe.
+struct ScopeGuard(Option<Arc<Tracker>>);
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+}
+
+/// The calls of one key. Each call is shared, so checks can take a snapshot and release the lock before running
+/// user code.
+type Calls = Arc<RwLock<Vec<Arc<CallInfo>>>>;
+type CallMap = Arc<Mutex<HashMap<String, Calls>>>;
+
+/// How assertions report failures and what their messages include. See [`AssertionBuilder`].
+#[derive(Clone)]
+pub(crate) struct Settings {
+    /// Where failures are collected instead of panicking, if they are
+    failures: Option<Arc<Mutex<Vec<CheckError>>>>,
+    /// Suggest the closest logged key for keys that were never logged
+    suggestions: bool,
+    /// Append the recorded calls to the failure messages
+    summaries: bool,
+    /// Merge the calls of every key that matches ignoring case
+    ignore_case: bool
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            failures: None,
+            suggestions: true,
+            summaries: true,
+            ignore_case: false
+        }
+    }
+}
+
+/// The calls logged for a key, restricted to the calls logged after a checkpoint.
+#[derive(Clone)]
+pub(crate) struct CallSet {
+    /// The calls of every key that matched, more than one only when ignoring case
+    calls: Vec<Calls>,
+    since: usize,
+    /// All calls of the tracker, used to suggest similar keys
+    keys: CallMap,
+    /// Whether the tracker is logging calls
+    enabled: Arc<AtomicBool>,
+    settings: Settings
+}
+
+impl CallSet {
+    /// The calls logged for `key` in `keys` after the sequence number `since`.
+    fn new(
+        keys: &CallMap,
+        enabled: &Arc<AtomicBool>,
+        key: &str,
+        since: usize,
+        settings: Settings
+    ) -> Self {
+        let mut calls = Self::lookup(&keys.lock(), key, settings.ignore_case);
+        if calls.is_empty() {
+            calls.push(Arc::new(RwLock::new(Vec::new())));
+        }
+        Self {
+            calls,
+            since,
+            keys: keys.clone(),
+            enabled: enabled.clone(),
+            settings
+        }
+    }
+
+    /// Pick up the calls for `key` if it was first logged after this set was created
+    fn reload(&mut self, key: &str) {
+        let calls = Self::lookup(&self.keys.lock(), key, self.settings.ignore_case);
+        if !calls.is_empty() {
+            self.calls = calls;
+        }
+    }
+
+    /// The calls logged for `key`, or for every key that matches it ignoring case
+    fn lookup(keys: &HashMap<String, Calls>, key: &str, ignore_case: bool) -> Vec<Calls> {
+        if !ignore_case {
+            return keys.get(key).cloned().into_iter().collect();
+        }
+        let key = key.to_lowercase();
+        keys.iter()
+            .filter(|(logged, _)| logged.to_lowercase() == key)
+            .map(|(_, calls)| calls.clone())
+            .collect()
+    }
+
+    /// Run `f` on the calls logged since the checkpoint. The calls of several keys are merged in the order they were
+    /// logged. Each key is only locked while its calls are collected, so `f` runs without any of the tracker's locks
+    /// and can call back into the tracker, e.g. from a predicate that calls a tracked function.
+    fn with_calls<T>(&self, f: impl FnOnce(&[&CallInfo]) -> T) -> T {
+        let since = self.since;
+        let mut snapshot = Vec::new();
+        for calls in &self.calls {
+            let calls = calls.read();
+            let after = calls.partition_point(|call_info| call_info.sequence < since);
+            snapshot.extend(calls[after..].iter().cloned());
+        }
+        // Each key's calls are already in order, so only the calls of several keys need sorting
+        if self.calls.len() > 1 {
+            snapshot.sort_by_key(|call_info| call_info.sequence);
+        }
+        let calls: Vec<&CallInfo> = snapshot.iter().map(|call_info| &**call_info).collect();
+        f(&calls)
+    }
+
+    /// Run `check` on the calls. If it fails and `key` was never logged, suggest the closest key that was.
+    /// If it fails while the tracker is disabled, the failure says so, since the calls it needed may not have been
+    /// logged. Checks that only pass because there are no calls, like `wasnt_called`, fail while the tracker is
+    /// disabled, since the calls they rule out may just not have been logged.
+    pub(crate) fn check<T>(
+        &self,
+        key: &str,
+        check: impl FnOnce(&[&CallInfo], &str) -> Result<T, CheckError>
+    ) -> Result<T, CheckError> {
+        let enabled = self.enabled.load(Ordering::SeqCst);
+        let result = self.with_calls(|calls| match check(calls, key) {
+            Ok(_) if calls.is_empty() && !enabled => {
+                Err(CheckError::new(format!("{} wasn't called.", key)))
+            }
+            result => result
+        });
+        result.map_err(|err| {
+            let err = if self.settings.summaries {
+                err
+            } else {
+                err.without_summary()
+            };
+            let err = if enabled {
+                err
+            } else {
+                err.append(format!(
+                    " Tracking is disabled, so calls to {} may not have been logged. Enable it with \
+                     Tracker::set_enabled before asserting.",
+                    key
+                ))
+            };
+            if !self.settings.suggestions {
+                return err;
+            }
+            let keys = self.keys.lock();
+            if !Self::lookup(&keys, key, self.settings.ignore_case).is_empty() {
+                return err;
+            }
+            match check::closest_key(key, keys.keys().map(String::as_str)) {
+                Some(suggestion) => err.append(format!(" Did you mean '{}'?", suggestion)),
+                None => err
+            }
+        })
+    }
+
+    /// Run `check` like [`check`](CallSet::check), and panic if it fails, or collect the failure if the settings say
+    /// so. Collected failures return the default value.
+    pub(crate) fn assert<T: Default>(
+        &self,
+        key: &str,
+        check: impl FnOnce(&[&CallInfo], &str) -> Result<T, CheckError>
+    ) -> T {
+        self.report(self.check(key, check))
+    }
+
+    /// Unwrap the result of a check, panicking or collecting the failure like [`assert`](CallSet::assert)
+    pub(crate) fn report<T: Default>(&self, result: Result<T, CheckError>) -> T {
+        result.unwrap_or_else(|err| match self.settings.failures {
+            Some(ref failures) => {
+                failures.lock().push(err);
+                T::default()
+            }
+            None => err.raise()
+        })
+    }
+}
+
+/// A position in the call history of a tracker. See [`Tracker::checkpoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Checkpoint(usize);
+
+/// The tracker of a struct with [`inject_tracker`](crate::inject_tracker), `None` until it's set with the generated
+/// `set_tracker`. It compares equal and hashes the same regardless of the tracker, so it doesn't change the derived
+/// traits of the struct.
+#[doc(hidden)]
+#[derive(Clone, Default)]
+pub struct TrackerSlot(pub Option<Arc<Tracker>>);
+
+impl Debug for TrackerSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TrackerSlot")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl PartialEq for TrackerSlot {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for TrackerSlot {}
+
+impl PartialOrd for TrackerSlot {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TrackerSlot {
+    fn cmp(&self, _other: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl Hash for TrackerSlot {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
 
 /// The main tracker class.
 /// Construct this in each test if possible, otherwise use a static copy.
@@ -51,7 +384,10 @@ type Calls = Arc<RwLock<Vec<CallInfo>>>;
 ///
 #[derive(Debug)]
 pub struct Tracker {
-    calls: Arc<Mutex<HashMap<String, Calls>>>
+    calls: CallMap,
+    sequence: AtomicUsize,
+    enabled: Arc<AtomicBool>,
+    stubs: Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>
 }
 
 impl Tracker {
@@ -59,23 +395,240 @@ impl Tracker {
     /// This allows for use of the tracker in multi-threaded/tasked scenarios.
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
-            calls: Arc::new(Mutex::new(HashMap::new()))
+            calls: Arc::new(Mutex::new(HashMap::new())),
+            sequence: AtomicUsize::new(0),
+            enabled: Arc::new(AtomicBool::new(true)),
+            stubs: Mutex::new(HashMap::new())
         })
     }
 
+    /// The current tracker of this thread. This is the tracker of the innermost [`scoped`](Tracker::scoped) call, or
+    /// a tracker that lives as long as the thread outside of them.
+    ///
+    /// Track statics with it to isolate their calls per test, e.g. `#[track_with(racetrack::Tracker::current())]`.
+    pub fn current() -> Arc<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Run `f` with a fresh tracker that's the [`current`](Tracker::current) tracker until `f` returns.
+    /// The previous tracker is restored afterwards, even if `f` panics.
+    ///
+    /// # Warning
+    ///
+    /// The current tracker is a thread local, so calls made on other threads aren't logged to the scoped tracker.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{track_with, Tracker};
+    ///
+    /// #[track_with(racetrack::Tracker::current())]
+    /// fn tracked_fn(arg: u32) {}
+    ///
+    /// Tracker::scoped(|tracker| {
+    ///     tracked_fn(1);
+    ///     tracker.assert_that("tracked_fn").was_called_once().with(1u32);
+    /// });
+    /// Tracker::scoped(|tracker| tracker.assert_that("tracked_fn").wasnt_called());
+    /// ```
+    pub fn scoped<R>(f: impl FnOnce(&Arc<Tracker>) -> R) -> R {
+        let tracker = Tracker::new();
+        let previous = CURRENT.with(|current| current.replace(tracker.clone()));
+        let _guard = ScopeGuard(Some(previous));
+        f(&tracker)
+    }
+
     /// Start an assertion chain.
+    /// If an assertion fails because the key was never logged, the failure message suggests the closest key that was.
+    ///
     /// # Arguments
     ///
-    /// * `item` - The key of the method for which assertions should be made. e.g. "Tracked::tracked_method"
-    pub fn assert_that(&self, item: impl Into<String>) -> Assertion {
-        let key = item.into();
-        let calls = self.calls.lock();
-        let item = if let Some(calls) = calls.get(&key) {
-            calls.clone()
-        } else {
-            Arc::new(RwLock::new(Vec::new()))
+    /// * `item` - The key of the method for which assertions should be made. e.g. "Tracked::tracked_method".
+    ///   This can also be a [`CallKey`](crate::key::CallKey), which lets the assertions infer the argument and return
+    ///   types.
+    pub fn assert_that<K: IntoKey>(&self, item: K) -> Assertion<K::Args, K::Ret> {
+        self.assert_with(item, Settings::default())
+    }
+
+    /// Start an assertion chain like [`assert_that`](Tracker::assert_that), but match keys ignoring case.
+    /// If several keys only differ in case, e.g. "Parser::parse" and "parser::parse", their calls are merged as if
+    /// they were logged under one key.
+    ///
+    /// # Warning
+    ///
+    /// This is a convenience for exploring and debugging. It can hide typos in keys, so prefer
+    /// [`assert_that`](Tracker::assert_that) in tests that are meant to stay.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("Parser::parse", CallInfo::default());
+    /// tracker.log_call("parser::parse", CallInfo::default());
+    ///
+    /// tracker.assert_that_ci("PARSER::PARSE").was_called_times(2);
+    /// ```
+    pub fn assert_that_ci<K: IntoKey>(&self, item: K) -> Assertion<K::Args, K::Ret> {
+        let settings = Settings {
+            ignore_case: true,
+            ..Settings::default()
         };
-        Assertion { item, key }
+        self.assert_with(item, settings)
+    }
+
+    fn assert_with<K: IntoKey>(&self, item: K, settings: Settings) -> Assertion<K::Args, K::Ret> {
+        let key = item.into_key();
+        Assertion {
+            item: CallSet::new(&self.calls, &self.enabled, &key, 0, settings),
+            key,
+            timeout: None,
+            types: PhantomData
+        }
+    }
+
+    /// Configure how assertions behave once, and start them from the returned builder with
+    /// [`AssertionBuilder::assert_that`] instead of passing the same options to every assertion.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("connect", CallInfo::default());
+    ///
+    /// let asserter = tracker.asserter().collect_failures(true).suggestions(false);
+    /// asserter.assert_that("connect").was_called_times(2);
+    /// asserter.assert_that("conect").was_called_once();
+    ///
+    /// let failures = asserter.failures();
+    /// assert_eq!(failures.len(), 2);
+    /// assert_eq!(failures[1].message(), "conect wasn't called.");
+    /// ```
+    pub fn asserter(&self) -> AssertionBuilder<'_> {
+        AssertionBuilder {
+            tracker: self,
+            settings: Settings::default()
+        }
+    }
+
+    /// Start an assertion chain for `item` like [`assert_that`](Tracker::assert_that), but every step returns a
+    /// `Result` that can be propagated with `?`, e.g. in a test that returns a `Result`. See [`checked`](crate::checked).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{checked::AssertionError, CallInfo, Tracker};
+    ///
+    /// # fn main() -> Result<(), AssertionError> {
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("connect", CallInfo::new().with_args(443u16));
+    ///
+    /// tracker.check("connect").called_once()?.with(443u16)?;
+    /// tracker.check("disconnect").not_called()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn check<K: IntoKey>(&self, item: K) -> Checker<K::Args, K::Ret> {
+        Checker(self.assert_that(item))
+    }
+
+    /// Mark the current position in the call history.
+    /// Assertions can be restricted to calls logged after the checkpoint with [`Assertion::since`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("setup", CallInfo::default());
+    /// let checkpoint = tracker.checkpoint();
+    /// tracker.log_call("setup", CallInfo::default());
+    ///
+    /// tracker.assert_that("setup").was_called_times(2);
+    /// tracker.assert_since(&checkpoint, "setup").was_called_once();
+    /// ```
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.sequence.load(Ordering::SeqCst))
+    }
+
+    /// Start an assertion chain that only considers calls logged after `checkpoint`.
+    /// Shorthand for `assert_that(item).since(checkpoint)`.
+    pub fn assert_since<K: IntoKey>(
+        &self,
+        checkpoint: &Checkpoint,
+        item: K
+    ) -> Assertion<K::Args, K::Ret> {
+        self.assert_that(item).since(checkpoint)
+    }
+
+    /// Require that each of `items` was called exactly once.
+    /// Unlike a chain of [`was_called_once`](Assertion::was_called_once) assertions, every key is checked before
+    /// failing, and the failure message lists each key that wasn't called exactly once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("open", CallInfo::default());
+    /// tracker.log_call("close", CallInfo::default());
+    ///
+    /// tracker.assert_all_called_once(&["open", "close"]);
+    /// ```
+    pub fn assert_all_called_once(&self, items: &[&str]) {
+        self.check_all_called_once(items)
+            .unwrap_or_else(CheckError::raise);
+    }
+
+    /// Check that each of `items` was called exactly once. This is the non-panicking version of
+    /// [`assert_all_called_once`](Tracker::assert_all_called_once).
+    pub fn check_all_called_once(&self, items: &[&str]) -> Result<(), CheckError> {
+        let failures: Vec<_> = items
+            .iter()
+            .filter_map(|item| self.assert_that(*item).check_called_once().err())
+            .collect();
+        if failures.is_empty() {
+            return Ok(());
+        }
+        let mut message = format!(
+            "{} of {} methods weren't called exactly once:",
+            failures.len(),
+            items.len()
+        );
+        for failure in failures {
+            message.push('\n');
+            message.push_str(&failure.to_string());
+        }
+        Err(CheckError::new(message))
+    }
+
+    /// Expect calls to `item` that are verified when the returned [`Expectation`] is dropped.
+    /// Only calls logged after this are counted. By default, at least one call is expected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// let _expectation = tracker.expect("my_fn").times(2);
+    ///
+    /// tracker.log_call("my_fn", CallInfo::default());
+    /// tracker.log_call("my_fn", CallInfo::default());
+    /// ```
+    pub fn expect(&self, item: impl Into<String>) -> Expectation {
+        Expectation {
+            calls: self.calls.clone(),
+            enabled: self.enabled.clone(),
+            since: self.checkpoint().0,
+            key: item.into(),
+            times: None
+        }
     }
 
     /// Log a call to the tracker.
@@ -85,20 +638,330 @@ impl Tracker {
     ///
     /// * `key` - The key for the method. e.g. Tracked::tracked_method
     /// * `call_info` - The call info for the call. May or may not contain arguments and return values.
-    pub fn log_call(&self, key: impl Into<String>, call_info: CallInfo) {
+    ///
+    /// # Panic safety
+    ///
+    /// The tracker's locks don't poison, and no user code runs while they're held. Assertions release them before
+    /// running predicates, comparisons or `Debug` impls, and stubbed values are cloned outside the lock. So a panic in
+    /// a tracked body, in another thread or in a failed assertion never leaves the tracker in a broken state, and
+    /// predicates can call tracked code without deadlocking. Later calls are still logged and later assertions still
+    /// work. The proc macro logs calls after the body returns, so calls that panic aren't logged.
+    ///
+    /// Returns the [`sequence`](CallInfo::sequence) of the call, or `None` if tracking is disabled.
+    #[track_caller]
+    pub fn log_call(&self, key: impl Into<String>, mut call_info: CallInfo) -> Option<usize> {
+        if !self.enabled.load(Ordering::SeqCst) {
+            return None;
+        }
+        if call_info.timestamp.is_none() {
+            call_info.timestamp = Some(Instant::now());
+        }
+        if call_info.location.is_none() {
+            call_info.location = Some(Location::caller());
+        }
+        if call_info.thread_id.is_none() {
+            let thread = thread::current();
+            call_info.thread_id = Some(thread.id());
+            call_info.thread = Some(thread);
+        }
+
+        // Only hold the map while looking up the key, so waiting for the key doesn't block logging to other keys
+        let calls = self.calls.lock().entry(key.into()).or_default().clone();
+        let mut calls = calls.write();
+        // Take the sequence under the key's lock, so the calls of each key stay in order
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+        call_info.sequence = sequence;
+        calls.push(Arc::new(call_info));
+        Some(sequence)
+    }
+
+    /// Record the return value of a call that was logged before it returned, like the proc macro does with
+    /// `log_before`. Does nothing if no call with `sequence` was logged under `key`, e.g. because the tracker was
+    /// cleared in the meantime. If an assertion is reading the call at the same time, this waits until it's done.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key the call was logged under.
+    /// * `sequence` - The sequence returned by [`log_call`](Tracker::log_call).
+    /// * `returned` - The boxed return value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// let sequence = tracker.log_call("my_fn", CallInfo::default());
+    /// tracker.assert_that("my_fn").was_called_once();
+    ///
+    /// if let Some(sequence) = sequence {
+    ///     tracker.log_return("my_fn", sequence, Some(Box::new(5u32)));
+    /// }
+    /// tracker.assert_that("my_fn").was_called_once().and_returned(5u32);
+    /// ```
+    pub fn log_return(
+        &self,
+        key: &str,
+        sequence: usize,
+        returned: Option<Box<dyn Any + Send + Sync>>
+    ) {
+        let calls = match self.calls.lock().get(key) {
+            Some(calls) => calls.clone(),
+            None => return
+        };
+        loop {
+            let mut calls = calls.write();
+            let call_info =
+                match calls.binary_search_by_key(&sequence, |call_info| call_info.sequence) {
+                    Ok(index) => &mut calls[index],
+                    Err(_) => return
+                };
+            // Assertions only share the call while they run, so it's ours again once they're done
+            if let Some(call_info) = Arc::get_mut(call_info) {
+                call_info.returned = returned;
+                return;
+            }
+            drop(calls);
+            thread::yield_now();
+        }
+    }
+
+    /// Wrap `f` so every call to it is logged under `key`. This is the manual counterpart to tracking a closure with
+    /// the proc macro, for callbacks that are passed to the code under test mid-expression.
+    /// The argument and return value are captured with `clone`, and the calls record the location `spy_fn` was called
+    /// from.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::Tracker;
+    ///
+    /// let tracker = Tracker::new();
+    /// let doubled: Vec<_> = vec![1, 2].into_iter().map(tracker.spy_fn("double", |x: i32| x * 2)).collect();
+    ///
+    /// assert_eq!(doubled, vec![2, 4]);
+    /// tracker.assert_that("double").was_called_times(2).with(1).and_returned(4);
+    /// ```
+    #[track_caller]
+    pub fn spy_fn<'a, A, R, F>(&'a self, key: impl Into<String>, f: F) -> impl Fn(A) -> R + 'a
+    where
+        A: Clone + Send + Sync + 'static,
+        R: Clone + Send + Sync + 'static,
+        F: Fn(A) -> R + 'a
+    {
         let key = key.into();
-        let mut calls = self.calls.lock();
-        if let Some(call_infos) = calls.get(&key) {
-            let mut call_infos = call_infos.write();
-            call_infos.push(call_info);
-        } else {
-            calls.insert(key, Arc::new(RwLock::new(vec![call_info])));
+        let location = Location::caller();
+        move |args| {
+            let depth = DepthGuard::enter(key.clone());
+            let arguments = args.clone();
+            let returned = f(args);
+            self.log_call(
+                key.as_str(),
+                CallInfo {
+                    arguments: Some(Box::new(arguments)),
+                    returned: Some(Box::new(returned.clone())),
+                    depth: depth.depth(),
+                    location: Some(location),
+                    ..Default::default()
+                }
+            );
+            returned
+        }
+    }
+
+    /// Count the calls to `item` that were logged in the window from `start` (inclusive) to `end` (exclusive).
+    /// Adjacent windows never count a call twice. Returns 0 if `item` was never logged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let tracker = Tracker::new();
+    /// let start = Instant::now();
+    /// tracker.log_call("request", CallInfo::default());
+    ///
+    /// assert_eq!(tracker.calls_in_window("request", start, start + Duration::from_secs(60)), 1);
+    /// ```
+    pub fn calls_in_window(&self, item: impl AsRef<str>, start: Instant, end: Instant) -> usize {
+        let calls = match self.calls.lock().get(item.as_ref()) {
+            Some(calls) => calls.clone(),
+            None => return 0
+        };
+        let calls = calls.read();
+        calls
+            .iter()
+            .filter_map(|call_info| call_info.timestamp)
+            .filter(|timestamp| *timestamp >= start && *timestamp < end)
+            .count()
+    }
+
+    /// The arguments of the most recent call to `item`, or `None` if it was never called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last call didn't log any arguments, or if they aren't a `T`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// for volume in 1..=3u32 {
+    ///     tracker.log_call("set_volume", CallInfo::new().with_args(volume));
+    /// }
+    ///
+    /// assert_eq!(tracker.last_call_args::<u32>("set_volume"), Some(3));
+    /// assert_eq!(tracker.last_call_args::<u32>("mute"), None);
+    /// ```
+    pub fn last_call_args<T: Clone + 'static>(&self, item: impl AsRef<str>) -> Option<T> {
+        let key = item.as_ref();
+        // Clone outside the lock, in case Clone calls back into the tracker
+        let last = self.calls.lock().get(key)?.read().last()?.clone();
+        let cast = check::last_args::<T>(&last, key).unwrap_or_else(CheckError::raise);
+        Some(cast.clone())
+    }
+
+    /// Whether every call to `item` logged its arguments. This is false if `item` was never called, so a `true` means
+    /// assertions like [`with`](MetaAssertion::with) won't fail because the arguments are missing. Calls logged by the
+    /// proc macro always capture their arguments unless `capture_args` is false, but calls logged manually with
+    /// [`log_call`](Tracker::log_call) may not.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("flush", CallInfo::new().with_args(true));
+    /// assert!(tracker.captures_arguments("flush"));
+    ///
+    /// tracker.log_call("flush", CallInfo::default());
+    /// assert!(!tracker.captures_arguments("flush"));
+    /// assert!(!tracker.captures_arguments("sync"));
+    /// ```
+    pub fn captures_arguments(&self, item: impl AsRef<str>) -> bool {
+        self.all_calls(item.as_ref(), |call_info| call_info.arguments.is_some())
+    }
+
+    /// Whether every call to `item` logged its return value. This is false if `item` was never called. See
+    /// [`captures_arguments`](Tracker::captures_arguments).
+    ///
+    /// Calls logged with `log_before` only have their return value once they've returned.
+    pub fn captures_returns(&self, item: impl AsRef<str>) -> bool {
+        self.all_calls(item.as_ref(), |call_info| call_info.returned.is_some())
+    }
+
+    /// Whether `key` was called and every call satisfies `predicate`
+    fn all_calls(&self, key: &str, predicate: impl Fn(&CallInfo) -> bool) -> bool {
+        let calls = match self.calls.lock().get(key) {
+            Some(calls) => calls.clone(),
+            None => return false
+        };
+        let calls = calls.read();
+        !calls.is_empty() && calls.iter().all(|call_info| predicate(call_info))
+    }
+
+    /// A snapshot of the number of calls logged for each key.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("read", CallInfo::default());
+    /// tracker.log_call("read", CallInfo::default());
+    /// tracker.log_call("write", CallInfo::default());
+    ///
+    /// let histogram = tracker.histogram();
+    /// assert_eq!(histogram["read"], 2);
+    /// assert_eq!(histogram["write"], 1);
+    /// ```
+    pub fn histogram(&self) -> HashMap<String, usize> {
+        self.calls
+            .lock()
+            .iter()
+            .map(|(key, calls)| (key.clone(), calls.read().len()))
+            .collect()
+    }
+
+    /// Stub the value returned by calls to `item` on mocks generated with [`mock`](crate::mock).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::Tracker;
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.when("Clock::now").return_value(42u64);
+    ///
+    /// assert_eq!(tracker.stubbed::<u64>("Clock::now"), Some(42));
+    /// ```
+    pub fn when(&self, item: impl Into<String>) -> Stub<'_> {
+        Stub {
+            tracker: self,
+            key: item.into()
         }
     }
 
-    /// Clear the tracker completely
+    /// A clone of the value stubbed for `item` with [`when`](Tracker::when), or `None` if nothing was stubbed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stubbed value isn't a `T`.
+    pub fn stubbed<T: Clone + 'static>(&self, item: impl AsRef<str>) -> Option<T> {
+        let key = item.as_ref();
+        // Clone outside the lock, in case Clone calls back into the tracker
+        let value = self.stubs.lock().get(key)?.clone();
+        let value = value.downcast_ref::<T>().unwrap_or_else(|| {
+            panic!(
+                "The value stubbed for {} isn't of type {}.",
+                key,
+                std::any::type_name::<T>()
+            )
+        });
+        Some(value.clone())
+    }
+
+    pub(crate) fn stub(&self, key: String, value: Box<dyn Any + Send + Sync>) {
+        self.stubs.lock().insert(key, value.into());
+    }
+
+    /// Turn logging on or off. While the tracker is disabled, [`log_call`](Tracker::log_call) returns immediately
+    /// without logging anything. Assertions still check the calls logged while the tracker was enabled, and failures
+    /// mention that tracking is disabled, since the calls they needed may not have been logged. Assertions that would
+    /// pass because there are no calls, like [`wasnt_called`](Assertion::wasnt_called) or `was_called_times(0)`, fail
+    /// while the tracker is disabled, since the calls they rule out may just not have been logged.
+    /// Calls logged while the tracker was enabled are kept. Trackers start out enabled.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.set_enabled(false);
+    /// tracker.log_call("my_fn", CallInfo::default());
+    /// tracker.set_enabled(true);
+    ///
+    /// tracker.assert_that("my_fn").wasnt_called();
+    /// ```
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Whether the tracker is logging calls. See [`set_enabled`](Tracker::set_enabled).
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Clear the tracker completely, including stubbed return values
     pub fn clear(&self) {
         self.calls.lock().clear();
+        self.stubs.lock().clear();
     }
 
     /// Print the call info for a specific method. To print the whole tracker, use debug format.
@@ -111,112 +974,307 @@ impl Tracker {
     }
 }
 
-/// An assertion object
-pub struct Assertion {
-    item: Calls,
-    key: String
+/// An assertion object.
+/// `Args` and `Ret` restrict the types that can be asserted on if the assertion was started with a
+/// [`CallKey`](crate::key::CallKey).
+///
+/// # Recorded calls
+///
+/// Failure messages list the recorded calls, but only values that were captured as strings can be printed, which is
+/// what `capture = "debug"` does. With the default `to_owned` and `clone` captures, each value is shown as `..`, and
+/// the list is left out entirely if no call has a printable value. To see the calls in failure messages, track the
+/// item with `#[track_with(tracker, capture = "debug")]` or log the arguments as a `String`. The list can be turned off
+/// with [`call_summaries`](AssertionBuilder::call_summaries).
+pub struct Assertion<Args = Untyped, Ret = Untyped> {
+    item: CallSet,
+    key: String,
+    timeout: Option<Duration>,
+    types: PhantomData<fn() -> (Args, Ret)>
 }
 
-impl Assertion {
+/// How long [`Assertion::eventually`] waits between checks
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+impl<Args, Ret> Assertion<Args, Ret> {
+    /// Only consider calls logged after `checkpoint`.
+    pub fn since(mut self, checkpoint: &Checkpoint) -> Self {
+        self.item.since = checkpoint.0;
+        self
+    }
+
+    /// Wait up to `timeout` for the expected number of calls, for calls made on other threads or tasks.
+    /// The calls are checked repeatedly until the count matches, or fails immediately once there are more calls than
+    /// expected.
+    ///
+    /// This only affects [`was_called_once`](Assertion::was_called_once),
+    /// [`was_called_times`](Assertion::was_called_times) and their non-panicking versions, since more calls can't make
+    /// [`wasnt_called`](Assertion::wasnt_called) pass. The assertions on the returned [`MetaAssertion`] run once the
+    /// count matched and don't wait.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    /// use std::{thread, time::Duration};
+    ///
+    /// let tracker = Tracker::new();
+    /// let background = tracker.clone();
+    /// thread::spawn(move || background.log_call("sync", CallInfo::default()));
+    ///
+    /// tracker
+    ///     .assert_that("sync")
+    ///     .eventually(Duration::from_secs(5))
+    ///     .was_called_once();
+    /// ```
+    pub fn eventually(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run a check that passes once the method was called `n` times, waiting for it if there's a timeout
+    fn check_count<T>(
+        &mut self,
+        n: usize,
+        check: impl Fn(&[&CallInfo], &str) -> Result<T, CheckError>
+    ) -> Result<T, CheckError> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let result = self.item.check(&self.key, &check);
+            match deadline {
+                Some(deadline) if result.is_err() && Instant::now() < deadline => {
+                    // More calls won't make the count go down
+                    if self.item.with_calls(|calls| calls.len()) > n {
+                        return result;
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                    self.item.reload(&self.key);
+                }
+                _ => return result
+            }
+        }
+    }
+
     /// Require that the method was called exactly once.
     /// Returns an object that lets you assert more detailed metadata.
-    pub fn was_called_once(self) -> MetaAssertion {
-        {
-            let item = self.item.read();
-            assert_ne!(item.len(), 0, "{} wasn't called.", self.key);
-            assert_eq!(
-                item.len(),
-                1,
-                "{} was called more than once. Was called {} times.",
-                self.key,
-                item.len()
-            );
-        }
+    pub fn was_called_once(mut self) -> MetaAssertion<Args, Ret> {
+        let result = self.check_count(1, check::called_once);
+        self.item.report(result);
         MetaAssertion {
             item: self.item,
-            key: self.key
+            key: self.key,
+            types: PhantomData
         }
     }
 
     /// Require that the method was called exactly `n` times.
     /// Returns an object that lets you assert more detailed metadata.
-    pub fn was_called_times(self, n: usize) -> MetaAssertion {
-        {
-            let item = self.item.read();
-            assert_ne!(
-                item.len(),
-                0,
-                "{} should've been called {} times, but wasn't called.",
-                self.key,
-                n
-            );
-            assert!(
-                item.len() >= n,
-                "{} was called fewer than {} times. Was called {} times.",
-                self.key,
-                n,
-                item.len()
-            );
-            assert_eq!(
-                item.len(),
-                n,
-                "{} was called more than {} times. Was called {} times.",
-                self.key,
-                n,
-                item.len()
-            );
-        }
+    ///
+    /// Calling this with `n == 0` behaves exactly like [`wasnt_called`](Assertion::wasnt_called).
+    pub fn was_called_times(mut self, n: usize) -> MetaAssertion<Args, Ret> {
+        let result = self.check_count(n, |calls, key| check::called_times(calls, key, n));
+        self.item.report(result);
         MetaAssertion {
             item: self.item,
-            key: self.key
+            key: self.key,
+            types: PhantomData
         }
     }
 
     /// Require that the method wasn't called. Ends the assertion chain.
     pub fn wasnt_called(self) {
-        let item = self.item.read();
-        let len = item.len();
-        assert_eq!(
-            len, 0,
-            "{} should not have been called but was called {} times.",
-            self.key, len
-        );
+        self.item.assert(&self.key, check::not_called);
+    }
+
+    /// Check that the method was called exactly once. This is the non-panicking version of
+    /// [`was_called_once`](Assertion::was_called_once).
+    pub fn check_called_once(mut self) -> Result<MetaCheck, CheckError> {
+        self.check_count(1, check::called_once)?;
+        Ok(MetaCheck {
+            item: self.item,
+            key: self.key
+        })
+    }
+
+    /// Check that the method was called exactly `n` times. This is the non-panicking version of
+    /// [`was_called_times`](Assertion::was_called_times).
+    pub fn check_called_times(mut self, n: usize) -> Result<MetaCheck, CheckError> {
+        self.check_count(n, |calls, key| check::called_times(calls, key, n))?;
+        Ok(MetaCheck {
+            item: self.item,
+            key: self.key
+        })
+    }
+
+    /// Check that the method wasn't called. This is the non-panicking version of
+    /// [`wasnt_called`](Assertion::wasnt_called).
+    pub fn check_not_called(self) -> Result<(), CheckError> {
+        self.item.check(&self.key, check::not_called)
+    }
+}
+
+/// Starts assertions that share their settings. See [`Tracker::asserter`].
+pub struct AssertionBuilder<'a> {
+    tracker: &'a Tracker,
+    settings: Settings
+}
+
+impl AssertionBuilder<'_> {
+    /// Collect failed assertions instead of panicking, so every failure of a test can be reported at once with
+    /// [`finish`](AssertionBuilder::finish). The rest of an assertion chain still runs after a failure.
+    /// Defaults to false.
+    pub fn collect_failures(mut self, collect: bool) -> Self {
+        self.settings.failures = if collect {
+            Some(Arc::new(Mutex::new(Vec::new())))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Suggest the closest key that was logged when asserting on a key that never was. Defaults to true.
+    pub fn suggestions(mut self, enabled: bool) -> Self {
+        self.settings.suggestions = enabled;
+        self
+    }
+
+    /// List the recorded calls in failure messages, for calls captured with `capture = "debug"`. Defaults to true.
+    /// See [recorded calls](Assertion#recorded-calls) for which values can be listed.
+    pub fn call_summaries(mut self, enabled: bool) -> Self {
+        self.settings.summaries = enabled;
+        self
+    }
+
+    /// Start an assertion chain with these settings. See [`Tracker::assert_that`].
+    pub fn assert_that<K: IntoKey>(&self, item: K) -> Assertion<K::Args, K::Ret> {
+        self.tracker.assert_with(item, self.settings.clone())
+    }
+
+    /// The failures collected so far, in the order they happened. Always empty unless
+    /// [`collect_failures`](AssertionBuilder::collect_failures) is set.
+    pub fn failures(&self) -> Vec<CheckError> {
+        match self.settings.failures {
+            Some(ref failures) => failures.lock().clone(),
+            None => Vec::new()
+        }
+    }
+
+    /// Fail with every collected failure, one per line, if there were any.
+    pub fn finish(&self) -> Result<(), CheckError> {
+        let failures = self.failures();
+        if failures.is_empty() {
+            return Ok(());
+        }
+        let messages: Vec<_> = failures.iter().map(ToString::to_string).collect();
+        Err(CheckError::new(messages.join("\n")))
+    }
+}
+
+/// An expected number of calls that is asserted when dropped. See [`Tracker::expect`].
+///
+/// If the thread is already panicking, e.g. because an assertion failed, the expectation isn't checked to avoid a
+/// double panic.
+#[must_use = "the expectation is checked when dropped, bind it to a variable to check it at the end of the scope"]
+pub struct Expectation {
+    calls: CallMap,
+    enabled: Arc<AtomicBool>,
+    since: usize,
+    key: String,
+    /// The expected number of calls. `None` means at least one.
+    times: Option<usize>
+}
+
+impl Expectation {
+    /// Expect exactly `n` calls.
+    pub fn times(mut self, n: usize) -> Self {
+        self.times = Some(n);
+        self
+    }
+
+    /// Expect exactly one call.
+    pub fn once(self) -> Self {
+        self.times(1)
+    }
+
+    /// Expect no calls.
+    pub fn never(self) -> Self {
+        self.times(0)
+    }
+}
+
+impl Drop for Expectation {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            return;
+        }
+        let times = self.times;
+        // The key may not have been logged yet when the expectation was created, so look the calls up now
+        CallSet::new(
+            &self.calls,
+            &self.enabled,
+            &self.key,
+            self.since,
+            Settings::default()
+        )
+        .check(&self.key, |calls, key| match times {
+            Some(n) => check::called_times(calls, key, n),
+            None => check::called(calls, key)
+        })
+        .unwrap_or_else(CheckError::raise);
     }
 }
 
 /// A meta assertion object for asserting additional metadata
-pub struct MetaAssertion {
-    item: Calls,
-    key: String
+pub struct MetaAssertion<Args = Untyped, Ret = Untyped> {
+    item: CallSet,
+    key: String,
+    types: PhantomData<fn() -> (Args, Ret)>
 }
 
-impl MetaAssertion {
+impl<Args, Ret> MetaAssertion<Args, Ret> {
     /// Require that the method was called at least once with `args`.
     /// T must be a tuple of arguments.
     ///
     /// # Warning
     ///
     /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
-    pub fn with<T: PartialEq + 'static>(self, args: T) -> Self {
-        {
-            let item = self.item.read();
-            assert!(item.len() > 0, "{} wasn't called.", self.key);
-            assert!(
-                item.iter().any(|call_info| {
-                    let call_args = call_info.arguments.as_ref().expect(&format!(
-                        "You didn't log any arguments for your calls to {}.",
-                        self.key
-                    ));
-                    let cast = call_args.downcast_ref::<T>().expect(&format!(
-                        "The arguments logged for {} didn't have that type.",
-                        self.key
-                    ));
-                    cast == &args
-                }),
-                "{} wasn't called with the arguments specified.",
-                self.key
-            );
-        }
+    pub fn with<T: PartialEq + 'static>(self, args: T) -> Self
+    where
+        Args: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::called_with(calls, key, &args)
+        });
+        self
+    }
+
+    /// Require that the method was called at least once with arguments equal to `args` according to `eq`.
+    /// This is for arguments that need a custom comparison, like floats with a tolerance or normalized strings.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("scale", CallInfo::new().with_args(0.1 + 0.2));
+    ///
+    /// tracker
+    ///     .assert_that("scale")
+    ///     .was_called_once()
+    ///     .with_eq(0.3, |a: &f64, b: &f64| (a - b).abs() < 1e-9);
+    /// ```
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn with_eq<T: 'static>(self, args: T, eq: impl Fn(&T, &T) -> bool) -> Self
+    where
+        Args: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::called_with_eq(calls, key, &args, eq)
+        });
         self
     }
 
@@ -226,27 +1284,330 @@ impl MetaAssertion {
     /// # Warning
     ///
     /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
-    pub fn not_with<T: PartialEq + 'static>(self, args: T) -> Self {
-        {
-            let item = self.item.read();
-            if item.len() > 0 {
-                assert!(
-                    !item.iter().any(|call_info| {
-                        let call_args = call_info.arguments.as_ref().expect(&format!(
-                            "You didn't log any arguments for your calls to {}.",
-                            self.key
-                        ));
-                        let cast = call_args.downcast_ref::<T>().expect(&format!(
-                            "The arguments logged for {} didn't have that type.",
-                            self.key
-                        ));
-                        cast == &args
-                    }),
-                    "{} was called with the argument when it should'nt have been.",
-                    self.key
-                );
-            }
-        }
+    pub fn not_with<T: PartialEq + 'static>(self, args: T) -> Self
+    where
+        Args: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::not_called_with(calls, key, &args)
+        });
+        self
+    }
+
+    /// Require that the method was called at least once with an `Arc<T>` or `Box<T>` pointing to `value`.
+    /// This is for functions with a single shared argument, e.g. `.with_deref("name")` for a parameter of type
+    /// `Arc<str>`.
+    ///
+    /// `with` compares `Arc` arguments by value too, since that's what `Arc`'s `PartialEq` does, but it needs the
+    /// expected value wrapped in an `Arc` of exactly the logged type.
+    pub fn with_deref<T: PartialEq + ?Sized + 'static>(self, value: &T) -> Self {
+        self.item.assert(&self.key, |calls, key| {
+            check::called_with_deref(calls, key, value)
+        });
+        self
+    }
+
+    /// Require that the method was called at least once on a receiver equal to `receiver` at the start of the call.
+    /// This needs the receiver to be captured with the proc macro's `capture_self` option.
+    pub fn with_receiver<T: PartialEq + 'static>(self, receiver: T) -> Self {
+        self.item.assert(&self.key, |calls, key| {
+            check::called_on(calls, key, &receiver)
+        });
+        self
+    }
+
+    /// Require that the method was called at least once without arguments.
+    /// Tracked functions and methods without parameters besides the receiver log their arguments as `()`, so this is
+    /// the same as `.with(())`.
+    pub fn with_no_args(self) -> Self
+    where
+        Args: Accepts<()>
+    {
+        self.item.assert(&self.key, check::called_with_no_args);
+        self
+    }
+
+    /// Require that the method was called at least once with arguments captured as `args`.
+    /// This is for methods tracked with `capture = "debug"`, which log the `Debug` output of their arguments, e.g.
+    /// `.with_debug(r#"("x", 3)"#)` for a call `f("x", 3)`.
+    pub fn with_debug(self, args: &str) -> Self
+    where
+        Args: Accepts<String>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::called_with_debug(calls, key, args)
+        });
+        self
+    }
+
+    /// Require that the arguments of at least one call satisfy `predicate`.
+    /// T must be a tuple of arguments.
+    ///
+    /// This is mostly used with [`with_pattern!`](crate::with_pattern) to match arguments with [`Wild`](crate::Wild)
+    /// positions, e.g. `.with_args_matching(with_pattern!(Wild, "expected".to_string(); (u32, String)))`.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn with_args_matching<T: 'static>(self, predicate: impl Fn(&T) -> bool) -> Self
+    where
+        Args: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::args_matching(calls, key, predicate)
+        });
+        self
+    }
+
+    /// Require that the arguments of every call satisfy `invariant`, e.g. that the first argument was always less than
+    /// the second. T must be the whole tuple of arguments.
+    /// Unlike [`with_args_matching`](MetaAssertion::with_args_matching), which only needs one matching call, this fails
+    /// on the first call that breaks the invariant.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// for range in &[(1u32, 4u32), (2, 3)] {
+    ///     tracker.log_call("slice", CallInfo::new().with_args(*range));
+    /// }
+    ///
+    /// tracker
+    ///     .assert_that("slice")
+    ///     .was_called_times(2)
+    ///     .with_invariant(|(start, end): &(u32, u32)| start < end);
+    /// ```
+    pub fn with_invariant<T: 'static>(self, invariant: impl Fn(&T) -> bool) -> Self
+    where
+        Args: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::args_invariant(calls, key, invariant)
+        });
+        self
+    }
+
+    /// Require that the calls were made with exactly the arguments in `expected`, in order, so there must be one call
+    /// per element. Fails at the first call with different arguments. This is the ordered counterpart of chaining
+    /// [`with`](MetaAssertion::with), which accepts the arguments of any call.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// for page in 1..=3u32 {
+    ///     tracker.log_call("fetch_page", CallInfo::new().with_args(page));
+    /// }
+    ///
+    /// tracker
+    ///     .assert_that("fetch_page")
+    ///     .was_called_times(3)
+    ///     .with_args_seq(vec![1u32, 2, 3]);
+    /// ```
+    pub fn with_args_seq<T: PartialEq + 'static>(self, expected: Vec<T>) -> Self
+    where
+        Args: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::args_seq(calls, key, &expected)
+        });
+        self
+    }
+
+    /// Map the arguments of every call with `f`, in the order the calls were made.
+    /// This is meant for aggregating over all calls, e.g. `map_args(|args: &(Vec<u8>, usize)| args.1).sum::<usize>()`.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn map_args<T: 'static, U>(&self, f: impl Fn(&T) -> U) -> std::vec::IntoIter<U>
+    where
+        Args: Accepts<T>
+    {
+        self.item
+            .assert(&self.key, |calls, key| check::map_args(calls, key, f))
+            .into_iter()
+    }
+
+    /// Require that the values produced by `f` for the arguments of every call add up to `expected`.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    pub fn assert_args_sum<T: 'static, U>(self, f: impl Fn(&T) -> U, expected: U) -> Self
+    where
+        Args: Accepts<T>,
+        U: Sum + PartialEq + Debug
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::args_sum(calls, key, f, &expected)
+        });
+        self
+    }
+
+    /// Require that no call was made at a recursion depth greater than `n`.
+    pub fn max_depth(self, n: usize) -> Self {
+        self.item
+            .assert(&self.key, |calls, key| check::max_depth(calls, key, n));
+        self
+    }
+
+    /// Require that the calls were made from at least `n` distinct threads.
+    ///
+    /// # Warning
+    ///
+    /// Under async, this counts the executor threads that polled the calls, not logical tasks.
+    pub fn from_distinct_threads(self, n: usize) -> Self {
+        self.item.assert(&self.key, |calls, key| {
+            check::from_distinct_threads(calls, key, n)
+        });
+        self
+    }
+
+    /// Require that the calls were made with exactly `k` distinct arguments, no matter how often each was repeated.
+    /// This is useful for checking that calls are deduplicated or memoized.
+    /// T must be a tuple of arguments.
+    ///
+    /// # Warning
+    ///
+    /// The argument type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// for user in &["ada", "bob", "ada"] {
+    ///     tracker.log_call("load_user", CallInfo::new().with_args(user.to_string()));
+    /// }
+    ///
+    /// tracker
+    ///     .assert_that("load_user")
+    ///     .was_called_times(3)
+    ///     .with_distinct_count::<String>(2);
+    /// ```
+    pub fn with_distinct_count<T: Eq + Hash + Clone + 'static>(self, k: usize) -> Self
+    where
+        Args: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::distinct_count::<T>(calls, key, k)
+        });
+        self
+    }
+
+    /// End this assertion chain and start a new one for `other_key` on `tracker`.
+    /// If this chain was restricted with [`since`](Assertion::since), the new one is restricted to the same checkpoint.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("A", CallInfo::default());
+    /// tracker.log_call("B", CallInfo::default());
+    ///
+    /// tracker
+    ///     .assert_that("A")
+    ///     .was_called_once()
+    ///     .and(&tracker, "B")
+    ///     .was_called_once();
+    /// ```
+    pub fn and<K: IntoKey>(self, tracker: &Tracker, other_key: K) -> Assertion<K::Args, K::Ret> {
+        let mut assertion = tracker.assert_that(other_key);
+        assertion.item.since = self.item.since;
+        assertion
+    }
+
+    /// Require that at least one call satisfies `predicate`.
+    /// This is the most flexible matcher, the predicate gets the raw [`CallInfo`] of every call.
+    /// The arguments and return value are stored as `Any`, so downcast them to the logged types to inspect them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// tracker.log_call("parse", CallInfo::new().with_args("42".to_string()).with_ret(42u32));
+    ///
+    /// tracker
+    ///     .assert_that("parse")
+    ///     .was_called_once()
+    ///     .with_call_matching(|call| {
+    ///         match (call.args::<String>(), call.ret::<u32>()) {
+    ///             (Some(input), Some(output)) => input.parse() == Ok(*output),
+    ///             _ => false
+    ///         }
+    ///     });
+    /// ```
+    pub fn with_call_matching(self, predicate: impl Fn(&CallInfo) -> bool) -> Self {
+        self.item.assert(&self.key, |calls, key| {
+            check::call_matching(calls, key, predicate)
+        });
+        self
+    }
+
+    /// Require that at least one call was made from `file` at `line`. `file` is compared with the output of `file!()`.
+    ///
+    /// # Warning
+    ///
+    /// Tracked functions record their own location unless they're marked `#[track_caller]`. See
+    /// [`CallInfo::location`].
+    pub fn called_from(self, file: &str, line: u32) -> Self {
+        self.item.assert(&self.key, |calls, key| {
+            check::called_from(calls, key, file, line)
+        });
+        self
+    }
+
+    /// Require that the `n`th call, counting from 0, returned `value`.
+    /// Failure messages number the calls from 1, like the recorded calls they list, so `n = 0` is reported as call 1.
+    /// Unlike [`and_returned`](MetaAssertion::and_returned), this doesn't end the assertion chain, so the return values
+    /// can be checked call by call.
+    /// T must be the return type.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use racetrack::{CallInfo, Tracker};
+    ///
+    /// let tracker = Tracker::new();
+    /// for i in 0..3u32 {
+    ///     tracker.log_call("next_id", CallInfo::new().with_ret(i));
+    /// }
+    ///
+    /// tracker
+    ///     .assert_that("next_id")
+    ///     .was_called_times(3)
+    ///     .nth_returned(0, 0u32)
+    ///     .nth_returned(1, 1u32)
+    ///     .nth_returned(2, 2u32);
+    /// ```
+    pub fn nth_returned<T: PartialEq + 'static>(self, n: usize, value: T) -> Self
+    where
+        Ret: Accepts<T>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::nth_returned(calls, key, n, &value)
+        });
         self
     }
 
@@ -256,23 +1617,22 @@ impl MetaAssertion {
     /// # Warning
     ///
     /// The return type must be whatever gets returned by `to_owned`. Usually this is the original type, but things like `&str` become `String`.
-    pub fn and_returned<T: PartialEq + 'static>(self, value: T) {
-        let item = self.item.read();
-        assert!(item.len() > 0, "{} wasn't called.", self.key);
-        assert!(
-            item.iter().any(|call_info| {
-                let call_return = call_info.returned.as_ref().expect(&format!(
-                    "You didn't log any arguments for your calls to {}.",
-                    self.key
-                ));
-                let cast = call_return.downcast_ref::<T>().expect(&format!(
-                    "The arguments logged for {} didn't have that type.",
-                    self.key
-                ));
-                cast == &value
-            }),
-            "{} wasn't called with the arguments specified.",
-            self.key
-        );
+    pub fn and_returned<T: PartialEq + 'static>(self, value: T)
+    where
+        Ret: Accepts<T>
+    {
+        self.item
+            .assert(&self.key, |calls, key| check::returned(calls, key, &value));
+    }
+
+    /// Require that the method returned a value captured as `value` at least once.
+    /// This is for methods tracked with `capture = "debug"`, see [`with_debug`](MetaAssertion::with_debug).
+    pub fn and_returned_debug(self, value: &str)
+    where
+        Ret: Accepts<String>
+    {
+        self.item.assert(&self.key, |calls, key| {
+            check::returned_debug(calls, key, value)
+        });
     }
 }
@@ -0,0 +1,1004 @@
+use racetrack::{checked::AssertionError, with_matches, with_pattern, CallInfo, Tracker};
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    time::{Duration, Instant}
+};
+
+fn log_calls(tracker: &Tracker, key: &str, n: usize) {
+    for i in 0..n {
+        tracker.log_call(
+            key,
+            CallInfo::new()
+                .with_args(format!("({})", i))
+                .with_ret("()".to_string())
+        );
+    }
+}
+
+fn panic_message(f: impl FnOnce()) -> String {
+    let err = catch_unwind(AssertUnwindSafe(f)).expect_err("the assertion should have failed");
+    if let Some(message) = err.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        err.downcast_ref::<&str>().unwrap().to_string()
+    }
+}
+
+#[test]
+fn test_called_times_under() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "under", 1);
+
+    let message = panic_message(|| {
+        tracker.assert_that("under").was_called_times(3);
+    });
+    assert_eq!(
+        message,
+        "under should've been called 3 times, but was called 1 times.\n\
+         Recorded calls:\n  1. arguments: (0), returned: ()"
+    );
+}
+
+#[test]
+fn test_called_times_over() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "over", 3);
+
+    let message = panic_message(|| {
+        tracker.assert_that("over").was_called_times(2);
+    });
+    assert_eq!(
+        message,
+        "over should've been called 2 times, but was called 3 times.\n\
+         Recorded calls:\n  1. arguments: (0), returned: ()\n  \
+         2. arguments: (1), returned: ()\n  3. arguments: (2), returned: ()"
+    );
+}
+
+#[test]
+fn test_called_times_not_called() {
+    let tracker = Tracker::new();
+
+    let message = panic_message(|| {
+        tracker.assert_that("missing").was_called_times(2);
+    });
+    assert_eq!(
+        message,
+        "missing should've been called 2 times, but was called 0 times."
+    );
+}
+
+#[test]
+fn test_called_times_zero() {
+    let tracker = Tracker::new();
+    tracker.assert_that("zero").was_called_times(0);
+
+    log_calls(&tracker, "zero", 1);
+    let times_message = panic_message(|| {
+        tracker.assert_that("zero").was_called_times(0);
+    });
+    let wasnt_called_message = panic_message(|| tracker.assert_that("zero").wasnt_called());
+    assert_eq!(times_message, wasnt_called_message);
+}
+
+#[test]
+fn test_called_times_one() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "one", 1);
+    tracker.assert_that("one").was_called_times(1);
+
+    log_calls(&tracker, "one", 1);
+    let message = panic_message(|| {
+        tracker.assert_that("one").was_called_times(1);
+    });
+    assert_eq!(
+        message,
+        "one should've been called 1 times, but was called 2 times.\n\
+         Recorded calls:\n  1. arguments: (0), returned: ()\n  2. arguments: (0), returned: ()"
+    );
+}
+
+#[test]
+fn test_called_times_without_debug_capture() {
+    let tracker = Tracker::new();
+    tracker.log_call("opaque", CallInfo::new().with_args(1u32));
+
+    let message = panic_message(|| {
+        tracker.assert_that("opaque").was_called_times(2);
+    });
+    assert_eq!(
+        message,
+        "opaque should've been called 2 times, but was called 1 times."
+    );
+}
+
+#[test]
+fn test_args_sum() {
+    let tracker = Tracker::new();
+    for buf in [vec![1u8, 2, 3], vec![4, 5], vec![6]].iter() {
+        tracker.log_call("Writer::write", CallInfo::new().with_args(buf.clone()));
+    }
+
+    let assertion = tracker
+        .assert_that("Writer::write")
+        .was_called_times(3)
+        .assert_args_sum(|buf: &Vec<u8>| buf.len(), 6);
+    assert_eq!(assertion.map_args(|buf: &Vec<u8>| buf.len()).max(), Some(3));
+    assert_eq!(assertion.map_args(|buf: &Vec<u8>| buf.len()).min(), Some(1));
+
+    let err = tracker
+        .assert_that("Writer::write")
+        .check_called_times(3)
+        .and_then(|check| check.args_sum(|buf: &Vec<u8>| buf.len(), 7))
+        .err()
+        .unwrap();
+    assert!(err.message().starts_with(
+        "The arguments of the calls to Writer::write should've added up to 7, but added up to 6."
+    ));
+    let message = panic_message(|| {
+        tracker
+            .assert_that("Writer::write")
+            .was_called_times(3)
+            .assert_args_sum(|buf: &Vec<u8>| buf.len(), 7);
+    });
+    assert_eq!(err.to_string(), message);
+}
+
+#[test]
+fn test_args_sum_wrong_type() {
+    let tracker = Tracker::new();
+    tracker.log_call("Writer::write", CallInfo::new().with_args(vec![1u8]));
+    tracker.log_call("Writer::write", CallInfo::new().with_args("oops"));
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("Writer::write")
+            .was_called_times(2)
+            .assert_args_sum(|buf: &Vec<u8>| buf.len(), 2);
+    });
+    assert_eq!(
+        message,
+        "The arguments logged for call 2 to Writer::write didn't have that type."
+    );
+}
+
+#[test]
+fn test_check_api() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "checked", 1);
+
+    tracker
+        .assert_that("checked")
+        .check_called_once()
+        .and_then(|check| check.with("(0)".to_string()))
+        .and_then(|check| check.not_with("(1)".to_string()))
+        .and_then(|check| check.and_returned("()".to_string()))
+        .unwrap();
+    tracker.assert_that("unchecked").check_not_called().unwrap();
+
+    let err = tracker
+        .assert_that("checked")
+        .check_called_times(2)
+        .err()
+        .unwrap();
+    let message = panic_message(|| {
+        tracker.assert_that("checked").was_called_times(2);
+    });
+    assert_eq!(err.to_string(), message);
+
+    let err = tracker
+        .assert_that("checked")
+        .check_called_once()
+        .and_then(|check| check.with(1u32))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.message(),
+        "The arguments logged for checked didn't have that type."
+    );
+
+    let err = tracker
+        .assert_that("checked")
+        .check_called_once()
+        .and_then(|check| check.and_returned("(0)".to_string()))
+        .unwrap_err();
+    assert_eq!(err.message(), "checked never returned the value specified.");
+    let err = tracker
+        .assert_that("checked")
+        .check_called_once()
+        .and_then(|check| check.and_returned(0u32))
+        .unwrap_err();
+    assert_eq!(
+        err.message(),
+        "The return values logged for checked didn't have that type."
+    );
+}
+
+#[test]
+fn test_distinct_threads() {
+    let tracker = Tracker::new();
+    let threads: Vec<_> = (0..3)
+        .map(|i| {
+            let tracker = tracker.clone();
+            std::thread::Builder::new()
+                .name(format!("worker-{}", i))
+                .spawn(move || log_calls(&tracker, "threaded", 2))
+                .unwrap()
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    tracker
+        .assert_that("threaded")
+        .was_called_times(6)
+        .from_distinct_threads(3)
+        .with_call_matching(|call| call.thread_name() == Some("worker-2"));
+    let message = panic_message(|| {
+        tracker
+            .assert_that("threaded")
+            .was_called_times(6)
+            .from_distinct_threads(4);
+    });
+    assert_eq!(
+        message,
+        "threaded should've been called from at least 4 distinct threads, but was called from 3."
+    );
+}
+
+#[test]
+fn test_checkpoints() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "setup", 2);
+    let first = tracker.checkpoint();
+    log_calls(&tracker, "setup", 1);
+    let second = tracker.checkpoint();
+    log_calls(&tracker, "other", 1);
+
+    tracker.assert_that("setup").was_called_times(3);
+    tracker
+        .assert_that("setup")
+        .since(&first)
+        .was_called_once()
+        .with("(0)".to_string());
+    tracker.assert_since(&second, "setup").wasnt_called();
+    tracker.assert_since(&first, "other").was_called_once();
+    assert!(tracker.assert_that("setup").check_called_once().is_err());
+}
+
+#[test]
+fn test_suggest_similar_key() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "Tracked::tracked_method", 1);
+    log_calls(&tracker, "Tracked::other_method", 1);
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("Tracked::trackd_method")
+            .was_called_once();
+    });
+    assert_eq!(
+        message,
+        "Tracked::trackd_method wasn't called. Did you mean 'Tracked::tracked_method'?"
+    );
+
+    let message = panic_message(|| {
+        tracker.assert_that("Unrelated::key").was_called_once();
+    });
+    assert_eq!(message, "Unrelated::key wasn't called.");
+
+    let checkpoint = tracker.checkpoint();
+    let message = panic_message(|| {
+        tracker
+            .assert_since(&checkpoint, "Tracked::tracked_method")
+            .was_called_once();
+    });
+    assert_eq!(message, "Tracked::tracked_method wasn't called.");
+}
+
+#[test]
+fn test_and() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "first", 1);
+    let checkpoint = tracker.checkpoint();
+    log_calls(&tracker, "first", 1);
+    log_calls(&tracker, "second", 2);
+
+    tracker
+        .assert_that("first")
+        .was_called_times(2)
+        .and(&tracker, "second")
+        .was_called_times(2)
+        .with("(1)".to_string())
+        .and(&tracker, "third")
+        .wasnt_called();
+    tracker
+        .assert_since(&checkpoint, "first")
+        .was_called_once()
+        .and(&tracker, "second")
+        .was_called_times(2);
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("first")
+            .was_called_times(2)
+            .and(&tracker, "second")
+            .was_called_once();
+    });
+    assert!(message.starts_with("second was called more than once. Was called 2 times."));
+}
+
+#[test]
+fn test_called_from() {
+    let tracker = Tracker::new();
+    let line = line!() + 1;
+    tracker.log_call("located", CallInfo::default());
+
+    tracker
+        .assert_that("located")
+        .was_called_once()
+        .called_from(file!(), line);
+    let message = panic_message(|| {
+        tracker
+            .assert_that("located")
+            .was_called_once()
+            .called_from(file!(), line + 1);
+    });
+    assert_eq!(
+        message,
+        format!(
+            "located wasn't called from {0}:{1}. Was called from {0}:{2}.",
+            file!(),
+            line + 1,
+            line
+        )
+    );
+}
+
+#[test]
+fn test_call_matching() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "matched", 2);
+
+    let argument = |call: &CallInfo| {
+        call.arguments
+            .as_ref()
+            .and_then(|args| args.downcast_ref::<String>())
+            .cloned()
+    };
+    tracker
+        .assert_that("matched")
+        .was_called_times(2)
+        .with_call_matching(|call| argument(call).as_deref() == Some("(1)"));
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("matched")
+            .was_called_times(2)
+            .with_call_matching(|call| call.depth > 0);
+    });
+    assert_eq!(
+        message,
+        "None of the calls to matched matched the predicate.\n\
+         Recorded calls:\n  1. arguments: (0), returned: ()\n  2. arguments: (1), returned: ()"
+    );
+}
+
+#[test]
+fn test_expect() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "expected", 1);
+    {
+        let _twice = tracker.expect("expected").times(2);
+        let _any = tracker.expect("other");
+        let _never = tracker.expect("never").never();
+        log_calls(&tracker, "expected", 2);
+        log_calls(&tracker, "other", 3);
+    }
+
+    let message = panic_message(|| {
+        let _once = tracker.expect("expected").once();
+    });
+    assert_eq!(
+        message,
+        "expected should've been called 1 times, but was called 0 times."
+    );
+    let message = panic_message(|| {
+        let _any = tracker.expect("missing");
+    });
+    assert_eq!(message, "missing wasn't called.");
+
+    // A failing expectation doesn't panic again while unwinding from another panic
+    let message = panic_message(|| {
+        let _once = tracker.expect("expected").once();
+        panic!("first panic");
+    });
+    assert_eq!(message, "first panic");
+}
+
+#[test]
+fn test_args_pattern() {
+    let tracker = Tracker::new();
+    tracker.log_call(
+        "connect",
+        CallInfo::new().with_args((7u32, "localhost".to_string(), 80u16))
+    );
+    tracker.log_call("single", CallInfo::new().with_args(5u32));
+
+    tracker
+        .assert_that("connect")
+        .was_called_once()
+        .with_args_matching(with_pattern!(Wild, "localhost".to_string(), 80u16; (u32, _, _)))
+        .with_args_matching(with_pattern!(7u32, Wild, Wild; (_, String, u16)))
+        .with_args_matching(with_pattern!(7u32, "localhost".to_string(), 80u16));
+    tracker
+        .assert_that("single")
+        .was_called_once()
+        .with_args_matching(with_pattern!(5u32))
+        .with_args_matching(with_pattern!(Wild; u32));
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("connect")
+            .was_called_once()
+            .with_args_matching(
+                with_pattern!(Wild, "remote".to_string(), Wild; (u32, String, u16))
+            );
+    });
+    assert_eq!(
+        message,
+        "connect wasn't called with arguments matching the pattern."
+    );
+}
+
+#[test]
+fn test_calls_in_window() {
+    let tracker = Tracker::new();
+    let start = Instant::now();
+    let at = |millis| start + Duration::from_millis(millis);
+    for millis in [0, 100, 200, 1000, 1500].iter() {
+        tracker.log_call("request", CallInfo::new().with_timestamp(at(*millis)));
+    }
+
+    assert_eq!(tracker.calls_in_window("request", at(0), at(1000)), 3);
+    assert_eq!(tracker.calls_in_window("request", at(1000), at(2000)), 2);
+    assert_eq!(tracker.calls_in_window("request", at(100), at(200)), 1);
+    assert_eq!(tracker.calls_in_window("missing", at(0), at(2000)), 0);
+
+    // Rate limit: no more than 3 calls in any 1 second window starting at a call
+    for millis in [0, 100, 200, 1000, 1500].iter() {
+        assert!(tracker.calls_in_window("request", at(*millis), at(millis + 1000)) <= 3);
+    }
+
+    log_calls(&tracker, "now", 1);
+    assert_eq!(
+        tracker.calls_in_window("now", start, Instant::now() + Duration::from_secs(1)),
+        1
+    );
+}
+
+fn apply_twice(f: impl Fn(i32) -> i32, value: i32) -> i32 {
+    f(f(value))
+}
+
+#[test]
+fn test_spy_fn() {
+    let tracker = Tracker::new();
+    let line = line!() + 1;
+    let result = apply_twice(tracker.spy_fn("increment", |value: i32| value + 1), 1);
+
+    assert_eq!(result, 3);
+    tracker
+        .assert_that("increment")
+        .was_called_times(2)
+        .with(1)
+        .with(2)
+        .called_from(file!(), line)
+        .and_returned(3);
+}
+
+#[test]
+fn test_set_enabled() {
+    let tracker = Tracker::new();
+    assert!(tracker.is_enabled());
+    log_calls(&tracker, "toggled", 1);
+
+    tracker.set_enabled(false);
+    log_calls(&tracker, "toggled", 2);
+    let message = panic_message(|| tracker.assert_that("toggled").wasnt_called());
+    assert!(message.starts_with(
+        "toggled should not have been called but was called 1 times. \
+         Tracking is disabled, so calls to toggled may not have been logged. \
+         Enable it with Tracker::set_enabled before asserting.\nRecorded calls:"
+    ));
+    // Checks that only pass without calls can't tell whether calls were missed
+    log_calls(&tracker, "muted", 1);
+    let message = panic_message(|| tracker.assert_that("muted").wasnt_called());
+    assert_eq!(
+        message,
+        "muted wasn't called. Tracking is disabled, so calls to muted may not have been logged. \
+         Enable it with Tracker::set_enabled before asserting."
+    );
+    let message = panic_message(|| {
+        tracker.assert_that("muted").was_called_times(0);
+    });
+    assert!(message.starts_with("muted wasn't called. Tracking is disabled"));
+    assert!(tracker.assert_that("muted").check_not_called().is_err());
+    // Checks that pass on the calls that were logged still pass
+    tracker
+        .assert_that("toggled")
+        .was_called_once()
+        .not_with("(1)".to_string());
+
+    tracker.set_enabled(true);
+    log_calls(&tracker, "toggled", 1);
+    tracker.assert_that("toggled").was_called_times(2);
+}
+
+#[test]
+fn test_nth_returned() {
+    let tracker = Tracker::new();
+    for i in 0..3u32 {
+        tracker.log_call("counter", CallInfo::new().with_ret(i));
+    }
+
+    tracker
+        .assert_that("counter")
+        .was_called_times(3)
+        .nth_returned(0, 0u32)
+        .nth_returned(1, 1u32)
+        .nth_returned(2, 2u32);
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("counter")
+            .was_called_times(3)
+            .nth_returned(3, 3u32);
+    });
+    assert_eq!(message, "counter was called 3 times, so there's no call 4.");
+    let message = panic_message(|| {
+        tracker
+            .assert_that("counter")
+            .was_called_times(3)
+            .nth_returned(1, 2u32);
+    });
+    assert_eq!(message, "Call 2 to counter returned a different value.");
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Click { x: i32, y: i32 },
+    Key(char)
+}
+
+#[test]
+fn test_args_matches() {
+    let tracker = Tracker::new();
+    for event in [Event::Click { x: 20, y: 5 }, Event::Key('q')] {
+        tracker.log_call("handle", CallInfo::new().with_args(event));
+    }
+
+    tracker
+        .assert_that("handle")
+        .was_called_times(2)
+        .with_args_matching(with_matches!(Event::Click { .. }; Event))
+        .with_args_matching(with_matches!(Event::Click { x, .. } if *x > 10; Event))
+        .with_args_matching(with_matches!(Event::Key('q' | 'Q'); Event));
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("handle")
+            .was_called_times(2)
+            .with_args_matching(with_matches!(Event::Click { y: 0, .. }; Event));
+    });
+    assert_eq!(
+        message,
+        "handle wasn't called with arguments matching the pattern."
+    );
+}
+
+#[test]
+fn test_all_called_once() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "start", 1);
+    log_calls(&tracker, "stop", 2);
+    tracker.assert_all_called_once(&["start"]);
+
+    let message = panic_message(|| {
+        tracker.assert_all_called_once(&["start", "stop", "restart"]);
+    });
+    assert_eq!(
+        message,
+        "2 of 3 methods weren't called exactly once:\n\
+         stop was called more than once. Was called 2 times.\n\
+         Recorded calls:\n  1. arguments: (0), returned: ()\n  2. arguments: (1), returned: ()\n\
+         restart wasn't called. Did you mean 'start'?"
+    );
+    assert!(tracker.check_all_called_once(&["start"]).is_ok());
+}
+
+#[test]
+fn test_eventually() {
+    let tracker = Tracker::new();
+    let background = tracker.clone();
+    let handle = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        log_calls(&background, "flush", 2);
+    });
+
+    tracker
+        .assert_that("flush")
+        .eventually(Duration::from_secs(10))
+        .was_called_times(2)
+        .with("(1)".to_string());
+    handle.join().unwrap();
+
+    let start = Instant::now();
+    let message = panic_message(|| {
+        tracker
+            .assert_that("flush")
+            .eventually(Duration::from_secs(10))
+            .was_called_once();
+    });
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(message.starts_with("flush was called more than once. Was called 2 times."));
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("never")
+            .eventually(Duration::from_millis(20))
+            .was_called_once();
+    });
+    assert_eq!(message, "never wasn't called.");
+}
+
+#[test]
+fn test_last_call_args() {
+    let tracker = Tracker::new();
+    assert_eq!(tracker.last_call_args::<String>("resize"), None);
+
+    log_calls(&tracker, "resize", 3);
+    assert_eq!(
+        tracker.last_call_args::<String>("resize"),
+        Some("(2)".to_string())
+    );
+
+    let message = panic_message(|| {
+        tracker.last_call_args::<u32>("resize");
+    });
+    assert_eq!(
+        message,
+        "The arguments logged for the last call to resize weren't of type u32."
+    );
+}
+
+#[test]
+fn test_with_eq() {
+    let tracker = Tracker::new();
+    tracker.log_call("move_to", CallInfo::new().with_args((0.1f64 + 0.2, 1.0f64)));
+    let close =
+        |a: &(f64, f64), b: &(f64, f64)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9;
+
+    tracker
+        .assert_that("move_to")
+        .was_called_once()
+        .with_eq((0.3, 1.0), close);
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("move_to")
+            .was_called_once()
+            .with_eq((0.4, 1.0), close);
+    });
+    assert_eq!(
+        message,
+        "move_to wasn't called with the arguments specified."
+    );
+    assert!(tracker
+        .assert_that("move_to")
+        .check_called_once()
+        .and_then(|check| check.with_eq((0.3, 1.0), close))
+        .is_ok());
+}
+
+#[test]
+fn test_histogram() {
+    let tracker = Tracker::new();
+    assert!(tracker.histogram().is_empty());
+
+    log_calls(&tracker, "read", 6);
+    log_calls(&tracker, "write", 2);
+    log_calls(&tracker, "flush", 1);
+
+    let histogram = tracker.histogram();
+    assert_eq!(histogram.len(), 3);
+    assert_eq!(histogram["read"], 6);
+    assert_eq!(histogram["write"], 2);
+    assert_eq!(histogram["flush"], 1);
+    assert_eq!(histogram["read"], 3 * histogram["write"]);
+
+    log_calls(&tracker, "flush", 1);
+    assert_eq!(histogram["flush"], 1);
+    assert_eq!(tracker.histogram()["flush"], 2);
+}
+
+#[test]
+fn test_stubs() {
+    let tracker = Tracker::new();
+    assert_eq!(tracker.stubbed::<u64>("Clock::now"), None);
+
+    tracker.when("Clock::now").return_value(1u64);
+    tracker.when("Clock::now").return_value(2u64);
+    assert_eq!(tracker.stubbed::<u64>("Clock::now"), Some(2));
+
+    let message = panic_message(|| {
+        tracker.stubbed::<u32>("Clock::now");
+    });
+    assert_eq!(
+        message,
+        "The value stubbed for Clock::now isn't of type u32."
+    );
+
+    tracker.clear();
+    assert_eq!(tracker.stubbed::<u64>("Clock::now"), None);
+}
+
+#[test]
+fn test_with_invariant() {
+    let tracker = Tracker::new();
+    for range in &[(0u32, 3u32), (2, 8), (5, 6)] {
+        tracker.log_call("copy_range", CallInfo::new().with_args(*range));
+    }
+    let ordered = |(start, end): &(u32, u32)| start < end;
+
+    tracker
+        .assert_that("copy_range")
+        .was_called_times(3)
+        .with_invariant(ordered);
+    assert!(tracker
+        .assert_that("copy_range")
+        .check_called_times(3)
+        .and_then(|check| check.with_invariant(ordered))
+        .is_ok());
+
+    tracker.log_call("copy_range", CallInfo::new().with_args((4u32, 4u32)));
+    let message = panic_message(|| {
+        tracker
+            .assert_that("copy_range")
+            .was_called_times(4)
+            .with_invariant(ordered);
+    });
+    assert_eq!(
+        message,
+        "The arguments of call 4 to copy_range broke the invariant."
+    );
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("missing")
+            .was_called_times(0)
+            .with_invariant(ordered);
+    });
+    assert_eq!(message, "missing wasn't called.");
+}
+
+#[test]
+fn test_checks_with_question_mark() -> Result<(), AssertionError> {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "render", 2);
+
+    tracker
+        .check("render")
+        .called_times(2)?
+        .with("(1)".to_string())?
+        .and_returned("()".to_string())?;
+    tracker.check("resize").not_called()?;
+
+    let failed = || -> Result<(), AssertionError> {
+        tracker
+            .check("render")
+            .called_times(2)?
+            .with("(5)".to_string())?;
+        panic!("The failed check should have returned early.");
+    };
+    assert_eq!(
+        failed().unwrap_err().message(),
+        "render wasn't called with the arguments specified."
+    );
+    let failed = tracker.check("render").called_once().err().unwrap();
+    assert_eq!(
+        failed.message(),
+        "render was called more than once. Was called 2 times."
+    );
+    assert_eq!(
+        failed.to_string(),
+        failed.message().to_string() + failed.summary()
+    );
+    let boxed: Box<dyn std::error::Error> = failed.clone().into();
+    assert_eq!(boxed.to_string(), failed.to_string());
+    Ok(())
+}
+
+#[test]
+fn test_with_args_seq() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "scroll", 3);
+
+    tracker
+        .assert_that("scroll")
+        .was_called_times(3)
+        .with_args_seq(vec![
+            "(0)".to_string(),
+            "(1)".to_string(),
+            "(2)".to_string(),
+        ]);
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("scroll")
+            .was_called_times(3)
+            .with_args_seq(vec![
+                "(0)".to_string(),
+                "(2)".to_string(),
+                "(1)".to_string(),
+            ]);
+    });
+    assert!(message.starts_with("Call 2 to scroll wasn't made with the arguments specified."));
+    // The number in the message is the one the call is listed under
+    assert!(message.contains("\n  2. arguments: (1), returned: ()"));
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("scroll")
+            .was_called_times(3)
+            .with_args_seq(vec!["(0)".to_string(), "(1)".to_string()]);
+    });
+    assert!(message.starts_with("scroll should've been called 2 times, but was called 3 times."));
+}
+
+#[test]
+fn test_asserter() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "render", 1);
+
+    // Panics by default, like assert_that
+    let asserter = tracker.asserter().call_summaries(false);
+    let message = panic_message(|| {
+        asserter.assert_that("render").was_called_times(2);
+    });
+    assert_eq!(
+        message,
+        "render should've been called 2 times, but was called 1 times."
+    );
+    let message = panic_message(|| {
+        asserter.assert_that("rendr").was_called_once();
+    });
+    assert_eq!(message, "rendr wasn't called. Did you mean 'render'?");
+    assert!(asserter.finish().is_ok());
+
+    let asserter = tracker.asserter().collect_failures(true).suggestions(false);
+    asserter
+        .assert_that("render")
+        .was_called_once()
+        .with("(0)".to_string())
+        .and_returned("()".to_string());
+    assert!(asserter.finish().is_ok());
+
+    asserter.assert_that("render").was_called_times(2);
+    asserter.assert_that("rendr").was_called_once();
+    asserter
+        .assert_that("render")
+        .was_called_once()
+        .with("(1)".to_string())
+        .and_returned("()".to_string());
+    let failures = asserter.failures();
+    assert_eq!(failures.len(), 3);
+    assert_eq!(
+        failures[0].message(),
+        "render should've been called 2 times, but was called 1 times."
+    );
+    assert!(failures[0].summary().starts_with("\nRecorded calls:"));
+    assert_eq!(failures[1].message(), "rendr wasn't called.");
+    assert!(failures[2]
+        .message()
+        .starts_with("render wasn't called with the arguments specified."));
+    let error = asserter.finish().unwrap_err();
+    assert_eq!(error.message().matches(" wasn't called").count(), 2);
+
+    // The assertions of other builders and the tracker still panic
+    panic_message(|| {
+        tracker.assert_that("rendr").was_called_once();
+    });
+}
+
+#[test]
+fn test_with_distinct_count() {
+    let tracker = Tracker::new();
+    for page in &[1u32, 2, 1, 3, 2] {
+        tracker.log_call("fetch", CallInfo::new().with_args(*page));
+    }
+
+    tracker
+        .assert_that("fetch")
+        .was_called_times(5)
+        .with_distinct_count::<u32>(3);
+
+    let message = panic_message(|| {
+        tracker
+            .assert_that("fetch")
+            .was_called_times(5)
+            .with_distinct_count::<u32>(5);
+    });
+    assert_eq!(
+        message,
+        "fetch should've been called with 5 distinct arguments, but was called with 3."
+    );
+}
+
+#[test]
+fn test_captures() {
+    let tracker = Tracker::new();
+    log_calls(&tracker, "render", 2);
+    tracker.log_call("poll", CallInfo::new().with_args(1u32));
+
+    assert!(tracker.captures_arguments("render"));
+    assert!(tracker.captures_returns("render"));
+    assert!(tracker.captures_arguments("poll"));
+    assert!(!tracker.captures_returns("poll"));
+    assert!(!tracker.captures_arguments("missing"));
+    assert!(!tracker.captures_returns("missing"));
+
+    // One call without arguments is enough to make `with` unreliable
+    tracker.log_call("render", CallInfo::default());
+    assert!(!tracker.captures_arguments("render"));
+    assert!(!tracker.captures_returns("render"));
+}
+
+#[test]
+fn test_assert_that_ci() {
+    let tracker = Tracker::new();
+    let log = |key: &str, argument: u32| {
+        tracker.log_call(key, CallInfo::new().with_args(argument));
+    };
+    log("Parser::parse", 1);
+    log("parser::parse", 2);
+    log("Parser::parse", 3);
+    log("Lexer::next", 4);
+
+    tracker
+        .assert_that_ci("PARSER::PARSE")
+        .was_called_times(3)
+        .with_args_seq(vec![1u32, 2, 3]);
+    tracker
+        .assert_that_ci("lexer::NEXT")
+        .was_called_once()
+        .with(4u32);
+    tracker.assert_that_ci("parser::lex").wasnt_called();
+    // Exact lookups still tell the keys apart
+    tracker
+        .assert_that("parser::parse")
+        .was_called_once()
+        .with(2u32);
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        tracker.assert_that_ci("parser::parse").was_called_once();
+    }));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_call_info_accessors() {
+    let call = CallInfo::new()
+        .with_args((1u32, "a".to_string()))
+        .with_ret(2u64);
+    assert_eq!(call.args::<(u32, String)>(), Some(&(1, "a".to_string())));
+    assert_eq!(call.ret::<u64>(), Some(&2));
+
+    // Mismatched types and missing values aren't errors
+    assert_eq!(call.args::<u32>(), None);
+    assert_eq!(call.ret::<u32>(), None);
+    let empty = CallInfo::default();
+    assert_eq!(empty.args::<(u32, String)>(), None);
+    assert_eq!(empty.ret::<u64>(), None);
+}
@@ -1,9 +1,12 @@
-#![allow(unused)]
+#![allow(unused, clippy::double_parens)]
 #![cfg_attr(feature = "nightly", feature(proc_macro_hygiene))]
 
 use racetrack::Tracker;
 use racetrack_proc_macro::track_with;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc
+};
 
 lazy_static::lazy_static! {
     static ref TRACKER: Arc<Tracker> = Tracker::new();
@@ -109,6 +112,134 @@ fn test_track_closure() {
         .and_returned("test".to_string());
 }
 
+#[cfg_attr(feature = "nightly", test)]
+#[cfg(feature = "nightly")]
+fn test_track_closure_cfg() {
+    let tracker = Tracker::new();
+
+    #[track_with(tracker, cfg = "test")]
+    let tracked = |arg: u32| -> u32 { arg };
+    #[track_with(tracker, cfg = "any()")]
+    let untracked = |arg: u32| -> u32 { arg };
+
+    tracked(1);
+    untracked(2);
+
+    tracker.assert_that("tracked").was_called_once().with(1u32);
+    tracker.assert_that("untracked").wasnt_called();
+}
+
+#[cfg_attr(feature = "nightly", test)]
+#[cfg(feature = "nightly")]
+fn test_track_closure_patterns() {
+    let tracker = Tracker::new();
+
+    #[track_with(tracker)]
+    let update =
+        |(x, y): (u32, u32), _: String, name: &str| -> String { format!("{}: {}", name, x + y) };
+
+    update((1, 2), "ignored".to_string(), "p");
+
+    tracker
+        .assert_that("update")
+        .was_called_once()
+        .with(((1u32, 2u32), "p".to_string()))
+        .and_returned("p: 3".to_string());
+}
+
+#[cfg_attr(feature = "nightly", test)]
+#[cfg(feature = "nightly")]
+fn test_track_closure_multiple_args() {
+    let tracker = Tracker::new();
+
+    #[track_with(tracker)]
+    let add = |a: u32, b: u32| a + b;
+    #[track_with(tracker)]
+    let label = |name: String, count: usize, plural: bool| -> String {
+        if plural {
+            format!("{} {}s", count, name)
+        } else {
+            format!("{} {}", count, name)
+        }
+    };
+
+    assert_eq!(add(1, 2), 3);
+    assert_eq!(label("apple".to_string(), 2, true), "2 apples");
+
+    tracker
+        .assert_that("add")
+        .was_called_once()
+        .with((1u32, 2u32))
+        .and_returned(3u32);
+    tracker
+        .assert_that("label")
+        .was_called_once()
+        .with(("apple".to_string(), 2usize, true))
+        .and_returned("2 apples".to_string());
+}
+
+#[cfg_attr(feature = "nightly", test)]
+#[cfg(feature = "nightly")]
+fn test_track_closure_captures() {
+    let tracker = Tracker::new();
+
+    let suffix = "!".to_string();
+    #[track_with(tracker)]
+    let exclaim = move |word: String| -> String { word + &suffix };
+    let mut total = 0;
+    #[track_with(tracker)]
+    let mut add = |value: u32| total += value;
+
+    exclaim("hi".to_string());
+    add(2);
+    add(3);
+    assert_eq!(total, 5);
+
+    tracker
+        .assert_that("exclaim")
+        .was_called_once()
+        .and_returned("hi!".to_string());
+    tracker.assert_that("add").was_called_times(2).with(3u32);
+}
+
+#[cfg_attr(feature = "nightly", test)]
+#[cfg(feature = "nightly")]
+fn test_track_closure_hygiene() {
+    let tracker = Tracker::new();
+
+    #[track_with(tracker)]
+    let run = |args: Vec<String>, tracker: u32| -> usize {
+        let returned = args.len() + tracker as usize;
+        returned
+    };
+
+    run(vec!["a".to_string()], 2);
+
+    tracker
+        .assert_that("run")
+        .was_called_once()
+        .with((vec!["a".to_string()], 2u32))
+        .and_returned(3usize);
+}
+
+#[cfg_attr(feature = "nightly", test)]
+#[cfg(feature = "nightly")]
+fn test_track_async_closure() {
+    let tracker = Tracker::new();
+
+    #[track_with(tracker)]
+    let closure = async |arg: String| -> String { arg.to_lowercase() };
+
+    let returned = futures::executor::block_on(closure("TEST".to_string()));
+
+    assert_eq!(returned, "test");
+    tracker
+        .assert_that("closure")
+        .was_called_once()
+        .with(("TEST".to_string()))
+        .and_returned("test".to_string());
+}
+
 #[test]
 fn test_regression1() {
     #[track_with(TRACKER)]
@@ -133,3 +264,3431 @@ fn test_regression2() {
         .was_called_once()
         .with(("Test".to_owned()));
 }
+
+#[track_with(crate::TRACKER, exclude = "untracked")]
+mod storage {
+    pub const PREFIX: &str = "storage:";
+
+    pub fn save(key: String, value: u32) -> bool {
+        true
+    }
+
+    pub fn load(key: String) -> u32 {
+        42
+    }
+
+    pub fn delete(key: String) {}
+
+    pub fn untracked() {}
+
+    #[track(skip)]
+    pub mod helpers {
+        pub fn normalize(key: String) -> String {
+            key.to_lowercase()
+        }
+    }
+
+    #[racetrack::track_with(crate::TRACKER, namespace = "storage::backup")]
+    pub mod backup {
+        pub fn snapshot(id: u32) {}
+    }
+}
+
+#[test]
+fn test_track_module() {
+    storage::helpers::normalize("A".to_string());
+    storage::backup::snapshot(3);
+    TRACKER
+        .assert_that("storage::helpers::normalize")
+        .wasnt_called();
+    TRACKER.assert_that("helpers::normalize").wasnt_called();
+    TRACKER
+        .assert_that("storage::backup::snapshot")
+        .was_called_once()
+        .with(3u32);
+
+    storage::save(storage::PREFIX.to_string(), 1);
+    storage::load("a".to_string());
+    storage::delete("a".to_string());
+    storage::untracked();
+
+    TRACKER
+        .assert_that("storage::save")
+        .was_called_once()
+        .with(("storage:".to_string(), 1u32))
+        .and_returned(true);
+    TRACKER
+        .assert_that("storage::load")
+        .was_called_once()
+        .with(("a".to_string()))
+        .and_returned(42u32);
+    TRACKER
+        .assert_that("storage::delete")
+        .was_called_once()
+        .and_returned(());
+    TRACKER.assert_that("storage::untracked").wasnt_called();
+}
+
+#[track_with(TRACKER)]
+fn factorial(n: u64) -> u64 {
+    if n <= 1 {
+        1
+    } else {
+        n * factorial(n - 1)
+    }
+}
+
+#[test]
+fn test_recursion_depth() {
+    factorial(4);
+    let threads: Vec<_> = (0..2)
+        .map(|_| std::thread::spawn(|| factorial(3)))
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    TRACKER
+        .assert_that("factorial")
+        .was_called_times(10)
+        .with(4u64)
+        .and_returned(24u64);
+    TRACKER
+        .assert_that("factorial")
+        .was_called_times(10)
+        .max_depth(4);
+
+    let too_deep = std::panic::catch_unwind(|| {
+        TRACKER
+            .assert_that("factorial")
+            .was_called_times(10)
+            .max_depth(3);
+    });
+    assert!(too_deep.is_err());
+}
+
+struct RenamedStruct(Arc<Tracker>);
+
+#[track_with(0)]
+impl RenamedStruct {
+    #[track_with(rename = "custom_key")]
+    fn renamed_method(&self) {}
+
+    #[track_with(rename = "custom::key")]
+    fn fully_renamed_method(&self) {}
+
+    fn plain_method(&self) {}
+}
+
+#[test]
+fn test_rename_method() {
+    let tracker = Tracker::new();
+    let tracked = RenamedStruct(tracker.clone());
+    tracked.renamed_method();
+    tracked.fully_renamed_method();
+    tracked.plain_method();
+
+    tracker
+        .assert_that("RenamedStruct::custom_key")
+        .was_called_once();
+    tracker.assert_that("custom::key").was_called_once();
+    tracker
+        .assert_that("RenamedStruct::renamed_method")
+        .wasnt_called();
+    tracker
+        .assert_that("RenamedStruct::plain_method")
+        .was_called_once();
+}
+
+#[derive(Clone, PartialEq)]
+struct Point {
+    x: i32,
+    y: i32
+}
+
+struct DestructuringStruct(Arc<Tracker>);
+
+#[track_with(0)]
+impl DestructuringStruct {
+    fn tuple(&self, (a, b): (i32, i32)) -> i32 {
+        a + b
+    }
+
+    #[allow(clippy::toplevel_ref_arg)]
+    fn mixed(&self, mut count: u32, Point { x, y }: Point, ref name: String) -> String {
+        count += 1;
+        format!("{}: {} {} {}", name, count, x, y)
+    }
+
+    fn update(&self, (x, y): (f32, f32), name: String) -> f32 {
+        x + y
+    }
+
+    fn ignored(&self, _: u32, value: u32, _: String) -> u32 {
+        value
+    }
+}
+
+#[test]
+fn test_destructured_params() {
+    let tracker = Tracker::new();
+    let tracked = DestructuringStruct(tracker.clone());
+    tracked.tuple((1, 2));
+    tracked.mixed(1, Point { x: 3, y: 4 }, "p".to_string());
+
+    tracker
+        .assert_that("DestructuringStruct::tuple")
+        .was_called_once()
+        .with((1, 2))
+        .and_returned(3);
+    tracker
+        .assert_that("DestructuringStruct::mixed")
+        .was_called_once()
+        .with((1u32, Point { x: 3, y: 4 }, "p".to_string()))
+        .and_returned("p: 2 3 4".to_string());
+}
+
+#[test]
+fn test_pattern_params() {
+    let tracker = Tracker::new();
+    let tracked = DestructuringStruct(tracker.clone());
+    tracked.update((1.0, 2.0), "p".to_string());
+    tracked.ignored(1, 2, "ignored".to_string());
+
+    // Destructured parameters are captured as a whole
+    tracker
+        .assert_that("DestructuringStruct::update")
+        .was_called_once()
+        .with(((1.0f32, 2.0f32), "p".to_string()))
+        .and_returned(3.0f32);
+    // Wildcard parameters aren't captured
+    tracker
+        .assert_that("DestructuringStruct::ignored")
+        .was_called_once()
+        .with(2u32)
+        .and_returned(2u32);
+}
+
+struct Store<T> {
+    tracker: Arc<Tracker>,
+    items: Vec<T>
+}
+
+#[track_with(tracker)]
+impl<T> Store<T>
+where
+    T: Clone + PartialEq + Send + Sync + 'static
+{
+    fn get(&self, index: usize) -> T {
+        self.items[index].clone()
+    }
+
+    fn contains(&self, item: T) -> bool {
+        self.items.contains(&item)
+    }
+}
+
+#[test]
+fn test_impl_where_clause() {
+    let tracker = Tracker::new();
+    let store = Store {
+        tracker: tracker.clone(),
+        items: vec!["a".to_string(), "b".to_string()]
+    };
+    store.get(1);
+    store.contains("c".to_string());
+
+    tracker
+        .assert_that("Store<T>::get")
+        .was_called_once()
+        .with(1usize)
+        .and_returned("b".to_string());
+    tracker
+        .assert_that("Store<T>::contains")
+        .was_called_once()
+        .with("c".to_string())
+        .and_returned(false);
+}
+
+mod cache {
+    use racetrack::Tracker;
+    use std::sync::Arc;
+
+    pub struct Cache<T> {
+        pub tracker: Arc<Tracker>,
+        pub value: T
+    }
+}
+
+#[track_with(tracker)]
+impl cache::Cache<String> {
+    fn get(&self) -> String {
+        self.value.clone()
+    }
+}
+
+#[track_with(tracker)]
+impl cache::Cache<Vec<Option<u32>>> {
+    fn get(&self) -> Vec<Option<u32>> {
+        self.value.clone()
+    }
+}
+
+#[test]
+fn test_generic_namespace() {
+    let tracker = Tracker::new();
+    let strings = cache::Cache {
+        tracker: tracker.clone(),
+        value: "a".to_string()
+    };
+    let nested = cache::Cache {
+        tracker: tracker.clone(),
+        value: vec![Some(1)]
+    };
+    strings.get();
+    nested.get();
+
+    tracker
+        .assert_that("cache::Cache<String>::get")
+        .was_called_once()
+        .and_returned("a".to_string());
+    tracker
+        .assert_that("cache::Cache<Vec<Option<u32>>>::get")
+        .was_called_once()
+        .and_returned(vec![Some(1u32)]);
+    tracker
+        .assert_that("cache :: Cache < String >::get")
+        .wasnt_called();
+}
+
+trait Named {
+    fn name(&self, suffix: u32) -> String;
+}
+
+trait Labelled {
+    fn name(&self, suffix: u32) -> String;
+}
+
+struct TraitStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, namespace_trait = true)]
+impl Named for TraitStruct {
+    fn name(&self, suffix: u32) -> String {
+        format!("named{}", suffix)
+    }
+}
+
+#[track_with(tracker, namespace_trait = true)]
+impl Labelled for TraitStruct {
+    fn name(&self, suffix: u32) -> String {
+        format!("labelled{}", suffix)
+    }
+}
+
+#[test]
+fn test_trait_impl() {
+    let tracker = Tracker::new();
+    let tracked = TraitStruct {
+        tracker: tracker.clone()
+    };
+    let named: &dyn Named = &tracked;
+    named.name(1);
+    Labelled::name(&tracked, 2);
+
+    tracker
+        .assert_that("<TraitStruct as Named>::name")
+        .was_called_once()
+        .with(1u32)
+        .and_returned("named1".to_string());
+    tracker
+        .assert_that("<TraitStruct as Labelled>::name")
+        .was_called_once()
+        .with(2u32)
+        .and_returned("labelled2".to_string());
+    tracker.assert_that("TraitStruct::name").wasnt_called();
+}
+
+#[track_with(TRACKER)]
+#[track_caller]
+fn located(value: u32) -> u32 {
+    value
+}
+
+#[test]
+fn test_call_site() {
+    let checkpoint = TRACKER.checkpoint();
+    let first_line = line!() + 1;
+    located(1);
+    let second_line = line!() + 1;
+    located(2);
+
+    TRACKER
+        .assert_since(&checkpoint, "located")
+        .was_called_times(2)
+        .called_from(file!(), first_line)
+        .called_from(file!(), second_line);
+}
+
+// Neither the `?Sized` bound nor the `return` in the closure leave the function early, so the body isn't moved into a
+// closure of its own, which would report the closure as the caller
+#[track_with(TRACKER)]
+#[track_caller]
+fn located_without_early_exit(value: u32) -> (u32, u32) {
+    fn size<T: ?Sized>(value: &T) -> usize {
+        std::mem::size_of_val(value)
+    }
+    let double = |value: u32| {
+        if value == 0 {
+            return 0;
+        }
+        value * 2
+    };
+    assert_eq!(size("ab"), 2);
+    (double(value), std::panic::Location::caller().line())
+}
+
+#[test]
+fn test_nested_exits_keep_call_site() {
+    let checkpoint = TRACKER.checkpoint();
+    let line = line!() + 1;
+    let (doubled, caller_line) = located_without_early_exit(2);
+    assert_eq!(doubled, 4);
+    assert_eq!(caller_line, line);
+
+    TRACKER
+        .assert_since(&checkpoint, "located_without_early_exit")
+        .was_called_once()
+        .with(2u32)
+        .called_from(file!(), line)
+        .and_returned((4u32, line));
+}
+
+struct IncludeStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, include = "tracked, other_tracked")]
+impl IncludeStruct {
+    fn tracked(&self) {}
+
+    fn other_tracked(&self, arg: u32) -> u32 {
+        arg
+    }
+
+    fn untracked(&self) {}
+}
+
+#[test]
+fn test_include() {
+    let tracker = Tracker::new();
+    let tracked = IncludeStruct {
+        tracker: tracker.clone()
+    };
+    tracked.tracked();
+    tracked.other_tracked(3);
+    tracked.untracked();
+
+    tracker
+        .assert_that("IncludeStruct::tracked")
+        .was_called_once();
+    tracker
+        .assert_that("IncludeStruct::other_tracked")
+        .was_called_once()
+        .with(3u32);
+    tracker
+        .assert_that("IncludeStruct::untracked")
+        .wasnt_called();
+}
+
+struct ListStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, exclude(untracked, other_untracked))]
+impl ListStruct {
+    fn tracked(&self) {}
+
+    fn untracked(&self) {}
+
+    fn other_untracked(&self) {}
+}
+
+#[track_with(crate::TRACKER, include(included))]
+mod listed {
+    pub fn included(value: u32) -> u32 {
+        value
+    }
+
+    pub fn not_included(value: u32) -> u32 {
+        value
+    }
+}
+
+#[test]
+fn test_list_syntax() {
+    let tracker = Tracker::new();
+    let tracked = ListStruct {
+        tracker: tracker.clone()
+    };
+    tracked.tracked();
+    tracked.untracked();
+    tracked.other_untracked();
+
+    tracker.assert_that("ListStruct::tracked").was_called_once();
+    tracker.assert_that("ListStruct::untracked").wasnt_called();
+    tracker
+        .assert_that("ListStruct::other_untracked")
+        .wasnt_called();
+
+    listed::included(1);
+    listed::not_included(2);
+    TRACKER
+        .assert_that("listed::included")
+        .was_called_once()
+        .with(1u32);
+    TRACKER.assert_that("listed::not_included").wasnt_called();
+}
+
+struct GatedStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, cfg = "test")]
+impl GatedStruct {
+    fn gated(&self, value: u32) -> u32 {
+        value
+    }
+}
+
+// `any()` never holds, so this doesn't need a tracker field
+struct UntrackedStruct;
+
+#[track_with(tracker, cfg = "any()")]
+impl UntrackedStruct {
+    fn untracked(&self, value: u32) -> u32 {
+        value
+    }
+}
+
+#[track_with(TRACKER, cfg = "any()")]
+fn gated_off(value: u32) -> u32 {
+    value
+}
+
+static PAYLOAD_CLONES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, PartialEq)]
+struct Payload(u32);
+
+impl Clone for Payload {
+    fn clone(&self) -> Self {
+        PAYLOAD_CLONES.fetch_add(1, Ordering::SeqCst);
+        Payload(self.0)
+    }
+}
+
+struct GatedFieldStruct {
+    #[cfg(test)]
+    tracker: Option<Arc<Tracker>>
+}
+
+#[track_with(tracker, optional = true, cfg = "test")]
+impl GatedFieldStruct {
+    fn gated(&self, payload: Payload) -> u32 {
+        payload.0
+    }
+}
+
+// The tracker field only exists when the cfg holds
+struct GatedOffFieldStruct {
+    #[cfg(any())]
+    tracker: Option<Arc<Tracker>>
+}
+
+#[track_with(tracker, optional = true, cfg = "any()")]
+impl GatedOffFieldStruct {
+    fn gated(&self, payload: Payload) -> u32 {
+        payload.0
+    }
+}
+
+#[test]
+fn test_cfg_without_overhead() {
+    let tracker = Tracker::new();
+    let untracked = GatedOffFieldStruct {};
+    assert_eq!(untracked.gated(Payload(1)), 1);
+    assert_eq!(PAYLOAD_CLONES.load(Ordering::SeqCst), 0);
+
+    let tracked = GatedFieldStruct {
+        tracker: Some(tracker.clone())
+    };
+    tracked.gated(Payload(2));
+    assert_eq!(PAYLOAD_CLONES.load(Ordering::SeqCst), 1);
+    tracker
+        .assert_that("GatedFieldStruct::gated")
+        .was_called_once()
+        .with(Payload(2));
+    assert_eq!(tracker.histogram().values().sum::<usize>(), 1);
+}
+
+#[test]
+fn test_cfg() {
+    let tracker = Tracker::new();
+    let tracked = GatedStruct {
+        tracker: tracker.clone()
+    };
+    tracked.gated(1);
+    tracker
+        .assert_that("GatedStruct::gated")
+        .was_called_once()
+        .with(1u32);
+
+    assert_eq!(UntrackedStruct.untracked(2), 2);
+    assert_eq!(gated_off(3), 3);
+    TRACKER.assert_that("gated_off").wasnt_called();
+}
+
+struct SkipStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, include = "tracked, skipped")]
+impl SkipStruct {
+    fn tracked(&self) {}
+
+    #[track(skip)]
+    #[inline]
+    fn skipped(&self, value: u32) -> u32 {
+        value
+    }
+}
+
+#[test]
+fn test_skip() {
+    let tracker = Tracker::new();
+    let tracked = SkipStruct {
+        tracker: tracker.clone()
+    };
+    tracked.tracked();
+    tracked.skipped(1);
+
+    tracker.assert_that("SkipStruct::tracked").was_called_once();
+    tracker.assert_that("SkipStruct::skipped").wasnt_called();
+}
+
+struct HelperRenameStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl HelperRenameStruct {
+    #[track(rename = "save_user")]
+    fn save(&self, user: String) {}
+
+    #[track(rename = "Users::load")]
+    fn load(&self) {}
+}
+
+#[track_with(crate::TRACKER)]
+mod renamed_fns {
+    #[track(rename = "store")]
+    pub fn save(value: u32) {}
+
+    #[track(skip)]
+    pub fn skipped() {}
+}
+
+#[track_with(TRACKER, name = "renamed_free_fn")]
+fn free_fn_to_rename(value: u32) -> u32 {
+    value
+}
+
+#[test]
+fn test_helper_rename() {
+    let tracker = Tracker::new();
+    let tracked = HelperRenameStruct {
+        tracker: tracker.clone()
+    };
+    tracked.save("user".to_string());
+    tracked.load();
+
+    tracker
+        .assert_that("HelperRenameStruct::save_user")
+        .was_called_once()
+        .with("user".to_string());
+    tracker
+        .assert_that("HelperRenameStruct::save")
+        .wasnt_called();
+    tracker.assert_that("Users::load").was_called_once();
+    tracker
+        .assert_that("HelperRenameStruct::load")
+        .wasnt_called();
+
+    renamed_fns::save(1);
+    renamed_fns::skipped();
+    free_fn_to_rename(2);
+    TRACKER
+        .assert_that("renamed_fns::store")
+        .was_called_once()
+        .with(1u32);
+    TRACKER.assert_that("renamed_fns::save").wasnt_called();
+    TRACKER.assert_that("renamed_fns::skipped").wasnt_called();
+    TRACKER
+        .assert_that("renamed_free_fn")
+        .was_called_once()
+        .with(2u32);
+    TRACKER.assert_that("free_fn_to_rename").wasnt_called();
+}
+
+struct KeyedStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, keys = "KeyedStructKeys")]
+impl KeyedStruct {
+    fn describe(&self, name: &str, count: u32) -> String {
+        format!("{}: {}", name, count)
+    }
+
+    fn single(&self, value: u32) {}
+
+    fn generic<T: Clone + Send + Sync + 'static>(&self, value: T) -> T {
+        value
+    }
+}
+
+#[test]
+fn test_call_keys() {
+    let tracker = Tracker::new();
+    let tracked = KeyedStruct {
+        tracker: tracker.clone()
+    };
+    tracked.describe("apples", 3);
+    tracked.single(1);
+    tracked.generic(2u8);
+
+    tracker
+        .assert_that(KeyedStructKeys::describe)
+        .was_called_once()
+        .with(("apples".into(), 3))
+        .and_returned("apples: 3".into());
+    tracker
+        .assert_that(KeyedStructKeys::single)
+        .was_called_once()
+        .with(1)
+        .and_returned(());
+    assert_eq!(KeyedStructKeys::single.key(), "KeyedStruct::single");
+    tracker
+        .assert_that(KeyedStructKeys::generic)
+        .was_called_once()
+        .with(2u8);
+}
+
+struct IterStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl IterStruct {
+    #[track(no_return)]
+    fn evens(&self, max: u32) -> impl Iterator<Item = u32> {
+        (0..max).filter(|i| i % 2 == 0)
+    }
+
+    fn count(&self) -> u32 {
+        3
+    }
+}
+
+#[track_with(TRACKER, capture_return = false)]
+fn boxed_error(fail: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if fail {
+        Err("failed".into())
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_no_return() {
+    let tracker = Tracker::new();
+    let tracked = IterStruct {
+        tracker: tracker.clone()
+    };
+    assert_eq!(tracked.evens(5).collect::<Vec<_>>(), vec![0, 2, 4]);
+    tracked.count();
+
+    tracker
+        .assert_that("IterStruct::evens")
+        .was_called_once()
+        .with(5u32);
+    tracker
+        .assert_that("IterStruct::count")
+        .was_called_once()
+        .and_returned(3u32);
+    let err = tracker
+        .assert_that("IterStruct::evens")
+        .check_called_once()
+        .and_then(|check| check.and_returned(()))
+        .unwrap_err();
+    assert_eq!(
+        err.message(),
+        "You didn't log any return values for your calls to IterStruct::evens."
+    );
+
+    assert!(boxed_error(true).is_err());
+    TRACKER
+        .assert_that("boxed_error")
+        .was_called_once()
+        .with(true);
+}
+
+struct Digits {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl Digits {
+    fn of(&self, mut n: u64) -> impl Iterator<Item = u64> {
+        let mut digits = Vec::new();
+        while n > 0 {
+            digits.push(n % 10);
+            n /= 10;
+        }
+        digits.into_iter().rev()
+    }
+
+    // Boxed trait objects aren't opaque, so they still need the override
+    #[track(no_return)]
+    fn formatter(&self, width: usize) -> Box<dyn Fn(u64) -> String> {
+        Box::new(move |n| format!("{:>width$}", n, width = width))
+    }
+}
+
+#[track_with(TRACKER)]
+fn repeated(value: &str, times: usize) -> impl Iterator<Item = String> + '_ {
+    (0..times).map(move |_| value.to_string())
+}
+
+#[test]
+fn test_impl_trait_return() {
+    let tracker = Tracker::new();
+    let digits = Digits {
+        tracker: tracker.clone()
+    };
+    assert_eq!(digits.of(123).collect::<Vec<_>>(), vec![1, 2, 3]);
+    digits.of(45).count();
+
+    tracker
+        .assert_that("Digits::of")
+        .was_called_times(2)
+        .with(123u64)
+        .with(45u64)
+        .with_call_matching(|call| call.returned.is_none());
+    assert_eq!(digits.formatter(3)(7), "  7");
+    tracker
+        .assert_that("Digits::formatter")
+        .was_called_once()
+        .with(3usize);
+
+    assert_eq!(repeated("a", 2).count(), 2);
+    TRACKER
+        .assert_that("repeated")
+        .was_called_once()
+        .with(("a".to_string(), 2usize));
+}
+
+/// A handle that can't be cloned, like a file
+struct Handle {
+    writes: usize
+}
+
+struct HandleStruct {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl HandleStruct {
+    #[track(no_args)]
+    fn write(&self, handle: &mut Handle, data: Vec<u8>) -> usize {
+        handle.writes += 1;
+        data.len()
+    }
+}
+
+#[track_with(TRACKER, capture_args = false)]
+fn write_handle(handle: &mut Handle) {
+    handle.writes += 1;
+}
+
+#[test]
+fn test_no_args() {
+    let tracker = Tracker::new();
+    let tracked = HandleStruct {
+        tracker: tracker.clone()
+    };
+    let mut handle = Handle { writes: 0 };
+    tracked.write(&mut handle, vec![1, 2]);
+    tracked.write(&mut handle, vec![3]);
+    assert_eq!(handle.writes, 2);
+
+    tracker
+        .assert_that("HandleStruct::write")
+        .was_called_times(2)
+        .and_returned(1usize);
+    let err = tracker
+        .assert_that("HandleStruct::write")
+        .check_called_times(2)
+        .and_then(|check| check.with(vec![3u8]))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.message(),
+        "You didn't log any arguments for your calls to HandleStruct::write."
+    );
+
+    write_handle(&mut handle);
+    write_handle(&mut handle);
+    TRACKER.assert_that("write_handle").was_called_times(2);
+}
+
+struct Connection;
+
+struct Database {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl Database {
+    fn exec(&self, #[track_skip] conn: &mut Connection, sql: String) -> bool {
+        true
+    }
+
+    fn query(&self, sql: String, #[track_skip] conn: &mut Connection, limit: u32) {}
+}
+
+#[track_with(TRACKER)]
+fn with_callback(#[track_skip] callback: impl Fn(u32) -> u32, value: u32) -> u32 {
+    callback(value)
+}
+
+#[test]
+fn test_track_skip() {
+    let tracker = Tracker::new();
+    let database = Database {
+        tracker: tracker.clone()
+    };
+    let mut conn = Connection;
+    database.exec(&mut conn, "DELETE".to_string());
+    database.query("SELECT".to_string(), &mut conn, 10);
+
+    tracker
+        .assert_that("Database::exec")
+        .was_called_once()
+        .with("DELETE".to_string())
+        .and_returned(true);
+    tracker
+        .assert_that("Database::query")
+        .was_called_once()
+        .with(("SELECT".to_string(), 10u32));
+
+    assert_eq!(with_callback(|value| value * 2, 4), 8);
+    TRACKER
+        .assert_that("with_callback")
+        .was_called_once()
+        .with(4u32)
+        .and_returned(8u32);
+}
+
+mod visibility {
+    use racetrack::{track_with, Tracker};
+    use std::sync::Arc;
+
+    pub struct Visible {
+        pub tracker: Arc<Tracker>
+    }
+
+    pub mod inner {
+        use super::*;
+
+        #[track_with(tracker)]
+        impl Visible {
+            pub(crate) fn crate_visible(&self) {}
+
+            pub(super) fn super_visible(&self) {}
+
+            pub(in crate::visibility) fn path_visible(&self) {}
+        }
+
+        #[track_with(crate::TRACKER)]
+        pub(crate) fn crate_fn() {}
+
+        #[track_with(crate::TRACKER)]
+        pub(super) fn super_fn() {}
+    }
+
+    pub fn call_restricted(visible: &Visible) {
+        visible.super_visible();
+        visible.path_visible();
+        inner::super_fn();
+    }
+}
+
+#[test]
+fn test_restricted_visibility() {
+    let tracker = Tracker::new();
+    let visible = visibility::Visible {
+        tracker: tracker.clone()
+    };
+    visible.crate_visible();
+    visibility::call_restricted(&visible);
+    visibility::inner::crate_fn();
+
+    tracker
+        .assert_that("Visible::crate_visible")
+        .was_called_once();
+    tracker
+        .assert_that("Visible::super_visible")
+        .was_called_once();
+    tracker
+        .assert_that("Visible::path_visible")
+        .was_called_once();
+    TRACKER.assert_that("crate_fn").was_called_once();
+    TRACKER.assert_that("super_fn").was_called_once();
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Color(u8, u8, u8);
+
+struct Canvas {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, capture = "clone", keys = "CanvasKeys")]
+impl Canvas {
+    fn mix(&self, color: Color, amount: u8) -> Color {
+        Color(color.0 / amount, color.1 / amount, color.2 / amount)
+    }
+}
+
+#[track_with(TRACKER, capture = "clone")]
+fn inverted(color: Color) -> Color {
+    Color(255 - color.0, 255 - color.1, 255 - color.2)
+}
+
+#[test]
+fn test_capture_clone() {
+    let tracker = Tracker::new();
+    let canvas = Canvas {
+        tracker: tracker.clone()
+    };
+    canvas.mix(Color(10, 20, 30), 2);
+
+    tracker
+        .assert_that(CanvasKeys::mix)
+        .was_called_once()
+        .with((Color(10, 20, 30), 2u8))
+        .and_returned(Color(5, 10, 15));
+
+    inverted(Color(0, 255, 100));
+    TRACKER
+        .assert_that("inverted")
+        .was_called_once()
+        .with(Color(0, 255, 100))
+        .and_returned(Color(255, 0, 155));
+}
+
+/// Neither `Clone` nor `ToOwned`, only `Debug`
+#[derive(Debug)]
+struct Request {
+    path: &'static str,
+    retries: u32
+}
+
+struct Server {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, keys = "ServerKeys")]
+impl Server {
+    #[track(capture = "debug")]
+    fn handle(&self, request: Request, priority: u8) -> Request {
+        Request {
+            retries: request.retries + 1,
+            ..request
+        }
+    }
+
+    fn status(&self, code: u16) -> u16 {
+        code
+    }
+}
+
+#[track_with(TRACKER, capture = "debug")]
+fn forward(request: Request) {}
+
+#[test]
+fn test_capture_debug() {
+    let tracker = Tracker::new();
+    let server = Server {
+        tracker: tracker.clone()
+    };
+    server.handle(
+        Request {
+            path: "/index",
+            retries: 0
+        },
+        2
+    );
+    server.status(200);
+
+    tracker
+        .assert_that(ServerKeys::handle)
+        .was_called_once()
+        .with_debug(r#"(Request { path: "/index", retries: 0 }, 2)"#)
+        .and_returned_debug(r#"Request { path: "/index", retries: 1 }"#);
+    tracker
+        .assert_that(ServerKeys::status)
+        .was_called_once()
+        .with(200u16);
+
+    let err = tracker
+        .assert_that("Server::handle")
+        .check_called_once()
+        .and_then(|check| check.with_debug("(Request, 2)"))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.message(),
+        "Server::handle wasn't called with the arguments specified."
+    );
+    assert!(err.summary().starts_with("\nRecorded calls:"));
+    assert_eq!(
+        err.to_string(),
+        format!("{}{}", err.message(), err.summary())
+    );
+
+    forward(Request {
+        path: "/",
+        retries: 3
+    });
+    TRACKER
+        .assert_that("forward")
+        .was_called_once()
+        .with_debug(r#"Request { path: "/", retries: 3 }"#)
+        .and_returned_debug("()");
+}
+
+/// A connection that can't be cloned, but has a cheap id
+struct Conn {
+    id: u32
+}
+
+impl Conn {
+    fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+#[track_with(tracker)]
+impl Database {
+    fn prepare(&self, sql: String, #[track(capture_with = "conn.id()")] conn: &mut Conn) {}
+
+    fn lookup(
+        &self,
+        #[track(capture_with = "conn.id()")] conn: &Conn,
+        #[track(capture_with = "table.len()")] table: &str,
+        limit: u32
+    ) -> u32 {
+        limit
+    }
+}
+
+#[track_with(TRACKER)]
+fn connect(#[track(capture_with = "conn.id")] conn: Conn) {}
+
+#[test]
+fn test_capture_with() {
+    let tracker = Tracker::new();
+    let database = Database {
+        tracker: tracker.clone()
+    };
+    let mut conn = Conn { id: 7 };
+    database.prepare("SELECT".to_string(), &mut conn);
+    database.lookup(&conn, "users", 10);
+
+    tracker
+        .assert_that("Database::prepare")
+        .was_called_once()
+        .with(("SELECT".to_string(), 7u32));
+    tracker
+        .assert_that("Database::lookup")
+        .was_called_once()
+        .with((7u32, 5usize, 10u32))
+        .and_returned(10u32);
+
+    connect(Conn { id: 3 });
+    TRACKER.assert_that("connect").was_called_once().with(3u32);
+}
+
+#[derive(racetrack::Tracked)]
+struct Service {
+    name: String,
+    #[tracker]
+    calls: Arc<Tracker>,
+    fallback: Arc<Tracker>
+}
+
+#[track_with(tracker())]
+impl Service {
+    fn start(&self, port: u16) -> bool {
+        true
+    }
+}
+
+#[derive(racetrack::Tracked)]
+struct Worker(u32, Arc<racetrack::Tracker>);
+
+#[track_with(tracker())]
+impl Worker {
+    fn work(&self) -> u32 {
+        self.0
+    }
+}
+
+#[test]
+fn test_derive_tracked() {
+    let tracker = Tracker::new();
+    let service = Service {
+        name: "service".to_string(),
+        calls: tracker.clone(),
+        fallback: Tracker::new()
+    };
+    service.start(8080);
+
+    tracker
+        .assert_that("Service::start")
+        .was_called_once()
+        .with(8080u16)
+        .and_returned(true);
+    service
+        .fallback
+        .assert_that("Service::start")
+        .wasnt_called();
+
+    let worker = Worker(3, tracker.clone());
+    worker.work();
+    tracker
+        .assert_that("Worker::work")
+        .was_called_once()
+        .and_returned(3u32);
+}
+
+struct Counter {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, keys = "CounterKeys")]
+impl Counter {
+    fn reset(&self) {}
+
+    fn add(&self, amount: u32) {}
+}
+
+#[track_with(TRACKER)]
+fn no_args() -> u32 {
+    1
+}
+
+#[test]
+fn test_no_args_assertion() {
+    let tracker = Tracker::new();
+    let counter = Counter {
+        tracker: tracker.clone()
+    };
+    counter.reset();
+    counter.add(2);
+
+    tracker
+        .assert_that(CounterKeys::reset)
+        .was_called_once()
+        .with_no_args();
+    let err = tracker
+        .assert_that("Counter::add")
+        .check_called_once()
+        .and_then(|check| check.with_no_args())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.message(),
+        "Counter::add was only called with arguments."
+    );
+
+    no_args();
+    TRACKER
+        .assert_that("no_args")
+        .was_called_once()
+        .with_no_args()
+        .and_returned(1u32);
+}
+
+struct Registry {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl Registry {
+    fn register(&self, name: Arc<String>) {}
+
+    fn alias(&self, name: Arc<str>) {}
+}
+
+#[test]
+fn test_shared_arguments() {
+    let tracker = Tracker::new();
+    let registry = Registry {
+        tracker: tracker.clone()
+    };
+    let name = Arc::new("service".to_string());
+    registry.register(name.clone());
+    registry.alias(Arc::from("svc"));
+
+    // Arc compares by value, so a different Arc with the same value matches as well
+    tracker
+        .assert_that("Registry::register")
+        .was_called_once()
+        .with(Arc::new("service".to_string()))
+        .with_deref(&"service".to_string());
+    tracker
+        .assert_that("Registry::alias")
+        .was_called_once()
+        .with_deref("svc");
+
+    let err = tracker
+        .assert_that("Registry::register")
+        .check_called_once()
+        .and_then(|check| check.with_deref("service"))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.message(),
+        "The arguments logged for Registry::register weren't an Arc or Box of that type."
+    );
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Report {
+    lines: Vec<String>
+}
+
+struct ReportBuilder {
+    tracker: Arc<Tracker>,
+    lines: Vec<String>
+}
+
+#[track_with(tracker)]
+impl ReportBuilder {
+    #[track(no_return)]
+    fn line(mut self, line: &str) -> Self {
+        self.lines.push(line.to_string());
+        self
+    }
+
+    fn finish(self) -> Report {
+        Report { lines: self.lines }
+    }
+}
+
+struct TupleBuilder(Vec<u32>, Arc<Tracker>);
+
+#[track_with(1)]
+impl TupleBuilder {
+    fn into_values(self) -> Vec<u32> {
+        self.0
+    }
+}
+
+#[test]
+fn test_by_value_receiver() {
+    let tracker = Tracker::new();
+    let report = ReportBuilder {
+        tracker: tracker.clone(),
+        lines: Vec::new()
+    }
+    .line("first")
+    .finish();
+    assert_eq!(report.lines, vec!["first".to_string()]);
+
+    tracker
+        .assert_that("ReportBuilder::line")
+        .was_called_once()
+        .with("first".to_string());
+    tracker
+        .assert_that("ReportBuilder::finish")
+        .was_called_once()
+        .and_returned(Report {
+            lines: vec!["first".to_string()]
+        });
+
+    TupleBuilder(vec![1, 2], tracker.clone()).into_values();
+    tracker
+        .assert_that("TupleBuilder::into_values")
+        .was_called_once()
+        .and_returned(vec![1u32, 2]);
+}
+
+#[derive(Clone)]
+struct Account {
+    tracker: Arc<Tracker>,
+    balance: u32
+}
+
+impl PartialEq for Account {
+    fn eq(&self, other: &Self) -> bool {
+        self.balance == other.balance
+    }
+}
+
+#[track_with(tracker)]
+impl Account {
+    #[track(capture_self)]
+    fn deposit(&mut self, amount: u32) {
+        self.balance += amount;
+    }
+
+    fn balance(&self) -> u32 {
+        self.balance
+    }
+}
+
+#[test]
+fn test_capture_self() {
+    let tracker = Tracker::new();
+    let mut account = Account {
+        tracker: tracker.clone(),
+        balance: 0
+    };
+    account.deposit(10);
+    account.deposit(5);
+    account.balance();
+
+    let snapshot = |balance| Account {
+        tracker: tracker.clone(),
+        balance
+    };
+    tracker
+        .assert_that("Account::deposit")
+        .was_called_times(2)
+        .with_receiver(snapshot(0))
+        .with_receiver(snapshot(10));
+    let err = tracker
+        .assert_that("Account::deposit")
+        .check_called_times(2)
+        .and_then(|check| check.with_receiver(snapshot(15)))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.message(),
+        "Account::deposit wasn't called on the receiver specified."
+    );
+
+    let err = tracker
+        .assert_that("Account::balance")
+        .check_called_once()
+        .and_then(|check| check.with_receiver(snapshot(15)))
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.message(),
+        "You didn't log the receiver for your calls to Account::balance. Track them with capture_self."
+    );
+}
+
+struct Actor {
+    tracker: Arc<Tracker>,
+    name: String
+}
+
+#[track_with(tracker)]
+impl Actor {
+    fn handle(self: Arc<Self>, message: String) -> String {
+        format!("{}: {}", self.name, message)
+    }
+
+    fn stop(self: Box<Self>, code: i32) -> i32 {
+        drop(self);
+        code
+    }
+
+    fn rename(self: std::pin::Pin<&mut Self>, name: &str) -> usize {
+        self.get_mut().name = name.to_string();
+        name.len()
+    }
+}
+
+#[test]
+fn test_typed_receivers() {
+    let tracker = Tracker::new();
+    let actor = Arc::new(Actor {
+        tracker: tracker.clone(),
+        name: "actor".to_string()
+    });
+    assert_eq!(actor.handle("ping".to_string()), "actor: ping");
+
+    let mut actor = Box::new(Actor {
+        tracker: tracker.clone(),
+        name: "boxed".to_string()
+    });
+    std::pin::Pin::new(&mut *actor).rename("renamed");
+    assert_eq!(actor.name, "renamed");
+    actor.stop(2);
+
+    tracker
+        .assert_that("Actor::handle")
+        .was_called_once()
+        .with("ping".to_string())
+        .and_returned("actor: ping".to_string());
+    tracker
+        .assert_that("Actor::rename")
+        .was_called_once()
+        .with("renamed".to_string())
+        .and_returned(7usize);
+    tracker
+        .assert_that("Actor::stop")
+        .was_called_once()
+        .with(2i32)
+        .and_returned(2i32);
+}
+
+#[derive(Clone)]
+struct Session {
+    tracker: Arc<Tracker>,
+    user: String
+}
+
+#[track_with(
+    tracker,
+    static_tracker = "crate::TRACKER",
+    exclude = "untracked_default"
+)]
+impl Session {
+    fn new(tracker: Arc<Tracker>, user: &str) -> Self {
+        Self {
+            tracker,
+            user: Self::normalize(user)
+        }
+    }
+
+    fn normalize(user: &str) -> String {
+        user.to_lowercase()
+    }
+
+    fn untracked_default() -> u32 {
+        0
+    }
+
+    fn user(&self) -> String {
+        self.user.clone()
+    }
+}
+
+#[test]
+fn test_static_tracker() {
+    let tracker = Tracker::new();
+    let session = Session::new(tracker.clone(), "Admin");
+    session.user();
+    Session::untracked_default();
+
+    TRACKER.assert_that("Session::new").was_called_once();
+    TRACKER
+        .assert_that("Session::normalize")
+        .was_called_once()
+        .with("Admin".to_string())
+        .and_returned("admin".to_string());
+    TRACKER
+        .assert_that("Session::untracked_default")
+        .wasnt_called();
+    TRACKER.assert_that("Session::user").wasnt_called();
+    tracker
+        .assert_that("Session::user")
+        .was_called_once()
+        .and_returned("admin".to_string());
+    tracker.assert_that("Session::new").wasnt_called();
+}
+
+#[track_with(racetrack::Tracker::current())]
+fn scoped_fn(value: u32) -> u32 {
+    value
+}
+
+#[test]
+fn test_scoped() {
+    Tracker::scoped(|tracker| {
+        scoped_fn(1);
+        tracker
+            .assert_that("scoped_fn")
+            .was_called_once()
+            .with(1u32);
+    });
+    Tracker::scoped(|tracker| {
+        scoped_fn(2);
+        tracker
+            .assert_that("scoped_fn")
+            .was_called_once()
+            .with(2u32);
+    });
+
+    let outer = Tracker::current();
+    let result = std::panic::catch_unwind(|| {
+        Tracker::scoped(|_| {
+            scoped_fn(3);
+            panic!("test failure");
+        })
+    });
+    assert!(result.is_err());
+    assert!(Arc::ptr_eq(&outer, &Tracker::current()));
+    outer.assert_that("scoped_fn").wasnt_called();
+}
+
+struct Cache {
+    tracker: Option<Arc<Tracker>>,
+    entries: Vec<String>
+}
+
+#[track_with(tracker, optional = true)]
+impl Cache {
+    fn insert(&mut self, entry: String) -> usize {
+        self.entries.push(entry);
+        self.entries.len()
+    }
+
+    #[track(capture_self)]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl Clone for Cache {
+    fn clone(&self) -> Self {
+        assert!(
+            self.tracker.is_some(),
+            "untracked caches shouldn't be cloned"
+        );
+        Self {
+            tracker: self.tracker.clone(),
+            entries: self.entries.clone()
+        }
+    }
+}
+
+impl PartialEq for Cache {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+#[test]
+fn test_optional_tracker() {
+    let tracker = Tracker::new();
+    let mut cache = Cache {
+        tracker: Some(tracker.clone()),
+        entries: Vec::new()
+    };
+    assert_eq!(cache.insert("a".to_string()), 1);
+    assert_eq!(cache.len(), 1);
+    tracker
+        .assert_that("Cache::insert")
+        .was_called_once()
+        .with("a".to_string())
+        .and_returned(1usize);
+    tracker
+        .assert_that("Cache::len")
+        .was_called_once()
+        .with_receiver(Cache {
+            tracker: None,
+            entries: vec!["a".to_string()]
+        });
+
+    // Nothing is captured without a tracker, so the receiver isn't cloned either
+    let mut untracked = Cache {
+        tracker: None,
+        entries: Vec::new()
+    };
+    assert_eq!(untracked.insert("b".to_string()), 1);
+    assert_eq!(untracked.len(), 1);
+    tracker.assert_that("Cache::insert").was_called_once();
+}
+
+struct Pooled {
+    tracker: std::sync::Weak<Tracker>,
+    fallback: Option<std::sync::Weak<Tracker>>
+}
+
+#[track_with(tracker, weak = true)]
+impl Pooled {
+    #[track(capture_self)]
+    fn fetch(&self, id: u32) -> u32 {
+        id * 2
+    }
+}
+
+#[track_with(fallback, weak = true, optional = true, namespace = "Fallback")]
+impl Pooled {
+    fn fetch_fallback(&self, id: u32) -> u32 {
+        id
+    }
+}
+
+impl Clone for Pooled {
+    fn clone(&self) -> Self {
+        assert!(
+            self.tracker.upgrade().is_some(),
+            "nothing should be captured after the tracker was dropped"
+        );
+        Self {
+            tracker: self.tracker.clone(),
+            fallback: self.fallback.clone()
+        }
+    }
+}
+
+impl PartialEq for Pooled {
+    fn eq(&self, other: &Self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_weak_tracker() {
+    let tracker = Tracker::new();
+    let pooled = Pooled {
+        tracker: Arc::downgrade(&tracker),
+        fallback: Some(Arc::downgrade(&tracker))
+    };
+    assert_eq!(pooled.fetch(2), 4);
+    assert_eq!(pooled.fetch_fallback(3), 3);
+    tracker
+        .assert_that("Pooled::fetch")
+        .was_called_once()
+        .with(2u32)
+        .and_returned(4u32);
+    tracker
+        .assert_that("Fallback::fetch_fallback")
+        .was_called_once()
+        .with(3u32);
+
+    // Capturing the receiver would panic in `clone`, so this also checks that nothing is captured
+    drop(tracker);
+    assert_eq!(pooled.fetch(5), 10);
+    assert_eq!(pooled.fetch_fallback(6), 6);
+    assert!(pooled.tracker.upgrade().is_none());
+}
+
+struct Deps {
+    tracker: Arc<Tracker>
+}
+
+impl Deps {
+    fn tracker(&self) -> &Arc<Tracker> {
+        &self.tracker
+    }
+}
+
+struct Handler {
+    deps: Deps
+}
+
+impl Handler {
+    fn deps(&self) -> &Deps {
+        &self.deps
+    }
+}
+
+#[track_with(expr = "self.deps().tracker()")]
+impl Handler {
+    fn handle(&self, request: u32) -> u32 {
+        request + 1
+    }
+
+    fn shutdown(self) -> bool {
+        true
+    }
+}
+
+#[track_with(expr = "Tracker::current()")]
+fn expr_tracked(value: u32) {}
+
+#[test]
+fn test_tracker_expr() {
+    let tracker = Tracker::new();
+    let handler = Handler {
+        deps: Deps {
+            tracker: tracker.clone()
+        }
+    };
+    handler.handle(1);
+    handler.shutdown();
+
+    tracker
+        .assert_that("Handler::handle")
+        .was_called_once()
+        .with(1u32)
+        .and_returned(2u32);
+    tracker
+        .assert_that("Handler::shutdown")
+        .was_called_once()
+        .and_returned(true);
+
+    Tracker::scoped(|tracker| {
+        expr_tracked(3);
+        tracker
+            .assert_that("expr_tracked")
+            .was_called_once()
+            .with(3u32);
+    });
+}
+
+struct Parser {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl Parser {
+    fn parse(&self, input: String) -> u32 {
+        input.parse::<u32>().expect("not a number")
+    }
+}
+
+#[test]
+fn test_panic_safety() {
+    let tracker = Tracker::new();
+    let parser = Parser {
+        tracker: tracker.clone()
+    };
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        parser.parse("one".to_string())
+    }));
+    assert!(result.is_err());
+    let result = std::thread::spawn({
+        let tracker = tracker.clone();
+        move || Parser { tracker }.parse("two".to_string())
+    })
+    .join();
+    assert!(result.is_err());
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        tracker.assert_that("Parser::parse").was_called_once();
+    }));
+    assert!(result.is_err());
+
+    parser.parse("3".to_string());
+    tracker
+        .assert_that("Parser::parse")
+        .was_called_once()
+        .with("3".to_string())
+        .and_returned(3u32);
+}
+
+/// Stubs the number of times it was cloned, so cloning it calls back into the tracker
+struct Reentrant(Arc<Tracker>);
+
+impl Clone for Reentrant {
+    fn clone(&self) -> Self {
+        let clones = self.0.stubbed::<u32>("Reentrant::clone").unwrap_or(0);
+        self.0.when("Reentrant::clone").return_value(clones + 1);
+        Reentrant(self.0.clone())
+    }
+}
+
+#[test]
+fn test_reentrant_assertions() {
+    let tracker = Tracker::new();
+    let parser = Parser {
+        tracker: tracker.clone()
+    };
+    parser.parse("1".to_string());
+
+    // Each of these deadlocks if the tracker holds a lock while running user code
+    let (done, finished) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn(move || {
+        tracker
+            .assert_that("Parser::parse")
+            .was_called_once()
+            .with_args_matching(|input: &String| parser.parse(input.clone()) == 1);
+        tracker
+            .assert_that("Parser::parse")
+            .was_called_times(2)
+            .with_call_matching(|_| tracker.last_call_args::<String>("Parser::parse").is_some());
+
+        tracker
+            .when("Parser::config")
+            .return_value(Reentrant(tracker.clone()));
+        tracker.stubbed::<Reentrant>("Parser::config");
+        assert_eq!(tracker.stubbed::<u32>("Reentrant::clone"), Some(1));
+        done.send(()).unwrap();
+    });
+    finished
+        .recv_timeout(std::time::Duration::from_secs(10))
+        .expect("the assertions deadlocked or failed");
+    handle.join().unwrap();
+}
+
+struct Services {
+    tracker: Arc<Tracker>
+}
+
+struct Mailer {
+    services: Services
+}
+
+#[track_with(services.tracker)]
+impl Mailer {
+    fn send(&self, to: String) {}
+}
+
+struct Notifier(u32, (String, Arc<Tracker>));
+
+#[track_with(1.1)]
+impl Notifier {
+    fn notify(&self, message: String) {}
+}
+
+struct Scheduler {
+    deps: (Services,)
+}
+
+#[track_with(deps.0.tracker, namespace = "Scheduler")]
+impl Scheduler {
+    fn schedule(&self, at: u64) {}
+}
+
+#[test]
+fn test_nested_tracker_fields() {
+    let tracker = Tracker::new();
+    let mailer = Mailer {
+        services: Services {
+            tracker: tracker.clone()
+        }
+    };
+    let notifier = Notifier(0, (String::new(), tracker.clone()));
+    let scheduler = Scheduler {
+        deps: (Services {
+            tracker: tracker.clone()
+        },)
+    };
+
+    mailer.send("someone".to_string());
+    notifier.notify("hello".to_string());
+    scheduler.schedule(5);
+
+    tracker
+        .assert_that("Mailer::send")
+        .was_called_once()
+        .with("someone".to_string());
+    tracker
+        .assert_that("Notifier::notify")
+        .was_called_once()
+        .with("hello".to_string());
+    tracker
+        .assert_that("Scheduler::schedule")
+        .was_called_once()
+        .with(5u64);
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ConfigError(String);
+
+impl From<std::num::ParseIntError> for ConfigError {
+    fn from(error: std::num::ParseIntError) -> Self {
+        ConfigError(error.to_string())
+    }
+}
+
+struct Config {
+    tracker: Arc<Tracker>,
+    values: Vec<u32>
+}
+
+#[track_with(tracker)]
+impl Config {
+    fn parse(&self, input: &str) -> Result<u32, ConfigError> {
+        let value = input.parse()?;
+        Ok(value)
+    }
+
+    #[track(no_return)]
+    fn push(&mut self, input: &str) -> Result<&mut Vec<u32>, ConfigError> {
+        self.values.push(input.parse()?);
+        Ok(&mut self.values)
+    }
+
+    fn first(&self) -> Option<u32> {
+        let first = self.values.first()?;
+        Some(*first)
+    }
+
+    async fn load(&self, input: &str) -> Result<u32, ConfigError> {
+        let value = async { input.parse() }.await?;
+        Ok(value)
+    }
+
+    fn finish(self, input: &str) -> Result<Vec<u32>, ConfigError> {
+        let mut values = self.values;
+        values.push(input.parse()?);
+        Ok(values)
+    }
+}
+
+#[test]
+fn test_question_mark() {
+    let tracker = Tracker::new();
+    let mut config = Config {
+        tracker: tracker.clone(),
+        values: Vec::new()
+    };
+
+    let error = config.parse("a").unwrap_err();
+    config.parse("1").unwrap();
+    config.first();
+    config.push("2").unwrap().push(3);
+    config.push("b").unwrap_err();
+    config.first();
+    futures::executor::block_on(config.load("d")).unwrap_err();
+    config.finish("c").unwrap_err();
+
+    tracker
+        .assert_that("Config::parse")
+        .was_called_times(2)
+        .with("a".to_string())
+        .and_returned(Err::<u32, _>(error.clone()));
+    tracker
+        .assert_that("Config::parse")
+        .was_called_times(2)
+        .nth_returned(1, Ok::<_, ConfigError>(1u32));
+    tracker.assert_that("Config::push").was_called_times(2);
+    tracker
+        .assert_that("Config::first")
+        .was_called_times(2)
+        .nth_returned(0, None::<u32>)
+        .nth_returned(1, Some(2u32));
+    tracker
+        .assert_that("Config::load")
+        .was_called_once()
+        .and_returned(Err::<u32, _>(ConfigError(
+            "invalid digit found in string".to_string()
+        )));
+    tracker
+        .assert_that("Config::finish")
+        .was_called_once()
+        .and_returned(Err::<Vec<u32>, _>(error));
+}
+
+#[track_with(TRACKER)]
+fn clamp_early(value: u32) -> u32 {
+    if value > 10 {
+        return 10;
+    }
+    value
+}
+
+struct Queue {
+    tracker: Arc<Tracker>,
+    items: Vec<String>
+}
+
+#[track_with(tracker)]
+impl Queue {
+    fn pop(&mut self) -> Option<String> {
+        if self.items.is_empty() {
+            return None;
+        }
+        Some(self.items.remove(0))
+    }
+
+    fn drain(&mut self, limit: usize) {
+        for _ in 0..limit {
+            if self.items.pop().is_none() {
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_early_return() {
+    assert_eq!(clamp_early(20), 10);
+    assert_eq!(clamp_early(5), 5);
+    TRACKER
+        .assert_that("clamp_early")
+        .was_called_times(2)
+        .nth_returned(0, 10u32)
+        .nth_returned(1, 5u32);
+
+    let tracker = Tracker::new();
+    let mut queue = Queue {
+        tracker: tracker.clone(),
+        items: vec!["a".to_string()]
+    };
+    queue.pop();
+    queue.pop();
+    queue.drain(2);
+    tracker
+        .assert_that("Queue::pop")
+        .was_called_times(2)
+        .nth_returned(0, Some("a".to_string()))
+        .nth_returned(1, None::<String>);
+    tracker
+        .assert_that("Queue::drain")
+        .was_called_once()
+        .with(2usize);
+}
+
+#[track_with(TRACKER)]
+fn parse_port(input: &str) -> Result<u16, std::num::ParseIntError> {
+    let port = input.trim().parse()?;
+    Ok(port)
+}
+
+#[track_with(TRACKER, capture_return = false)]
+fn read_missing(path: &str) -> std::io::Result<String> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(contents)
+}
+
+#[test]
+fn test_question_mark_functions() {
+    let error = parse_port("port").unwrap_err();
+    parse_port(" 80 ").unwrap();
+    TRACKER
+        .assert_that("parse_port")
+        .was_called_times(2)
+        .nth_returned(0, Err::<u16, _>(error))
+        .nth_returned(1, Ok::<u16, std::num::ParseIntError>(80));
+
+    read_missing("/racetrack/does/not/exist").unwrap_err();
+    TRACKER
+        .assert_that("read_missing")
+        .was_called_once()
+        .with("/racetrack/does/not/exist".to_string());
+}
+
+struct Socket {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl Socket {
+    fn write(&self, data: &[u8]) -> usize {
+        data.len()
+    }
+
+    fn write_all(&self, chunks: &[String], flush: bool) {}
+
+    fn peek<'a>(&self, buffer: &'a [u16]) -> &'a [u16] {
+        &buffer[..1]
+    }
+}
+
+#[test]
+fn test_slice_arguments() {
+    let tracker = Tracker::new();
+    let socket = Socket {
+        tracker: tracker.clone()
+    };
+    socket.write(&[1, 2, 3]);
+    socket.write_all(&["a".to_string(), "b".to_string()], true);
+    socket.peek(&[4, 5]);
+
+    tracker
+        .assert_that("Socket::write")
+        .was_called_once()
+        .with(vec![1u8, 2, 3])
+        .and_returned(3usize);
+    tracker
+        .assert_that("Socket::write_all")
+        .was_called_once()
+        .with((vec!["a".to_string(), "b".to_string()], true));
+    tracker
+        .assert_that("Socket::peek")
+        .was_called_once()
+        .with(vec![4u16, 5])
+        .and_returned(vec![4u16]);
+}
+
+struct Daemon {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, log_before = true)]
+impl Daemon {
+    fn run(&self, job: u32) -> u32 {
+        job * 2
+    }
+
+    fn block(&self, job: u32) {
+        loop {
+            std::thread::park();
+        }
+    }
+
+    fn process(&self, job: u32) -> u32 {
+        if job == 0 {
+            loop {
+                std::thread::park();
+            }
+        }
+        job * 2
+    }
+}
+
+struct OptionalDaemon {
+    tracker: Option<Arc<Tracker>>
+}
+
+#[track_with(tracker, optional = true, log_before = true)]
+impl OptionalDaemon {
+    fn run(&self, job: u32) -> u32 {
+        job * 2
+    }
+}
+
+#[test]
+fn test_log_before() {
+    let tracker = Tracker::new();
+    let daemon = Arc::new(Daemon {
+        tracker: tracker.clone()
+    });
+    daemon.run(2);
+    tracker
+        .assert_that("Daemon::run")
+        .was_called_once()
+        .with(2u32)
+        .and_returned(4u32);
+
+    let blocked = daemon.clone();
+    std::thread::spawn(move || blocked.block(7));
+    tracker
+        .assert_that("Daemon::block")
+        .eventually(std::time::Duration::from_secs(10))
+        .was_called_once()
+        .with(7u32);
+
+    // A call that hasn't returned doesn't hide the return values of the ones that did
+    let parked = daemon.clone();
+    std::thread::spawn(move || parked.process(0));
+    tracker
+        .assert_that("Daemon::process")
+        .eventually(std::time::Duration::from_secs(10))
+        .was_called_once();
+    daemon.process(5);
+    tracker
+        .assert_that("Daemon::process")
+        .was_called_times(2)
+        .and_returned(10u32);
+    assert!(!tracker.captures_returns("Daemon::process"));
+
+    let daemon = OptionalDaemon {
+        tracker: Some(tracker.clone())
+    };
+    daemon.run(3);
+    OptionalDaemon { tracker: None }.run(4);
+    tracker
+        .assert_that("OptionalDaemon::run")
+        .was_called_once()
+        .with(3u32)
+        .and_returned(6u32);
+}
+
+#[track_with(TRACKER)]
+fn hygienic(args: Vec<String>, returned: bool, tracker: u32) -> usize {
+    let inner = args.len();
+    if returned {
+        inner
+    } else {
+        tracker as usize
+    }
+}
+
+#[track_with(TRACKER)]
+fn hygienic_early(args: Vec<String>) -> Option<usize> {
+    let returned = args.first()?.len();
+    Some(returned)
+}
+
+#[test]
+fn test_generated_names_dont_shadow() {
+    hygienic(vec!["a".to_string()], true, 5);
+    hygienic_early(vec!["abc".to_string()]);
+
+    TRACKER
+        .assert_that("hygienic")
+        .was_called_once()
+        .with((vec!["a".to_string()], true, 5u32))
+        .and_returned(1usize);
+    TRACKER
+        .assert_that("hygienic_early")
+        .was_called_once()
+        .with(vec!["abc".to_string()])
+        .and_returned(Some(3usize));
+}
+
+struct Inventory {
+    tracker: Arc<Tracker>,
+    items: Vec<u32>
+}
+
+#[track_with(tracker)]
+impl Inventory {
+    #[track(no_return)]
+    fn find_mut(&mut self, id: u32) -> Option<&mut u32> {
+        let index = self.items.iter().position(|item| *item == id)?;
+        if index == 0 {
+            return self.items.first_mut();
+        }
+        Some(&mut self.items[index])
+    }
+
+    fn into_total(self, limit: u32) -> u32 {
+        let total = self.items.iter().sum();
+        if total > limit {
+            return limit;
+        }
+        total
+    }
+}
+
+#[test]
+fn test_early_return_methods() {
+    let tracker = Tracker::new();
+    let mut inventory = Inventory {
+        tracker: tracker.clone(),
+        items: vec![1, 2]
+    };
+
+    *inventory.find_mut(2).unwrap() = 5;
+    assert!(inventory.find_mut(3).is_none());
+    assert_eq!(inventory.into_total(4), 4);
+
+    tracker
+        .assert_that("Inventory::find_mut")
+        .was_called_times(2)
+        .with(2u32)
+        .with(3u32);
+    tracker
+        .assert_that("Inventory::into_total")
+        .was_called_once()
+        .with(4u32)
+        .and_returned(4u32);
+}
+
+#[test]
+fn test_tracked_closure_macro() {
+    let tracker = Tracker::new();
+
+    let shout = racetrack::tracked_closure!(tracker, shout, |word: String| word.to_uppercase());
+    let add = racetrack::tracked_closure!(tracker, add, |a: u32, b: u32| -> u32 { a + b });
+    let join = racetrack::tracked_closure!(
+        tracker,
+        join,
+        |a: &str, b: &str, separator: char| format!("{}{}{}", a, separator, b),
+        capture_return = false
+    );
+
+    assert_eq!(shout("hi".to_string()), "HI");
+    assert_eq!(add(1, 2), 3);
+    assert_eq!(join("a", "b", '-'), "a-b");
+
+    tracker
+        .assert_that("shout")
+        .was_called_once()
+        .with("hi".to_string())
+        .and_returned("HI".to_string());
+    tracker
+        .assert_that("add")
+        .was_called_once()
+        .with((1u32, 2u32))
+        .and_returned(3u32);
+    tracker
+        .assert_that("join")
+        .was_called_once()
+        .with(("a".to_string(), "b".to_string(), '-'));
+}
+
+#[test]
+fn test_tracked_async_closure_macro() {
+    let tracker = Tracker::new();
+
+    let suffix = "!".to_string();
+    let fetch = racetrack::tracked_closure!(tracker, fetch, async |id: u32| -> String {
+        let name = async { format!("item {}", id) }.await;
+        format!("{}{}", name, suffix)
+    });
+
+    let first = futures::executor::block_on(fetch(1));
+    let second = futures::executor::block_on(fetch(2));
+
+    assert_eq!(first, "item 1!");
+    assert_eq!(second, "item 2!");
+    tracker
+        .assert_that("fetch")
+        .was_called_times(2)
+        .with_args_seq(vec![1u32, 2])
+        .nth_returned(0, "item 1!".to_string())
+        .nth_returned(1, "item 2!".to_string());
+}
+
+#[test]
+fn test_tracked_closure_captures() {
+    let tracker = Tracker::new();
+
+    let prefix = "Hello, ".to_string();
+    let greet = racetrack::tracked_closure!(tracker, greet, move |name: String| format!(
+        "{}{}",
+        prefix, name
+    ));
+    let mut count = 0;
+    let mut increment = racetrack::tracked_closure!(tracker, increment, |by: u32| {
+        count += by;
+        count
+    });
+    let message = "consumed".to_string();
+    let consume = racetrack::tracked_closure!(tracker, consume, move || message);
+
+    assert_eq!(greet("you".to_string()), "Hello, you");
+    increment(1);
+    increment(2);
+    assert_eq!(consume(), "consumed");
+    assert_eq!(count, 3);
+
+    tracker
+        .assert_that("greet")
+        .was_called_once()
+        .and_returned("Hello, you".to_string());
+    tracker
+        .assert_that("increment")
+        .was_called_times(2)
+        .nth_returned(0, 1u32)
+        .nth_returned(1, 3u32);
+    tracker
+        .assert_that("consume")
+        .was_called_once()
+        .and_returned("consumed".to_string());
+}
+
+struct Settings {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, keys = "SettingsKeys")]
+impl Settings {
+    fn set(&self, key: impl Into<String>, value: u32) -> String {
+        let key: String = key.into();
+        format!("{}={}", key, value)
+    }
+
+    fn render(&self, label: impl std::fmt::Display, width: usize) -> String {
+        format!("{:>width$}", label, width = width)
+    }
+
+    fn rename(&self, name: impl Into<String>, #[track_skip] fallback: impl Into<String>) {
+        let name: String = name.into();
+        let _ = (name + "!", fallback.into());
+    }
+}
+
+#[test]
+fn test_impl_trait_arguments() {
+    let tracker = Tracker::new();
+    let settings = Settings {
+        tracker: tracker.clone()
+    };
+    settings.set("volume", 3);
+    settings.set(String::from("mode"), 1);
+    settings.render(7, 3);
+    settings.rename("user", "guest");
+
+    tracker
+        .assert_that(SettingsKeys::set)
+        .was_called_times(2)
+        .with(("volume".to_string(), 3u32))
+        .with(("mode".to_string(), 1u32));
+    tracker
+        .assert_that(SettingsKeys::render)
+        .was_called_once()
+        .with(3usize)
+        .and_returned("  7".to_string());
+    tracker
+        .assert_that(SettingsKeys::rename)
+        .was_called_once()
+        .with("user".to_string());
+}
+
+#[track_with(spy = true)]
+trait Repo {
+    type Id: Clone + Send + Sync + 'static;
+
+    fn get(&self, id: u32) -> Option<String>;
+
+    fn insert(&mut self, id: u32, value: &str) -> bool;
+
+    fn first_id(&self) -> Option<Self::Id>;
+
+    #[track(skip)]
+    fn name(&self) -> String {
+        "repo".to_string()
+    }
+
+    fn describe(&self, id: u32) -> String {
+        format!("{}#{}", self.name(), id)
+    }
+}
+
+#[derive(Default)]
+struct MemoryRepo {
+    values: std::collections::BTreeMap<u32, String>
+}
+
+impl Repo for MemoryRepo {
+    type Id = u32;
+
+    fn get(&self, id: u32) -> Option<String> {
+        self.values.get(&id).cloned()
+    }
+
+    fn insert(&mut self, id: u32, value: &str) -> bool {
+        self.values.insert(id, value.to_string()).is_none()
+    }
+
+    fn first_id(&self) -> Option<u32> {
+        self.values.keys().next().copied()
+    }
+
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+}
+
+#[test]
+fn test_trait_spy() {
+    let tracker = Tracker::new();
+    let mut repo: Box<dyn Repo<Id = u32>> =
+        Box::new(RepoSpy::new(MemoryRepo::default(), tracker.clone()));
+
+    assert!(repo.insert(2, "b"));
+    assert!(!repo.insert(2, "c"));
+    assert_eq!(repo.get(2), Some("c".to_string()));
+    assert_eq!(repo.get(5), None);
+    assert_eq!(repo.first_id(), Some(2));
+    assert_eq!(repo.describe(2), "memory#2");
+
+    tracker
+        .assert_that("Repo::insert")
+        .was_called_times(2)
+        .with((2u32, "c".to_string()))
+        .and_returned(false);
+    tracker
+        .assert_that("Repo::get")
+        .was_called_times(2)
+        .with(5u32)
+        .and_returned(None::<String>);
+    tracker
+        .assert_that("Repo::first_id")
+        .was_called_once()
+        .and_returned(Some(2u32));
+    tracker
+        .assert_that("Repo::describe")
+        .was_called_once()
+        .with(2u32)
+        .and_returned("memory#2".to_string());
+    tracker.assert_that("Repo::name").wasnt_called();
+}
+
+#[racetrack::mock]
+trait Clock {
+    fn now(&self) -> u64;
+
+    fn sleep(&mut self, millis: u64);
+
+    fn zone(&self) -> Option<String>;
+
+    #[track(rename = "next")]
+    fn next_tick(&self, from: u64, step: u64) -> std::time::Duration;
+}
+
+fn elapsed(clock: &dyn Clock, start: u64) -> u64 {
+    clock.now() - start
+}
+
+#[test]
+fn test_mock() {
+    let tracker = Tracker::new();
+    let mut clock: Box<dyn Clock> = Box::new(MockClock::new(tracker.clone()));
+    tracker.when("Clock::now").return_value(42u64);
+    tracker
+        .when("Clock::next")
+        .return_value(std::time::Duration::from_millis(5));
+
+    assert_eq!(elapsed(&*clock, 40), 2);
+    clock.sleep(10);
+    assert_eq!(clock.zone(), None);
+    assert_eq!(clock.next_tick(1, 2), std::time::Duration::from_millis(5));
+    tracker.when("Clock::now").return_value(50u64);
+    assert_eq!(clock.now(), 50);
+
+    tracker
+        .assert_that("Clock::now")
+        .was_called_times(2)
+        .with_no_args()
+        .and_returned(42u64);
+    tracker
+        .assert_that("Clock::sleep")
+        .was_called_once()
+        .with(10u64);
+    tracker
+        .assert_that("Clock::zone")
+        .was_called_once()
+        .and_returned(None::<String>);
+    tracker
+        .assert_that("Clock::next")
+        .was_called_once()
+        .with((1u64, 2u64));
+}
+
+#[racetrack::mock]
+trait Storage {
+    const PREFIX: &'static str = "storage";
+
+    fn modified(&self, key: &str) -> std::time::Instant;
+}
+
+#[test]
+#[should_panic(
+    expected = "No return value was stubbed for Storage::modified, and std::time::Instant doesn't implement Default. \
+                Stub it with tracker.when(\"Storage::modified\").return_value(...)."
+)]
+fn test_mock_without_stub() {
+    let storage = MockStorage::new(Tracker::new());
+    assert_eq!(MockStorage::PREFIX, "storage");
+    storage.modified("config");
+}
+
+struct Gauge {
+    tracker: Arc<Tracker>,
+    label: String,
+    level: u32
+}
+
+#[track_with(tracker, capture_fields = "label, level")]
+impl Gauge {
+    fn new(tracker: Arc<Tracker>) -> Self {
+        Self {
+            tracker,
+            label: "idle".to_string(),
+            level: 0
+        }
+    }
+
+    fn raise(&mut self, by: u32) -> u32 {
+        self.level += by;
+        self.label = "active".to_string();
+        self.level
+    }
+
+    fn reset(&mut self) {
+        self.level = 0;
+    }
+}
+
+#[test]
+fn test_capture_fields() {
+    let tracker = Tracker::new();
+    let mut gauge = Gauge::new(tracker.clone());
+    gauge.raise(3);
+    gauge.raise(2);
+    gauge.reset();
+
+    tracker
+        .assert_that("Gauge::raise")
+        .was_called_times(2)
+        .with((3u32, "idle".to_string(), 0u32))
+        .with((2u32, "active".to_string(), 3u32))
+        .and_returned(5u32);
+    tracker
+        .assert_that("Gauge::reset")
+        .was_called_once()
+        .with(("active".to_string(), 5u32));
+}
+
+struct Ledger {
+    tracker: Arc<Tracker>,
+    entries: Vec<u64>
+}
+
+#[track_with(tracker, keys = true)]
+impl Ledger {
+    fn deposit(&mut self, amount: u64) -> usize {
+        self.entries.push(amount);
+        self.entries.len()
+    }
+
+    #[track(no_return)]
+    fn entries(&self) -> impl Iterator<Item = &u64> {
+        self.entries.iter()
+    }
+
+    #[track(rename = "Accounts::audit")]
+    fn audit(&self) {}
+
+    fn note<T: Clone + Send + Sync + 'static>(&self, note: T) {}
+}
+
+#[test]
+fn test_default_keys_struct() {
+    let tracker = Tracker::new();
+    let mut ledger = Ledger {
+        tracker: tracker.clone(),
+        entries: Vec::new()
+    };
+    ledger.deposit(20);
+    ledger.deposit(5);
+    assert_eq!(ledger.entries().sum::<u64>(), 25);
+    ledger.audit();
+    ledger.note('!');
+
+    tracker
+        .assert_that(LedgerKeys::deposit)
+        .was_called_times(2)
+        .with(5)
+        .and_returned(2);
+    tracker.assert_that(LedgerKeys::entries).was_called_once();
+    tracker.assert_that(LedgerKeys::audit).was_called_once();
+    tracker
+        .assert_that(LedgerKeys::note)
+        .was_called_once()
+        .with('!');
+
+    let logged = tracker.histogram();
+    assert_eq!(logged.len(), 4);
+    for key in &[
+        LedgerKeys::deposit.key(),
+        LedgerKeys::entries.key(),
+        LedgerKeys::audit.key(),
+        LedgerKeys::note
+    ] {
+        assert!(logged.contains_key(*key), "{} wasn't logged", key);
+    }
+}
+
+struct Thermostat {
+    tracker: Arc<Tracker>,
+    target: f32
+}
+
+#[track_with(tracker, typed_assertions = true)]
+impl Thermostat {
+    fn set_target(&mut self, target: f32, zone: &str) -> f32 {
+        std::mem::replace(&mut self.target, target)
+    }
+
+    fn target(&self) -> f32 {
+        self.target
+    }
+
+    fn schedule<T: Clone + Send + Sync + 'static>(&self, at: T) {}
+}
+
+#[test]
+fn test_typed_assertions() {
+    let tracker = Tracker::new();
+    let mut thermostat = Thermostat {
+        tracker: tracker.clone(),
+        target: 18.0
+    };
+    thermostat.set_target(21.5, "living room");
+    thermostat.target();
+    thermostat.schedule(7u8);
+
+    let assertions = ThermostatAssertions::new(&tracker);
+    assertions
+        .set_target()
+        .was_called_once()
+        .with((21.5, "living room".into()))
+        .and_returned(18.0);
+    assertions.target().was_called_once().and_returned(21.5);
+    assertions.schedule().was_called_once().with(7u8);
+}
+
+mod inventory {
+    use racetrack::{track_with, Tracker};
+    use std::sync::Arc;
+
+    pub struct Stock {
+        pub tracker: Arc<Tracker>
+    }
+
+    #[track_with(tracker, keys = true, typed_assertions = true, vis = "pub")]
+    impl Stock {
+        pub fn restock(&self, count: u32) -> u32 {
+            count
+        }
+    }
+}
+
+pub use inventory::{StockAssertions, StockKeys};
+
+#[test]
+fn test_public_keys_and_assertions() {
+    let tracker = Tracker::new();
+    let stock = inventory::Stock {
+        tracker: tracker.clone()
+    };
+    stock.restock(12);
+
+    tracker
+        .assert_that(StockKeys::restock)
+        .was_called_once()
+        .with(12u32);
+    StockAssertions::new(&tracker)
+        .restock()
+        .was_called_once()
+        .and_returned(12);
+}
+
+mod http {
+    use racetrack::{track_with, Tracker};
+    use std::sync::Arc;
+
+    pub struct Client {
+        pub tracker: Arc<Tracker>
+    }
+
+    #[track_with(tracker, namespace_module = true, keys = "ClientKeys")]
+    impl Client {
+        pub fn get(&self, path: &str) -> u16 {
+            200
+        }
+    }
+
+    #[track_with(crate::TRACKER, namespace_module = true)]
+    pub fn parse(line: &str) -> usize {
+        line.len()
+    }
+}
+
+mod ftp {
+    use racetrack::{track_with, Tracker};
+    use std::sync::Arc;
+
+    pub struct Client {
+        pub tracker: Arc<Tracker>
+    }
+
+    #[track_with(tracker, namespace_module = true)]
+    impl Client {
+        pub fn get(&self, path: &str) -> u16 {
+            226
+        }
+
+        #[track(rename = "Transfers::abort")]
+        pub fn abort(&self) {}
+    }
+
+    #[track_with(tracker, namespace_module = true, namespace = "Ftp")]
+    impl Client {
+        pub fn list(&self) {}
+    }
+}
+
+#[track_with(tracker, namespace_module = true)]
+mod smtp {
+    use racetrack::Tracker;
+    use std::sync::Arc;
+
+    pub fn send(#[track_skip] tracker: &Arc<Tracker>, to: &str) {}
+}
+
+#[test]
+fn test_namespace_module() {
+    let tracker = Tracker::new();
+    http::Client {
+        tracker: tracker.clone()
+    }
+    .get("/index");
+    let ftp = ftp::Client {
+        tracker: tracker.clone()
+    };
+    ftp.get("/pub");
+    ftp.get("/incoming");
+    ftp.abort();
+    ftp.list();
+    smtp::send(&tracker, "root");
+
+    tracker
+        .assert_that("track::http::Client::get")
+        .was_called_once()
+        .with("/index".to_string())
+        .and_returned(200u16);
+    assert_eq!(http::ClientKeys::get.key(), "track::http::Client::get");
+    tracker
+        .assert_that("track::ftp::Client::get")
+        .was_called_times(2)
+        .and_returned(226u16);
+    tracker.assert_that("Client::get").wasnt_called();
+    tracker.assert_that("Transfers::abort").was_called_once();
+    tracker.assert_that("Ftp::list").was_called_once();
+    tracker
+        .assert_that("track::smtp::send")
+        .was_called_once()
+        .with("root".to_string());
+
+    http::parse("GET /");
+    TRACKER
+        .assert_that("track::http::parse")
+        .was_called_once()
+        .with("GET /".to_string());
+}
+
+struct Shard {
+    tracker: Arc<Tracker>,
+    id: u32
+}
+
+#[track_with(tracker, namespace_field = "id")]
+impl Shard {
+    fn spawn(tracker: Arc<Tracker>, id: u32) -> Self {
+        Self { tracker, id }
+    }
+
+    fn run(&self, job: &str) -> bool {
+        !job.is_empty()
+    }
+
+    #[track(rename = "Pool::stop")]
+    fn stop(self) -> u32 {
+        self.id
+    }
+
+    fn retire(self) -> u32 {
+        self.id
+    }
+}
+
+#[test]
+fn test_namespace_field() {
+    let tracker = Tracker::new();
+    let first = Shard::spawn(tracker.clone(), 3);
+    let second = Shard::spawn(tracker.clone(), 7);
+    first.run("build");
+    first.run("");
+    second.run("test");
+    first.stop();
+    second.retire();
+
+    tracker
+        .assert_that("Shard#3::run")
+        .was_called_times(2)
+        .with("build".to_string())
+        .and_returned(false);
+    tracker
+        .assert_that("Shard#7::run")
+        .was_called_once()
+        .with("test".to_string())
+        .and_returned(true);
+    tracker.assert_that("Shard::run").wasnt_called();
+    tracker
+        .assert_that("Pool::stop")
+        .was_called_once()
+        .and_returned(3u32);
+    tracker
+        .assert_that("Shard#7::retire")
+        .was_called_once()
+        .and_returned(7u32);
+}
+
+struct Catalog {
+    tracker: Arc<Tracker>,
+    region: &'static str
+}
+
+#[track_with(tracker, key_format = "{namespace}.{name}", keys = "CatalogKeys")]
+impl Catalog {
+    fn reserve(&self, sku: &str, count: u32) -> bool {
+        count > 0 && !sku.is_empty()
+    }
+
+    #[track(rename = "restock")]
+    fn refill(&self, sku: &str) {
+        let _ = sku;
+    }
+}
+
+struct Warehouse {
+    tracker: Arc<Tracker>,
+    region: &'static str
+}
+
+#[track_with(tracker, key_format = "{namespace}.{name}", namespace_field = "region")]
+impl Warehouse {
+    fn ship(&self, count: u32) -> u32 {
+        count
+    }
+}
+
+#[track_with(TRACKER, namespace = "billing", key_format = "{namespace}.{name}")]
+fn charge(cents: u64) -> bool {
+    cents > 0
+}
+
+#[test]
+fn test_key_format() {
+    let tracker = Tracker::new();
+    let catalog = Catalog {
+        tracker: tracker.clone(),
+        region: "eu"
+    };
+    catalog.reserve("apple", 3);
+    catalog.refill("pear");
+    let warehouse = Warehouse {
+        tracker: tracker.clone(),
+        region: catalog.region
+    };
+    warehouse.ship(4);
+
+    assert_eq!(CatalogKeys::reserve.key(), "Catalog.reserve");
+    tracker
+        .assert_that(CatalogKeys::reserve)
+        .was_called_once()
+        .with(("apple".to_string(), 3u32))
+        .and_returned(true);
+    tracker
+        .assert_that("Catalog.restock")
+        .was_called_once()
+        .with("pear".to_string());
+    tracker
+        .assert_that("Warehouse#eu.ship")
+        .was_called_once()
+        .and_returned(4u32);
+    tracker.assert_that("Catalog::reserve").wasnt_called();
+
+    charge(250);
+    TRACKER
+        .assert_that("billing.charge")
+        .was_called_once()
+        .with(250u64)
+        .and_returned(true);
+}
+
+struct Endpoint {
+    tracker: Arc<Tracker>,
+    port: u16
+}
+
+#[track_with(tracker)]
+impl Endpoint {
+    fn open(tracker: Arc<Tracker>, port: u16) -> Self {
+        Self { tracker, port }
+    }
+
+    fn probe(tracker: &Tracker, port: u16) -> bool {
+        let _ = tracker;
+        port != 0
+    }
+
+    fn default_port() -> u16 {
+        80
+    }
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+#[test]
+fn test_tracker_param() {
+    let tracker = Tracker::new();
+    Endpoint::probe(&tracker, 0);
+    let endpoint = Endpoint::open(tracker.clone(), Endpoint::default_port());
+    endpoint.port();
+
+    tracker
+        .assert_that("Endpoint::open")
+        .was_called_once()
+        .with(80u16);
+    tracker
+        .assert_that("Endpoint::probe")
+        .was_called_once()
+        .with(0u16)
+        .and_returned(false);
+    tracker
+        .assert_that("Endpoint::port")
+        .was_called_once()
+        .and_returned(80u16);
+    tracker.assert_that("Endpoint::default_port").wasnt_called();
+}
+
+#[async_trait::async_trait]
+trait Bank {
+    async fn balance(&self, account: &str) -> Result<u64, String>;
+    async fn deposit(&mut self, amount: u64) -> u64;
+}
+
+struct OuterBank {
+    tracker: Arc<Tracker>,
+    total: u64
+}
+
+// `track_with` above `async_trait`, so racetrack sees the async methods
+#[track_with(tracker)]
+#[async_trait::async_trait]
+impl Bank for OuterBank {
+    async fn balance(&self, account: &str) -> Result<u64, String> {
+        if account.is_empty() {
+            return Err("no account".to_string());
+        }
+        Ok(self.total)
+    }
+
+    async fn deposit(&mut self, amount: u64) -> u64 {
+        self.total += amount;
+        self.total
+    }
+}
+
+struct InnerBank {
+    tracker: Arc<Tracker>,
+    total: u64
+}
+
+// `async_trait` above `track_with`, so racetrack sees the boxed futures
+#[async_trait::async_trait]
+#[track_with(tracker)]
+impl Bank for InnerBank {
+    async fn balance(&self, account: &str) -> Result<u64, String> {
+        let total = Some(self.total).filter(|_| !account.is_empty());
+        total.ok_or_else(|| "no account".to_string())
+    }
+
+    async fn deposit(&mut self, amount: u64) -> u64 {
+        self.total += amount;
+        self.total
+    }
+}
+
+#[tokio::test]
+async fn test_async_trait() {
+    let tracker = Tracker::new();
+    let mut outer = OuterBank {
+        tracker: tracker.clone(),
+        total: 0
+    };
+    let mut inner = InnerBank {
+        tracker: tracker.clone(),
+        total: 10
+    };
+    outer.deposit(5).await;
+    outer.balance("").await.unwrap_err();
+    inner.deposit(5).await;
+    inner.balance("savings").await.unwrap();
+
+    tracker
+        .assert_that("OuterBank::deposit")
+        .was_called_once()
+        .with(5u64)
+        .and_returned(5u64);
+    tracker
+        .assert_that("OuterBank::balance")
+        .was_called_once()
+        .with("".to_string())
+        .and_returned(Err::<u64, String>("no account".to_string()));
+    tracker
+        .assert_that("InnerBank::deposit")
+        .was_called_once()
+        .with(5u64)
+        .and_returned(15u64);
+    tracker
+        .assert_that("InnerBank::balance")
+        .was_called_once()
+        .with("savings".to_string())
+        .and_returned(Ok::<u64, String>(15));
+}
+
+struct Record {
+    name: String
+}
+
+struct Invoice {
+    total: u64
+}
+
+struct Archive {
+    tracker: Arc<Tracker>
+}
+
+// Neither `Record` nor `Invoice` can be cloned, so they're left out of the capture
+#[track_with(tracker, capture_generics = false, key_generics = true)]
+impl Archive {
+    fn store<T>(&self, id: u32, value: T) -> u32 {
+        let _ = value;
+        id
+    }
+
+    fn empty<T: Default>(&self) -> T {
+        T::default()
+    }
+
+    fn count(&self) -> usize {
+        0
+    }
+}
+
+#[test]
+fn test_generic_methods() {
+    let tracker = Tracker::new();
+    let archive = Archive {
+        tracker: tracker.clone()
+    };
+    archive.store(
+        1,
+        Record {
+            name: "a".to_string()
+        }
+    );
+    archive.store(2, Invoice { total: 3 });
+    archive.store(3, Invoice { total: 4 });
+    let _: Vec<u8> = archive.empty();
+    archive.count();
+
+    let record_key = format!("Archive::store::<{}>", std::any::type_name::<Record>());
+    let invoice_key = format!("Archive::store::<{}>", std::any::type_name::<Invoice>());
+    tracker
+        .assert_that(record_key)
+        .was_called_once()
+        .with(1u32)
+        .and_returned(1u32);
+    tracker
+        .assert_that(invoice_key)
+        .was_called_times(2)
+        .with(2u32)
+        .with(3u32);
+    tracker.assert_that("Archive::store").wasnt_called();
+    tracker
+        .assert_that(format!(
+            "Archive::empty::<{}>",
+            std::any::type_name::<Vec<u8>>()
+        ))
+        .was_called_once()
+        .with_call_matching(|call| call.returned.is_none());
+    tracker.assert_that("Archive::count").was_called_once();
+}
+
+/// # Safety
+///
+/// `value` must point to a valid `u32`.
+#[track_with(TRACKER)]
+unsafe fn read_raw(
+    #[track(capture_with = "value as usize")] value: *const u32,
+    offset: u32
+) -> u32 {
+    *value + offset
+}
+
+/// # Safety
+///
+/// `values` must point to `len` valid `u8`s.
+#[track_with(TRACKER)]
+unsafe fn checked_sum(#[track_skip] values: *const u8, len: usize) -> Result<u32, String> {
+    if values.is_null() {
+        return Err("null".to_string());
+    }
+    let values = std::slice::from_raw_parts(values, len);
+    Ok(values.iter().map(|&value| u32::from(value)).sum())
+}
+
+#[track_with(TRACKER)]
+pub extern "C" fn ffi_callback(code: i32) -> i32 {
+    code + 1
+}
+
+struct RawBuffer {
+    tracker: Arc<Tracker>,
+    data: Vec<u8>
+}
+
+#[track_with(tracker)]
+impl RawBuffer {
+    /// # Safety
+    ///
+    /// `index` must be in bounds.
+    unsafe fn get_unchecked(&self, index: usize) -> u8 {
+        *self.data.get_unchecked(index)
+    }
+
+    extern "C" fn len(&self) -> usize {
+        self.data.len()
+    }
+}
+
+#[test]
+fn test_unsafe_and_extern() {
+    let value = 4u32;
+    assert_eq!(unsafe { read_raw(&value, 1) }, 5);
+    let bytes = [1u8, 2, 3];
+    assert_eq!(unsafe { checked_sum(bytes.as_ptr(), bytes.len()) }, Ok(6));
+    assert!(unsafe { checked_sum(std::ptr::null(), 0) }.is_err());
+    let callback: extern "C" fn(i32) -> i32 = ffi_callback;
+    assert_eq!(callback(41), 42);
+
+    TRACKER
+        .assert_that("read_raw")
+        .was_called_once()
+        .with((&value as *const u32 as usize, 1u32))
+        .and_returned(5u32);
+    TRACKER
+        .assert_that("checked_sum")
+        .was_called_times(2)
+        .with_args_seq(vec![3usize, 0])
+        .and_returned(Ok::<u32, String>(6));
+    TRACKER
+        .assert_that("ffi_callback")
+        .was_called_once()
+        .with(41i32)
+        .and_returned(42i32);
+
+    let tracker = Tracker::new();
+    let buffer = RawBuffer {
+        tracker: tracker.clone(),
+        data: vec![7, 8]
+    };
+    assert_eq!(unsafe { buffer.get_unchecked(1) }, 8);
+    let len: extern "C" fn(&RawBuffer) -> usize = RawBuffer::len;
+    assert_eq!(len(&buffer), 2);
+    tracker
+        .assert_that("RawBuffer::get_unchecked")
+        .was_called_once()
+        .with(1usize)
+        .and_returned(8u8);
+    tracker
+        .assert_that("RawBuffer::len")
+        .was_called_once()
+        .and_returned(2usize);
+}
+
+#[racetrack::inject_tracker]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+struct Heater {
+    target: u32,
+    history: Vec<u32>
+}
+
+#[track_with(derived = true)]
+impl Heater {
+    fn new(target: u32) -> Self {
+        Heater {
+            target,
+            history: Vec::new()
+        }
+    }
+
+    fn set(&mut self, target: u32) -> u32 {
+        self.history.push(self.target);
+        self.target = target;
+        self.history.len() as u32
+    }
+
+    fn into_history(self) -> Vec<u32> {
+        self.history
+    }
+}
+
+#[test]
+fn test_inject_tracker() {
+    let tracker = Tracker::new();
+    let mut heater = Heater::new(18);
+    heater.set(19);
+    assert!(heater.tracker().is_none());
+
+    heater.set_tracker(tracker.clone());
+    assert!(heater.tracker().is_some());
+    heater.set(21);
+    assert_eq!(heater.clone().into_history(), vec![18, 19]);
+
+    tracker
+        .assert_that("Heater::set")
+        .was_called_once()
+        .with(21u32)
+        .and_returned(2u32);
+    tracker
+        .assert_that("Heater::into_history")
+        .was_called_once()
+        .and_returned(vec![18u32, 19]);
+    tracker.assert_that("Heater::new").wasnt_called();
+
+    // The injected tracker doesn't take part in comparisons
+    let untracked = Heater {
+        target: 21,
+        history: vec![18, 19],
+        ..Default::default()
+    };
+    assert_eq!(heater, untracked);
+}
+
+struct Derived {
+    derived: Arc<Tracker>
+}
+
+// A tracker that happens to be named `derived` is given by name, since a bare `derived` is ambiguous
+#[track_with(tracker = derived)]
+impl Derived {
+    fn run(&self) -> u32 {
+        1
+    }
+}
+
+#[test]
+fn test_tracker_named_derived() {
+    let tracker = Tracker::new();
+    let derived = Derived {
+        derived: tracker.clone()
+    };
+    derived.run();
+
+    tracker
+        .assert_that("Derived::run")
+        .was_called_once()
+        .and_returned(1u32);
+}
+
+struct NamedTracker(Arc<Tracker>);
+
+#[track_with(namespace = "Named", tracker = self.0)]
+impl NamedTracker {
+    fn greet(&self, name: &str) -> String {
+        format!("Hello, {}", name)
+    }
+}
+
+#[track_with(tracker = TRACKER, namespace = "named")]
+fn named_tracker_fn(value: u32) -> u32 {
+    value + 1
+}
+
+#[test]
+fn test_named_tracker() {
+    let tracker = Tracker::new();
+    let named = NamedTracker(tracker.clone());
+    named.greet("racetrack");
+    tracker
+        .assert_that("Named::greet")
+        .was_called_once()
+        .with("racetrack".to_string())
+        .and_returned("Hello, racetrack".to_string());
+
+    named_tracker_fn(41);
+    TRACKER
+        .assert_that("named::named_tracker_fn")
+        .was_called_once()
+        .with(41u32)
+        .and_returned(42u32);
+}
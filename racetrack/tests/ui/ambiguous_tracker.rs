@@ -0,0 +1,33 @@
+use racetrack::{inject_tracker, track_with, Tracker};
+use std::sync::Arc;
+
+#[inject_tracker]
+struct Injected {
+    value: u32
+}
+
+#[track_with(derived)]
+impl Injected {
+    fn run(&self) {}
+}
+
+#[track_with(spy)]
+trait Service {
+    fn call(&self);
+}
+
+struct Named {
+    derived: Arc<Tracker>
+}
+
+#[track_with(derived = true, tracker = derived)]
+impl Named {
+    fn run(&self) {}
+}
+
+#[track_with(derived = true, spy = true)]
+impl Injected {
+    fn stop(&self) {}
+}
+
+fn main() {}
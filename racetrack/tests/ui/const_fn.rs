@@ -0,0 +1,36 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Arc<Tracker> = Tracker::new();
+}
+
+#[track_with(TRACKER)]
+const fn doubled(value: u32) -> u32 {
+    value * 2
+}
+
+struct Tracked {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker)]
+impl Tracked {
+    const fn limit(&self) -> u32 {
+        10
+    }
+
+    fn tracked(&self) -> u32 {
+        self.limit()
+    }
+}
+
+// Excluded const fns are left alone
+#[track_with(tracker, exclude = "other_limit")]
+impl Tracked {
+    const fn other_limit(&self) -> u32 {
+        20
+    }
+}
+
+fn main() {}
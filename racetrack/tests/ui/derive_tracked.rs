@@ -0,0 +1,15 @@
+use racetrack::{Tracked, Tracker};
+use std::sync::Arc;
+
+#[derive(Tracked)]
+struct Untracked {
+    name: String
+}
+
+#[derive(Tracked)]
+struct Ambiguous {
+    first: Arc<Tracker>,
+    second: Arc<Tracker>
+}
+
+fn main() {}
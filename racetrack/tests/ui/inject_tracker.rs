@@ -0,0 +1,9 @@
+use racetrack::{inject_tracker, track_with};
+
+#[inject_tracker]
+struct Pair(u32, u32);
+
+#[track_with(derived = true)]
+fn untracked() {}
+
+fn main() {}
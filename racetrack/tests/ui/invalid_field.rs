@@ -0,0 +1,17 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Services {
+    tracker: Arc<Tracker>
+}
+
+struct Tracked {
+    services: Services
+}
+
+#[track_with(services.+tracker)]
+impl Tracked {
+    fn tracked(&self) {}
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Tracked(Arc<Tracker>);
+
+#[track_with(0)]
+impl Tracked {
+    #[track(rename = 1)]
+    fn renamed(&self) {}
+
+    #[track(unknown)]
+    fn unknown(&self) {}
+
+    #[track_with(rename = "other", capture = "copy")]
+    fn captured(&self) {}
+
+    fn projected(&self, #[track(capture_with = "+")] _value: u32) {}
+
+    fn skipped(&self, #[track(skip)] _value: u32) {}
+}
+
+fn main() {}
@@ -0,0 +1,51 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Tracked(Arc<Tracker>);
+
+#[track_with(0, include_receiver = "yes")]
+impl Tracked {
+    fn receiver(&self) {}
+}
+
+#[track_with(0, exclude = 1)]
+impl Tracked {
+    fn excluded(&self) {}
+}
+
+#[track_with(0, namespace = true)]
+impl Tracked {
+    fn namespaced(&self) {}
+}
+
+#[track_with(0, capture = "copy")]
+impl Tracked {
+    fn captured(&self) {}
+}
+
+#[track_with(0, keys = 1)]
+impl Tracked {
+    fn keyed(&self) {}
+}
+
+#[track_with(0, keys = true, vis = "public")]
+impl Tracked {
+    fn visible(&self) {}
+}
+
+#[track_with(0, unknown = true)]
+impl Tracked {
+    fn unknown(&self) {}
+}
+
+#[track_with(0, optional)]
+impl Tracked {
+    fn optional(&self) {}
+}
+
+#[track_with(0, only(receiver))]
+impl Tracked {
+    fn listed(&self) {}
+}
+
+fn main() {}
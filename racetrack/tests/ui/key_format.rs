@@ -0,0 +1,18 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Tracked {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, key_format = "{namespace}/{method}")]
+impl Tracked {
+    fn tracked(&self) {}
+}
+
+#[track_with(tracker, key_format = "{namespace}.")]
+impl Tracked {
+    fn other(&self) {}
+}
+
+fn main() {}
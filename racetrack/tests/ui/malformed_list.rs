@@ -0,0 +1,13 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Tracked(Arc<Tracker>);
+
+#[track_with(0, exclude(untracked, "tracked", some::path))]
+impl Tracked {
+    fn tracked(&self) {}
+
+    fn untracked(&self) {}
+}
+
+fn main() {}
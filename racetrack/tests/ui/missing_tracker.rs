@@ -0,0 +1,12 @@
+use racetrack::track_with;
+
+#[track_with()]
+fn untracked() {}
+
+#[track_with("tracker")]
+fn literal() {}
+
+#[track_with(0, tracker = self.0)]
+fn twice() {}
+
+fn main() {}
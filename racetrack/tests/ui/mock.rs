@@ -0,0 +1,17 @@
+use racetrack::mock;
+
+#[mock]
+struct Clock;
+
+#[mock]
+trait Timer {
+    type Tick;
+
+    fn start() -> Self;
+
+    fn ticks(&self) -> impl Iterator<Item = u64>;
+
+    async fn wait(&self);
+}
+
+fn main() {}
@@ -0,0 +1,53 @@
+#![deny(unused_must_use)]
+
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Tracked(Arc<Tracker>);
+
+#[track_with(0)]
+impl Tracked {
+    #[inline]
+    #[must_use]
+    fn before(&self) -> u32 {
+        1
+    }
+
+    #[must_use = "the value should be used"]
+    #[inline(always)]
+    fn after(&self) -> u32 {
+        2
+    }
+
+    #[inline]
+    #[track(rename = "renamed")]
+    #[must_use]
+    fn between(&self) -> u32 {
+        3
+    }
+}
+
+#[must_use]
+#[track_with(crate::TRACKER)]
+fn above() -> u32 {
+    3
+}
+
+#[track_with(crate::TRACKER)]
+#[must_use]
+fn below() -> u32 {
+    4
+}
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Arc<Tracker> = Tracker::new();
+}
+
+fn main() {
+    let tracked = Tracked(Tracker::new());
+    tracked.before();
+    tracked.after();
+    tracked.between();
+    above();
+    below();
+}
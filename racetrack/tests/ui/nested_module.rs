@@ -0,0 +1,17 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Arc<Tracker> = Tracker::new();
+}
+
+#[track_with(crate::TRACKER)]
+mod storage {
+    pub fn save(key: String) {}
+
+    mod cache {
+        pub fn get(key: String) {}
+    }
+}
+
+fn main() {}
@@ -0,0 +1,34 @@
+use racetrack::Tracker;
+use std::sync::Arc;
+
+mod outer {
+    use racetrack::Tracker;
+    use std::sync::Arc;
+
+    pub struct Visible(pub Arc<Tracker>);
+
+    pub mod inner {
+        use racetrack::track_with;
+
+        #[track_with(0)]
+        impl super::Visible {
+            pub(super) fn super_visible(&self) {}
+
+            pub(in crate::outer) fn path_visible(&self) {}
+        }
+
+        #[track_with(crate::TRACKER)]
+        pub(super) fn super_fn() {}
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Arc<Tracker> = Tracker::new();
+}
+
+fn main() {
+    let visible = outer::Visible(Tracker::new());
+    visible.super_visible();
+    visible.path_visible();
+    outer::inner::super_fn();
+}
@@ -0,0 +1,13 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+#[track_with(tracker)]
+trait Greeter {
+    fn tracker(&self) -> &Arc<Tracker>;
+
+    fn greet(&self) -> String {
+        "hello".to_string()
+    }
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+use racetrack::track_with;
+
+#[track_with(spy = true)]
+trait Store<K> {
+    fn get(&self, key: K) -> Option<String>;
+}
+
+#[track_with(spy = true)]
+trait Shape {
+    fn scaled(&self, factor: f64) -> Self;
+
+    fn merge(&self, other: &Self) -> f64;
+
+    async fn area(&self) -> f64;
+
+    fn boxed(self: Box<Self>) -> f64;
+}
+
+fn main() {}
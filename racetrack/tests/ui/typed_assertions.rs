@@ -0,0 +1,19 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Thermostat {
+    tracker: Arc<Tracker>
+}
+
+#[track_with(tracker, typed_assertions = true)]
+impl Thermostat {
+    fn set_target(&self, target: f32, zone: &str) {}
+}
+
+fn main() {
+    let tracker = Tracker::new();
+    ThermostatAssertions::new(&tracker)
+        .set_target()
+        .was_called_once()
+        .with((21u32, "kitchen".to_string()));
+}
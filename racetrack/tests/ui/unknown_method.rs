@@ -0,0 +1,13 @@
+use racetrack::{track_with, Tracker};
+use std::sync::Arc;
+
+struct Tracked(Arc<Tracker>);
+
+#[track_with(0, include(tracked, trackd))]
+impl Tracked {
+    fn tracked(&self) {}
+
+    fn untracked(&self) {}
+}
+
+fn main() {}